@@ -18,6 +18,20 @@ fn main() {
     let out_dir = PathBuf::from(env::var("OUT_DIR").expect("Could not read out dir"));
 
     generate_excalidraw_assets(&manifest_dir, &out_dir);
+    generate_app_assets(
+        &manifest_dir,
+        &out_dir,
+        "mermaid-app",
+        "mermaid",
+        "HDIAG_MERMAID_VERSION",
+    );
+    generate_app_assets(
+        &manifest_dir,
+        &out_dir,
+        "graphviz-app",
+        "@viz-js/viz",
+        "HDIAG_VIZ_VERSION",
+    );
 }
 
 fn gen_zip_file<P, Q>(source_path: P, zip_path: Q)
@@ -69,9 +83,22 @@ where
     }
 }
 
-fn generate_excalidraw_assets(manifest_dir: &Path, out_dir: &Path) {
-    let excalidraw_app_dir = manifest_dir.join("excalidraw-app");
-    for f in ignore::Walk::new(&excalidraw_app_dir) {
+/// The version actually installed, not the one in package.json, which is just
+/// a range
+fn emit_package_version(package_dir: &Path, env_name: &str) {
+    let package_json =
+        fs::read(package_dir.join("package.json")).expect("Failed to read package.json");
+    let package_json: serde_json::Value =
+        serde_json::from_slice(&package_json).expect("package.json is not JSON");
+    let version = package_json["version"]
+        .as_str()
+        .expect("package.json has no version");
+    println!("cargo:rustc-env={env_name}={version}");
+}
+
+/// Reruns the build when the sources of the js app change
+fn rerun_if_app_changed(manifest_dir: &Path, app_dir: &Path) {
+    for f in ignore::Walk::new(app_dir) {
         let f = f.expect("Failed to read file directory");
         let relative_path = f
             .path()
@@ -87,19 +114,34 @@ fn generate_excalidraw_assets(manifest_dir: &Path, out_dir: &Path) {
             Some(_) | None => println!("cargo:rerun-if-changed={}", f.path().display()),
         }
     }
+}
 
-    let excalidraw_build_dir = excalidraw_app_dir.join("dist");
-
+fn pnpm(app_dir: &Path, args: &[&str]) {
     let mut pnpm = Command::new("pnpm")
-        .arg("install")
+        .args(args)
         .stdout(Stdio::null())
-        .current_dir(&excalidraw_app_dir)
+        .current_dir(app_dir)
         .spawn()
-        .expect("Could not spawn pnpm");
-    pnpm.wait().expect("Failed waiting for pnpm");
+        .unwrap_or_else(|e| panic!("Could not spawn `pnpm {}`: {e}", args.join(" ")));
+    pnpm.wait()
+        .unwrap_or_else(|e| panic!("Failed waiting for `pnpm {}`: {e}", args.join(" ")));
+}
+
+/// Builds the js app, and bundles its output in `{name}.zip` in the out dir
+fn build_js_app(app_dir: &Path, out_dir: &Path, name: &str) {
+    pnpm(app_dir, &["run", "build"]);
+    gen_zip_file(app_dir.join("dist"), out_dir.join(format!("{name}.zip")));
+}
+
+fn generate_excalidraw_assets(manifest_dir: &Path, out_dir: &Path) {
+    let excalidraw_app_dir = manifest_dir.join("excalidraw-app");
+    rerun_if_app_changed(manifest_dir, &excalidraw_app_dir);
+    pnpm(&excalidraw_app_dir, &["install"]);
 
-    let excalidraw_assets_dir =
-        excalidraw_app_dir.join("node_modules/@excalidraw/excalidraw/dist/excalidraw-assets");
+    let excalidraw_package_dir = excalidraw_app_dir.join("node_modules/@excalidraw/excalidraw");
+    emit_package_version(&excalidraw_package_dir, "HDIAG_EXCALIDRAW_VERSION");
+
+    let excalidraw_assets_dir = excalidraw_package_dir.join("dist/excalidraw-assets");
     let assets_dir = excalidraw_app_dir.join("public/excalidraw-assets");
     fs::create_dir_all(&assets_dir).expect("Failed to create assets output dir");
     let font_output_dir = out_dir.join("fonts_temp");
@@ -138,16 +180,21 @@ fn generate_excalidraw_assets(manifest_dir: &Path, out_dir: &Path) {
     gen_zip_file(&font_output_dir, font_zip_file);
     rmdir_force(&font_output_dir);
 
-    let mut js_build = Command::new("pnpm")
-        .args(["run", "build"])
-        .stdout(Stdio::null())
-        .current_dir(&excalidraw_app_dir)
-        .spawn()
-        .expect("Could not spawn `pnpm run build`");
-    js_build
-        .wait()
-        .expect("Failed waiting for `pnpm run build`");
+    build_js_app(&excalidraw_app_dir, out_dir, "excalidraw-app");
+}
 
-    let bundle_path = out_dir.join("excalidraw-app.zip");
-    gen_zip_file(excalidraw_build_dir, bundle_path);
+/// Builds a js app that needs nothing but its npm packages, `package` being
+/// the one whose version is emitted as `env_name`
+fn generate_app_assets(
+    manifest_dir: &Path,
+    out_dir: &Path,
+    app_name: &str,
+    package: &str,
+    env_name: &str,
+) {
+    let app_dir = manifest_dir.join(app_name);
+    rerun_if_app_changed(manifest_dir, &app_dir);
+    pnpm(&app_dir, &["install"]);
+    emit_package_version(&app_dir.join("node_modules").join(package), env_name);
+    build_js_app(&app_dir, out_dir, app_name);
 }
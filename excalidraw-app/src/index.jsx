@@ -1,26 +1,144 @@
-import { exportToSvg, loadFromBlob } from "@excalidraw/excalidraw";
+import {
+  convertToExcalidrawElements,
+  exportToSvg,
+  loadFromBlob,
+  loadLibraryFromBlob,
+} from "@excalidraw/excalidraw";
+import { parseMermaidToExcalidraw } from "@excalidraw/mermaid-to-excalidraw";
 
-window.onload = async function main() {
-  const input = await (await fetch("/input.excalidraw")).blob();
-  const opts = await (await fetch("/export_opts")).json();
-  const scene = await loadFromBlob(input, null, null);
+// mulberry32, so that anything excalidraw randomizes is the same every run
+function seededRandom(seed) {
+  return function random() {
+    seed = (seed + 0x6d2b79f5) | 0;
+    let t = Math.imul(seed ^ (seed >>> 15), 1 | seed);
+    t = (t + Math.imul(t ^ (t >>> 7), 61 | t)) ^ t;
+    return ((t ^ (t >>> 14)) >>> 0) / 4294967296;
+  };
+}
 
-  const appState = scene.appState;
+function applyExportOpts(appState, opts) {
   appState.exportBackground = opts.exportBackground;
   appState.exportEmbedScene = opts.exportEmbedScene;
   appState.exportWithDarkMode = opts.exportWithDarkMode;
   appState.exportScale = opts.exportScale;
+  return appState;
+}
+
+function readAsDataURL(blob) {
+  return new Promise((resolve, reject) => {
+    const reader = new FileReader();
+    reader.onload = () => resolve(reader.result);
+    reader.onerror = () => reject(reader.error);
+    reader.readAsDataURL(blob);
+  });
+}
+
+// Adds the images hdiag found in the asset directory, for the files the
+// scene is missing
+async function loadAssets(files, fileIds) {
+  for (const id of fileIds) {
+    const response = await fetch(`/assets/${encodeURIComponent(id)}`);
+    if (!response.ok) {
+      continue;
+    }
+    const blob = await response.blob();
+    files[id] = {
+      id,
+      mimeType: blob.type,
+      dataURL: await readAsDataURL(blob),
+      created: 0,
+    };
+  }
+  return files;
+}
+
+async function exportScene(opts) {
+  const input = await (await fetch("/input.excalidraw")).blob();
+  const scene = await loadFromBlob(input, null, null);
+  const files = await loadAssets(scene.files ?? {}, opts.assets ?? []);
+
+  let elements = scene.elements;
+  if (opts.deterministic) {
+    // Restoring the scene can bump versions, which stamps the current time
+    elements = elements.map((e) => ({ ...e, updated: 1 }));
+  }
+
   const svg = await exportToSvg({
-    elements: scene.elements,
-    appState: appState,
-    files: scene.files,
+    elements: elements,
+    appState: applyExportOpts(scene.appState, opts),
+    files,
   });
 
+  return new XMLSerializer().serializeToString(svg);
+}
+
+async function exportLibrary(opts) {
+  const input = await (await fetch("/input.excalidrawlib")).blob();
+  const items = await loadLibraryFromBlob(input, "published");
+
   const serializer = new XMLSerializer();
-  const svgMarkup = serializer.serializeToString(svg);
+  const svgs = [];
+  for (const item of items) {
+    const svg = await exportToSvg({
+      elements: item.elements,
+      // Library items have no scene of their own to embed
+      appState: applyExportOpts({}, { ...opts, exportEmbedScene: false }),
+      files: null,
+    });
+    svgs.push({
+      name: item.name ?? null,
+      svg: serializer.serializeToString(svg),
+    });
+  }
+
+  return JSON.stringify(svgs);
+}
+
+// Turns a mermaid definition into an editable scene, flowcharts become
+// shapes and arrows while other diagram types become a single image
+async function convertMermaid() {
+  const definition = await (await fetch("/input.mmd")).text();
+  let parsed;
+  try {
+    parsed = await parseMermaidToExcalidraw(definition);
+  } catch (e) {
+    // Report the syntax error, instead of leaving hdiag waiting for a scene
+    // forever
+    return JSON.stringify({ error: String(e.message ?? e) });
+  }
+  const { elements, files } = parsed;
+
+  return JSON.stringify({
+    type: "excalidraw",
+    version: 2,
+    source: "hdiag",
+    elements: convertToExcalidrawElements(elements),
+    appState: { viewBackgroundColor: "#ffffff" },
+    files: files ?? {},
+  });
+}
+
+async function run(opts) {
+  switch (opts.mode) {
+    case "library":
+      return exportLibrary(opts);
+    case "mermaid":
+      return convertMermaid();
+    default:
+      return exportScene(opts);
+  }
+}
+
+window.onload = async function main() {
+  const opts = await (await fetch("/export_opts")).json();
+  if (opts.deterministic) {
+    Math.random = seededRandom(0x68646961);
+  }
+
+  const output = await run(opts);
 
   fetch("/return", {
 	method: "POST",
-	body: svgMarkup
+	body: output
   })
 };
@@ -0,0 +1,24 @@
+import { instance } from "@viz-js/viz";
+
+window.onload = async function main() {
+  const opts = await (await fetch("/export_opts")).json();
+  const source = await (await fetch("/input.dot")).text();
+
+  const viz = await instance();
+  const result = viz.render(source, {
+    format: "svg",
+    graphAttributes: {
+      bgcolor: opts.exportBackground ? "white" : "transparent",
+    },
+  });
+
+  // Errors are returned instead of thrown, so that hdiag can report them
+  // instead of waiting for an svg forever
+  fetch("/return", {
+    method: "POST",
+    body: JSON.stringify({
+      svg: result.status === "success" ? result.output : null,
+      errors: result.errors.map((e) => e.message),
+    }),
+  });
+};
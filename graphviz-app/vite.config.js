@@ -0,0 +1,6 @@
+import { defineConfig } from "vite";
+
+// https://vitejs.dev/config/
+export default defineConfig(() => {
+  return {};
+});
@@ -0,0 +1,124 @@
+import mermaid from "mermaid";
+import opentype from "opentype.js";
+import fontUrl from "@fontsource/inter/files/inter-latin-400-normal.woff?url";
+
+const SVG_NS = "http://www.w3.org/2000/svg";
+// Labels are laid out with this font when it is embedded or turned into
+// paths, otherwise mermaid's default system fonts are used
+const FONT_FAMILY = "hdiag-mermaid";
+
+function toBase64(buffer) {
+  const bytes = new Uint8Array(buffer);
+  let binary = "";
+  for (const b of bytes) {
+    binary += String.fromCharCode(b);
+  }
+  return window.btoa(binary);
+}
+
+function embedFont(svg, fontBytes) {
+  const style = document.createElementNS(SVG_NS, "style");
+  style.textContent =
+    `@font-face { font-family: "${FONT_FAMILY}"; ` +
+    `src: url(data:font/woff;base64,${toBase64(fontBytes)}) format("woff"); }`;
+  svg.prepend(style);
+}
+
+// Replaces every text with the outlines of its glyphs, at the positions the
+// browser laid them out at
+function textToPaths(svg, font) {
+  for (const text of svg.querySelectorAll("text")) {
+    const style = window.getComputedStyle(text);
+    const size = parseFloat(style.fontSize);
+    const chars = text.textContent;
+    let d = "";
+    for (let i = 0; i < text.getNumberOfChars(); i++) {
+      const start = text.getStartPositionOfChar(i);
+      d += font.getPath(chars[i], start.x, start.y, size).toPathData(2);
+    }
+
+    const path = document.createElementNS(SVG_NS, "path");
+    path.setAttribute("d", d);
+    path.setAttribute("fill", style.fill);
+    const transform = text.getAttribute("transform");
+    if (transform) {
+      path.setAttribute("transform", transform);
+    }
+    text.replaceWith(path);
+  }
+}
+
+function applyExportOpts(svg, opts) {
+  // Mermaid sizes the svg to its container, give it its own size instead
+  const viewBox = svg.viewBox.baseVal;
+  svg.setAttribute("width", viewBox.width * opts.exportScale);
+  svg.setAttribute("height", viewBox.height * opts.exportScale);
+  svg.style.removeProperty("max-width");
+  if (opts.exportBackground) {
+    svg.style.backgroundColor = opts.theme === "dark" ? "#333" : "white";
+  }
+}
+
+window.onload = async function main() {
+  const opts = await (await fetch("/export_opts")).json();
+  const source = await (await fetch("/input.mmd")).text();
+
+  const ownFont = opts.fontFormat === "path" || opts.fontFormat === "embed";
+  let fontBytes = null;
+  if (ownFont) {
+    fontBytes = await (await fetch(fontUrl)).arrayBuffer();
+    const fontFace = new FontFace(FONT_FAMILY, fontBytes);
+    document.fonts.add(await fontFace.load());
+  }
+  const fontConfig = ownFont
+    ? { fontFamily: FONT_FAMILY, themeVariables: { fontFamily: FONT_FAMILY } }
+    : {};
+
+  mermaid.initialize({
+    startOnLoad: false,
+    securityLevel: "strict",
+    theme: opts.theme === "dark" ? "dark" : "default",
+    ...fontConfig,
+    // Html labels are foreign objects, which can't be turned into paths and
+    // most svg viewers don't show
+    htmlLabels: false,
+    flowchart: { htmlLabels: false },
+    deterministicIds: opts.deterministic,
+  });
+  let markup;
+  try {
+    ({ svg: markup } = await mermaid.render("hdiag", source));
+  } catch (e) {
+    // Report the syntax error, instead of leaving hdiag waiting for an svg
+    // forever
+    fetch("/return", {
+      method: "POST",
+      body: JSON.stringify({ svg: null, texts: [], error: String(e.message ?? e) }),
+    });
+    return;
+  }
+
+  // The svg has to be in the page for its text to be laid out
+  const root = document.getElementById("root");
+  root.innerHTML = markup;
+  const svg = root.querySelector("svg");
+
+  const texts = [...svg.querySelectorAll("text")]
+    .map((t) => t.textContent.trim())
+    .filter((t) => t.length > 0);
+
+  applyExportOpts(svg, opts);
+  if (opts.fontFormat === "path") {
+    textToPaths(svg, opentype.parse(fontBytes));
+  } else if (opts.fontFormat === "embed") {
+    embedFont(svg, fontBytes);
+  }
+
+  fetch("/return", {
+    method: "POST",
+    body: JSON.stringify({
+      svg: new XMLSerializer().serializeToString(svg),
+      texts,
+    }),
+  });
+};
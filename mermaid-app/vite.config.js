@@ -0,0 +1,9 @@
+import { defineConfig } from "vite";
+
+// https://vitejs.dev/config/
+export default defineConfig(() => {
+  return {
+    // The font is fetched back as bytes, so it must not be inlined
+    build: { assetsInlineLimit: 0 },
+  };
+});
@@ -0,0 +1,130 @@
+//! Finds the images that excalidraw scenes use but don't embed, in a
+//! directory of assets
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use base64::prelude::*;
+use color_eyre::{eyre::WrapErr, Result};
+use hdiag::scene::{ElementData, FileData, Scene};
+use serde_json::Map;
+use tracing::{debug, warn};
+
+/// Files of the images a scene is missing, by file id
+pub type Assets = BTreeMap<String, PathBuf>;
+
+/// Name of the file a scene's file points to, when its data is a link
+/// instead of a data URL
+fn linked_name<'a>(scene: &'a Scene, file_id: &str) -> Option<&'a str> {
+    let url = &scene.files.as_ref()?.get(file_id)?.data_url;
+    if url.starts_with("data:") {
+        return None;
+    }
+    let path = url.split(['?', '#']).next().unwrap_or_default();
+    path.rsplit(['/', '\\']).next().filter(|n| !n.is_empty())
+}
+
+fn has_data(scene: &Scene, file_id: &str) -> bool {
+    scene
+        .files
+        .as_ref()
+        .and_then(|files| files.get(file_id))
+        .is_some_and(|file| file.data_url.starts_with("data:"))
+}
+
+/// Looks for the file the link points to, or else a file named after the id
+fn find(dir: &Path, file_id: &str, linked: Option<&str>) -> Result<Option<PathBuf>> {
+    if let Some(path) = linked.map(|name| dir.join(name)).filter(|p| p.is_file()) {
+        return Ok(Some(path));
+    }
+    let entries = fs::read_dir(dir)
+        .wrap_err_with(|| format!("Failed to read directory {}", dir.display()))?;
+    for entry in entries {
+        let path = entry
+            .wrap_err_with(|| format!("Failed to read directory {}", dir.display()))?
+            .path();
+        if path.is_file() && path.file_stem().is_some_and(|s| s == file_id) {
+            return Ok(Some(path));
+        }
+    }
+    Ok(None)
+}
+
+/// Ids of the files whose data is missing from the scene, with the elements
+/// showing them
+fn missing_files(scene: &Scene) -> BTreeMap<&str, Vec<&str>> {
+    let mut missing = BTreeMap::<&str, Vec<&str>>::new();
+    for element in scene.live_elements() {
+        let ElementData::Image(image) = &element.data else {
+            continue;
+        };
+        match image.file_id.as_deref() {
+            Some(file_id) if !has_data(scene, file_id) => {
+                missing.entry(file_id).or_default().push(&element.id);
+            }
+            Some(_) => {}
+            None => warn!(element = element.id, "Image has no file, it is drawn empty"),
+        }
+    }
+    missing
+}
+
+/// Finds the files of the images whose data is missing from the scene, and
+/// warns about the ones that can't be found
+pub fn resolve(scene: &Scene, asset_dir: Option<&Path>) -> Result<Assets> {
+    let mut assets = Assets::new();
+    for (file_id, elements) in missing_files(scene) {
+        let linked = linked_name(scene, file_id);
+        let found = asset_dir
+            .map(|dir| find(dir, file_id, linked))
+            .transpose()?
+            .flatten();
+        if let Some(path) = found {
+            debug!(file_id, path = %path.display(), "Found image in asset directory");
+            assets.insert(file_id.to_owned(), path);
+            continue;
+        }
+        let hint = if asset_dir.is_some() {
+            "nor in the asset directory"
+        } else {
+            "pass `--asset-dir` to look for it"
+        };
+        warn!(
+            file_id,
+            linked,
+            elements = elements.join(", "),
+            "Image file is not in the scene, {hint}, it is drawn as a placeholder"
+        );
+    }
+    Ok(assets)
+}
+
+/// Mime type of an asset, from its extension
+pub fn mime_type(path: &Path) -> mime::Mime {
+    mime_guess::from_path(path).first_or_octet_stream()
+}
+
+/// Adds the assets to the files of the scene, as data URLs
+pub fn inject(scene: &mut Scene, assets: &Assets) -> Result<()> {
+    for (file_id, path) in assets {
+        let bytes =
+            fs::read(path).wrap_err_with(|| format!("Failed to read image {}", path.display()))?;
+        let mime_type = mime_type(path).essence_str().to_owned();
+        let data_url = format!("data:{mime_type};base64,{}", BASE64_STANDARD.encode(bytes));
+        scene.files.get_or_insert_with(BTreeMap::new).insert(
+            file_id.clone(),
+            FileData {
+                id: file_id.clone(),
+                mime_type,
+                data_url,
+                created: 0,
+                last_retrieved: None,
+                extra: Map::new(),
+            },
+        );
+    }
+    Ok(())
+}
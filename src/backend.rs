@@ -0,0 +1,129 @@
+use color_eyre::Result;
+use tokio::runtime::Runtime;
+use tracing::warn;
+
+use crate::{
+    cli, dsl, embedded, excalidraw, graphviz, library, mermaid, mxgraph, obsidian, plantuml,
+    share_link, Output,
+};
+
+/// Options of the export a backend honours, on top of the theme, background
+/// and scale which all of them do
+#[derive(Clone, Copy, Debug, Default)]
+#[allow(clippy::struct_excessive_bools)]
+pub struct Supports {
+    /// Fonts can be embedded or turned into paths with `-f`
+    pub fonts: bool,
+    /// The source can be embedded with `--source`
+    pub embed_source: bool,
+    /// It can be drawn without a browser with `--engine native`
+    pub native_engine: bool,
+    /// Missing images are looked up in `--asset-dir`
+    pub assets: bool,
+    /// Placeholders in the text are filled with `--vars` and `--set`
+    pub templates: bool,
+    /// Text is swapped for its translation with `--locale`
+    pub translations: bool,
+    /// It has pages, picked with `--page` and `--all-pages`
+    pub pages: bool,
+    /// It has items, exported on their own with `--library-items`
+    pub items: bool,
+}
+
+/// A diagram format, and how to render it
+#[allow(clippy::module_name_repetitions)]
+pub trait DiagramBackend: Sync {
+    /// Name of the format, as passed to `-t`
+    fn name(&self) -> &'static str;
+
+    /// Shown in the help of `-t`
+    fn description(&self) -> &'static str;
+
+    /// Ends of the names of files in the format, like `.excalidraw`
+    fn suffixes(&self) -> &'static [&'static str] {
+        &[]
+    }
+
+    /// Checks whether the contents are in the format, the error says why not
+    fn detect(&self, contents: &[u8]) -> Result<()>;
+
+    fn supports(&self) -> Supports {
+        Supports::default()
+    }
+
+    /// Whether its outputs can be cached. Outputs of programs hdiag doesn't
+    /// bundle change when they are upgraded, which the cache key can't see
+    fn cacheable(&self) -> bool {
+        true
+    }
+
+    fn render(&self, rt: &Runtime, opts: &cli::Opts, input_contents: &[u8]) -> Result<Vec<Output>>;
+}
+
+/// Every format hdiag renders, in the order they are detected in
+pub static BACKENDS: [&dyn DiagramBackend; 11] = [
+    &embedded::ExcalidrawImage,
+    &embedded::DrawioImage,
+    &excalidraw::Excalidraw,
+    &library::Library,
+    &obsidian::Obsidian,
+    &mxgraph::Drawio,
+    &mermaid::Mermaid,
+    &graphviz::Graphviz,
+    &plantuml::PlantUml,
+    &dsl::Dsl,
+    &share_link::ShareLink,
+];
+
+pub fn by_name(name: &str) -> Option<&'static dyn DiagramBackend> {
+    BACKENDS.iter().copied().find(|b| b.name() == name)
+}
+
+/// Warns about the options that are set but that the backend ignores
+pub fn warn_unsupported(backend: &dyn DiagramBackend, opts: &cli::Opts) {
+    let name = backend.name();
+    let supports = backend.supports();
+    let ignored = [
+        // The default format is for the formats that have fonts, it's only
+        // worth a warning when it was asked for
+        (
+            !supports.fonts && opts.font_format_set && opts.output_format == cli::FontFormat::Embed,
+            "diagrams use the fonts installed on the system, none are embedded",
+        ),
+        (
+            !supports.fonts && opts.font_format_set && opts.output_format == cli::FontFormat::Path,
+            "diagrams can't have their text converted to paths, it is kept as text",
+        ),
+        (
+            !supports.embed_source && opts.export.embed_source,
+            "svgs can't embed their source, ignoring `--source`",
+        ),
+        (
+            !supports.native_engine && opts.engine == cli::Engine::Native,
+            "diagrams have no native engine, ignoring `--engine native`",
+        ),
+        (
+            !supports.assets && opts.asset_dir.is_some(),
+            "diagrams have no images to look up, ignoring `--asset-dir`",
+        ),
+        (
+            !supports.templates && !opts.vars.is_empty(),
+            "diagrams have no placeholders to fill, ignoring `--vars` and `--set`",
+        ),
+        (
+            !supports.translations && !opts.locales.is_empty(),
+            "diagrams have no text to translate, every locale is rendered the same",
+        ),
+        (
+            !supports.pages && opts.pages != cli::Pages::First,
+            "diagrams have no pages, ignoring `--page` and `--all-pages`",
+        ),
+        (
+            !supports.items && opts.split_library,
+            "diagrams have no items, ignoring `--library-items`",
+        ),
+    ];
+    for (_, message) in ignored.iter().filter(|(ignored, _)| *ignored) {
+        warn!("{name} {message}");
+    }
+}
@@ -0,0 +1,223 @@
+use std::{
+    fmt::Write as _,
+    fs, io,
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use color_eyre::{
+    eyre::{ContextCompat as _, WrapErr as _},
+    Result,
+};
+use sha2::{Digest as _, Sha256};
+use tracing::debug;
+
+use crate::{cli, excalidraw, graphviz, i18n::Catalogue, mermaid, template::Vars, Output};
+
+const NAMES_FILE: &str = "names.json";
+
+/// Everything that can change the rendered output of a file
+pub struct Key<'a> {
+    pub input_type: &'a cli::FileType,
+    pub input_contents: &'a [u8],
+    pub output_format: &'a cli::FontFormat,
+    pub engine: cli::Engine,
+    pub export: &'a cli::ExportOpts,
+    pub accessibility: Option<&'a cli::AccessibilityOpts>,
+    pub split_library: bool,
+    pub pages: &'a cli::Pages,
+    /// Key that decrypts a share link
+    pub share_link: Option<&'a str>,
+    /// Directory images are looked up in
+    pub asset_dir: Option<&'a Path>,
+    /// Values of the placeholders in the text
+    pub vars: &'a Vars,
+    /// Translations the text is swapped for
+    pub translation: Option<&'a Catalogue>,
+}
+
+/// Names, sizes and modification times of the files in a directory, which
+/// change whenever one of the files does
+fn dir_listing(dir: &Path) -> String {
+    let Ok(entries) = fs::read_dir(dir) else {
+        debug!(dir = %dir.display(), "Failed listing directory for cache key");
+        return String::new();
+    };
+    let mut files = entries
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            let metadata = entry.metadata().ok()?;
+            Some(format!(
+                "{:?} {} {:?}",
+                entry.file_name(),
+                metadata.len(),
+                metadata.modified().ok()
+            ))
+        })
+        .collect::<Vec<_>>();
+    files.sort();
+    files.join("\n")
+}
+
+impl Key<'_> {
+    fn digest(&self) -> String {
+        let mut hasher = Sha256::new();
+        // The options are hashed through their debug representation, which
+        // changes whenever they do
+        let opts = format!(
+            "{:?} {:?} {:?} {:?} {:?} {:?} {:?} {:?} {:?} {:?} {:?}",
+            self.input_type,
+            self.output_format,
+            self.engine,
+            self.export,
+            self.accessibility,
+            self.split_library,
+            self.pages,
+            self.share_link,
+            self.asset_dir,
+            self.vars,
+            self.translation
+        );
+        let assets = self.asset_dir.map(dir_listing).unwrap_or_default();
+        let fields = [
+            env!("CARGO_PKG_VERSION").as_bytes(),
+            excalidraw::VERSION.as_bytes(),
+            mermaid::VERSION.as_bytes(),
+            graphviz::VERSION.as_bytes(),
+            opts.as_bytes(),
+            assets.as_bytes(),
+            self.input_contents,
+        ];
+        // Every field is prefixed by its length, so two fields can't run into
+        // each other and collide
+        for field in fields {
+            hasher.update((field.len() as u64).to_le_bytes());
+            hasher.update(field);
+        }
+
+        hasher
+            .finalize()
+            .iter()
+            .fold(String::new(), |mut output, b| {
+                write!(output, "{b:02x}").expect("Writing to a string can't fail");
+                output
+            })
+    }
+}
+
+pub struct Cache {
+    dir: PathBuf,
+}
+
+impl Cache {
+    pub fn new(dir: &Path) -> Result<Self> {
+        fs::create_dir_all(dir)
+            .wrap_err_with(|| format!("Failed to create cache dir {}", dir.display()))?;
+        Ok(Self {
+            dir: dir.to_path_buf(),
+        })
+    }
+
+    /// Every entry is a directory with the outputs of a render, and a file
+    /// with their names
+    fn entry_path(&self, key: &Key<'_>) -> PathBuf {
+        self.dir.join(key.digest())
+    }
+
+    pub fn get(&self, key: &Key<'_>) -> Result<Option<Vec<Output>>> {
+        let path = self.entry_path(key);
+        let names = match fs::read(path.join(NAMES_FILE)) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                debug!(path = %path.display(), "Cache miss");
+                return Ok(None);
+            }
+            Err(e) => {
+                return Err(e)
+                    .wrap_err_with(|| format!("Failed to read cache entry {}", path.display()))
+            }
+        };
+        let names: Vec<Option<String>> = serde_json::from_slice::<serde_json::Value>(&names)
+            .ok()
+            .and_then(|v| {
+                v.as_array()?
+                    .iter()
+                    .map(|n| match n {
+                        serde_json::Value::Null => Some(None),
+                        serde_json::Value::String(n) => Some(Some(n.clone())),
+                        _ => None,
+                    })
+                    .collect()
+            })
+            .with_context(|| format!("Cache entry {} is corrupted", path.display()))?;
+
+        let outputs = names
+            .into_iter()
+            .enumerate()
+            .map(|(i, name)| {
+                let svg_path = path.join(format!("{i}.svg"));
+                let svg = fs::read(&svg_path).wrap_err_with(|| {
+                    format!("Failed to read cache entry {}", svg_path.display())
+                })?;
+                Ok(Output { name, svg })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        debug!(path = %path.display(), "Cache hit");
+        Ok(Some(outputs))
+    }
+
+    pub fn put(&self, key: &Key<'_>, outputs: &[Output]) -> Result<()> {
+        let path = self.entry_path(key);
+        if path.join(NAMES_FILE).is_file() {
+            debug!(path = %path.display(), "Cache entry was already written");
+            return Ok(());
+        }
+        // Write to a temporary directory first, so that an interrupted write
+        // never leaves a partial entry behind. Every writer has its own, for
+        // the renders running at the same time
+        let writer = NEXT_WRITER.fetch_add(1, Ordering::Relaxed);
+        let temp_path = path.with_extension(format!("{}-{writer}.tmp", process::id()));
+        let written = write_entry(&temp_path, outputs);
+        let moved = written.and_then(|()| {
+            fs::rename(&temp_path, &path)
+                .wrap_err_with(|| format!("Failed to move cache entry to {}", path.display()))
+        });
+        if moved.is_err() {
+            // Best effort, a leftover is only wasted space
+            let _ = fs::remove_dir_all(&temp_path);
+        }
+        match moved {
+            // Another render wrote the same entry first
+            Err(_) if path.join(NAMES_FILE).is_file() => {
+                debug!(path = %path.display(), "Cache entry was written by another render");
+                Ok(())
+            }
+            moved => moved,
+        }
+    }
+}
+
+/// Tells apart the temporary directories of the writers of this process
+static NEXT_WRITER: AtomicUsize = AtomicUsize::new(0);
+
+/// Writes the outputs and their names to a new entry directory
+fn write_entry(temp_path: &Path, outputs: &[Output]) -> Result<()> {
+    fs::create_dir_all(temp_path)
+        .wrap_err_with(|| format!("Failed to create cache entry {}", temp_path.display()))?;
+
+    for (i, output) in outputs.iter().enumerate() {
+        fs::write(temp_path.join(format!("{i}.svg")), &output.svg)
+            .wrap_err_with(|| format!("Failed to write cache entry {}", temp_path.display()))?;
+    }
+    let names = outputs
+        .iter()
+        .map(|o| o.name.as_deref())
+        .collect::<Vec<_>>();
+    fs::write(
+        temp_path.join(NAMES_FILE),
+        serde_json::json!(names).to_string(),
+    )
+    .wrap_err_with(|| format!("Failed to write cache entry {}", temp_path.display()))?;
+    Ok(())
+}
@@ -1,56 +1,250 @@
-use clap::{Parser, ValueEnum};
-use std::{fs, io, path::PathBuf};
+use clap::{
+    builder::{PossibleValue, PossibleValuesParser},
+    error::ErrorKind,
+    CommandFactory as _, Parser, Subcommand, ValueEnum,
+};
+use serde::Deserialize;
+use std::{
+    fmt, fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    backend::{self, DiagramBackend},
+    config, detect,
+    i18n::Catalogue,
+    share_link,
+    template::{self, Vars},
+};
 
 #[derive(Parser)]
-#[command(author, version, about, long_about = None)]
+#[command(
+    author,
+    version,
+    about,
+    long_about = None,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
+#[allow(clippy::struct_excessive_bools)]
 pub struct Cli {
+    #[command(subcommand)]
+    command: Option<Commands>,
+
     /// Input file to read
-    #[arg(short)]
-    input_file: PathBuf,
+    #[arg(short, required = true)]
+    input_file: Option<PathBuf>,
 
     /// Type of the input file
-    #[arg(short = 't', value_enum, default_value_t = FileTypes::Inferred)]
-    input_type: FileTypes,
+    #[arg(short = 't', default_value = INFERRED, value_parser = input_types())]
+    input_type: String,
 
     /// Path of the output file.
-    /// Default is filename with svg extension
+    /// Default is filename with svg extension, or what `output-dir` and
+    /// `output-name` of the `hdiag.toml` make of it
     #[arg(short = 'o')]
     output_path: Option<PathBuf>,
 
-    /// What type of svg should be outputted
-    #[arg(short = 'f', value_enum, default_value_t = FontFormats::Path)]
-    font_output_format: FontFormats,
+    /// What type of svg should be outputted.
+    /// Default is path
+    #[arg(short = 'f', value_enum)]
+    font_output_format: Option<FontFormats>,
 
-    /// What theme should the svg be exported in
-    #[arg(long = "theme", value_enum, default_value_t = OutputTheme::Dark)]
-    output_theme: OutputTheme,
+    /// What theme should the svg be exported in.
+    /// Default is dark
+    #[arg(long = "theme", value_enum)]
+    output_theme: Option<OutputTheme>,
 
     /// Should the export have a background
     #[arg(short = 'b', long = "background")]
     output_background: bool,
 
+    /// Export without a background, even when the `hdiag.toml` asks for one
+    #[arg(long = "no-background", conflicts_with = "output_background")]
+    no_background: bool,
+
     /// Should the export bundle the program's source
     #[arg(long = "source")]
     embed_source: bool,
 
-    /// What scale should the export be in
-    #[arg(short, long = "scale", default_value_t = 1)]
-    scale: u8,
+    /// What scale should the export be in.
+    /// Default is 1
+    #[arg(short, long = "scale", value_parser = clap::value_parser!(u8).range(1..))]
+    scale: Option<u8>,
+
+    /// What renders the diagram
+    #[arg(long = "engine", value_enum, default_value_t = Engine::Browser)]
+    engine: Engine,
+
+    /// Make the output only depend on the input, so that exporting the
+    /// same file twice gives the same svg
+    #[arg(long = "deterministic")]
+    deterministic: bool,
+
+    /// Key to decrypt a share link scene with. Either the whole link, the
+    /// `#json=id,key` fragment, or just the key
+    #[arg(long = "share-key")]
+    share_key: Option<String>,
+
+    /// Export every item of a library to its own svg, named after the item,
+    /// instead of a single contact sheet with all of them
+    #[arg(long = "library-items")]
+    library_items: bool,
+
+    /// Page of a draw.io file to export, by name or by its position
+    /// starting at 1.
+    /// Default is the first page
+    #[arg(long = "page", value_name = "NAME|INDEX")]
+    page: Option<String>,
+
+    /// Export every page of a draw.io file to its own svg, named after the
+    /// page
+    #[arg(long = "all-pages", conflicts_with = "page")]
+    all_pages: bool,
+
+    /// Directory to look up the images of excalidraw scenes in, when the
+    /// scene doesn't embed them. Files are found by the name the scene links
+    /// to, or else named after the file id with any extension
+    #[arg(long = "asset-dir")]
+    asset_dir: Option<PathBuf>,
+
+    /// TOML file with the values of the `{{name}}` placeholders in the text
+    /// of the diagram. Values in nested tables are named like `table.name`
+    #[arg(long = "vars", value_name = "FILE")]
+    vars_file: Option<PathBuf>,
+
+    /// Value of a placeholder, winning over the one in `--vars`. Can be
+    /// repeated
+    #[arg(long = "set", value_name = "KEY=VALUE", value_parser = template::parse_assignment)]
+    set: Vec<(String, String)>,
+
+    /// Locales to render the diagram in, each to its own output named like
+    /// `name.de.svg`. Can be repeated or separated by commas
+    #[arg(long = "locale", value_delimiter = ',', requires = "translations_dir")]
+    locales: Vec<String>,
+
+    /// Directory of the translation catalogues of `--locale`, gettext `.po`
+    /// or JSON files keyed by element id. The catalogue of a diagram is
+    /// `<locale>/<diagram>.po`, or else `<locale>.po` for all diagrams, or
+    /// the same with `.json`
+    #[arg(long = "translations", value_name = "DIR", requires = "locales")]
+    translations_dir: Option<PathBuf>,
+
+    /// Don't write the output, instead check that the existing output is the
+    /// same as what would be rendered, and fail if it is not
+    #[arg(long = "check")]
+    check: bool,
+
+    /// Directory to cache rendered outputs in. Files that were already
+    /// rendered with the same options are copied from the cache instead
+    #[arg(long = "cache-dir")]
+    cache_dir: Option<PathBuf>,
+
+    /// Title of the svg, read by assistive technology.
+    /// Default is the input file name
+    #[arg(long = "title")]
+    title: Option<String>,
+
+    /// Description of the svg, read by assistive technology.
+    /// Default is all the text in the diagram
+    #[arg(long = "description")]
+    description: Option<String>,
+
+    /// Don't add a title, description or ARIA attributes to the svg
+    #[arg(long = "no-accessibility")]
+    no_accessibility: bool,
 }
 
-#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
-enum FileTypes {
-    /// Excalidraw JSON file (https://excalidraw.com)
-    Excalidraw,
-    /// Drawio mxGraph file (https://drawio.com)
-    Drawio,
-    /// Inferred from the file extension or content.
-    /// The extension is preferred
-    Inferred,
+#[derive(Subcommand)]
+enum Commands {
+    /// Run as an mdBook preprocessor, rendering the diagrams linked from the
+    /// book.
+    /// Reads the book from stdin, and writes the processed book to stdout
+    Mdbook {
+        #[command(subcommand)]
+        command: Option<MdbookCommands>,
+    },
+    /// Check excalidraw scenes for problems, like text bound to missing
+    /// containers, or images missing their files
+    Lint {
+        /// Scenes to check
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+
+        /// Fix the problems that can be fixed, and save the fixed scenes
+        #[arg(long = "fix")]
+        fix: bool,
+    },
+    /// Convert a diagram between excalidraw and draw.io. Rectangles,
+    /// ellipses, diamonds, text and arrows are converted, other shapes are
+    /// reported and left out
+    Convert {
+        /// Diagram to convert
+        input_file: PathBuf,
+
+        /// Path of the converted diagram.
+        /// Default is the input file name with the extension of the format
+        #[arg(short = 'o')]
+        output_path: Option<PathBuf>,
+
+        /// Format of the input.
+        /// Default is inferred from the file
+        #[arg(long = "from", value_enum)]
+        from: Option<ConvertFormat>,
+
+        /// Format to convert to.
+        /// Default is excalidraw for hdiag and mermaid inputs, and the other
+        /// format than the input's for the rest
+        #[arg(long = "to", value_enum)]
+        to: Option<ConvertFormat>,
+
+        /// Page of a draw.io file to convert, by name or by its position
+        /// starting at 1.
+        /// Default is the first page
+        #[arg(long = "page", value_name = "NAME|INDEX")]
+        page: Option<String>,
+    },
+    /// Print the text of diagrams, for search indexes and translation
+    /// catalogues. Reads the text elements of excalidraw scenes and the
+    /// labels of every page of draw.io files
+    Text {
+        /// Diagrams to read the text of
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+
+        /// How to print the text
+        #[arg(long = "format", value_enum, default_value_t = TextFormat::Plain)]
+        format: TextFormat,
+
+        /// Path of the file to write the text to.
+        /// Default is stdout
+        #[arg(short = 'o')]
+        output_path: Option<PathBuf>,
+    },
+}
+
+#[derive(Subcommand)]
+enum MdbookCommands {
+    /// Check whether the preprocessor supports a renderer
+    Supports { renderer: String },
+}
+
+/// Name of `-t` for types inferred from the file
+const INFERRED: &str = "inferred";
+
+/// Values of `-t`, one for every backend
+fn input_types() -> PossibleValuesParser {
+    let inferred = PossibleValue::new(INFERRED)
+        .help("Inferred from the file extension or content.\nThe extension is preferred");
+    let backends = backend::BACKENDS
+        .iter()
+        .map(|b| PossibleValue::new(b.name()).help(b.description()));
+    PossibleValuesParser::new(backends.chain([inferred]))
 }
 
-#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
-enum FontFormats {
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FontFormats {
     /// The raw svg outputted by the renderer for the filetype
     Raw,
     /// Embed no fonts into the svg, and rely on the system installed ones
@@ -62,16 +256,107 @@ enum FontFormats {
     /// filesize and more portable
     Path,
 }
-#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Debug)]
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
 pub enum OutputTheme {
     Dark,
     Light,
 }
 
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Debug)]
+pub enum Engine {
+    /// Export with excalidraw itself, in headless Chrome
+    Browser,
+    /// Draw the shapes in hdiag, without a browser. Falls back to the browser
+    /// for elements it can't draw
+    Native,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MdbookCommand {
+    Supports { renderer: String },
+    Preprocess,
+}
+
 #[derive(Clone, Debug, PartialEq, Eq)]
-pub enum FileType {
+pub struct LintOpts {
+    pub files: Vec<PathBuf>,
+    pub fix: bool,
+}
+
+/// Formats diagrams can be converted between
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Debug)]
+pub enum ConvertFormat {
+    /// Excalidraw scene, saved as `.excalidraw`
     Excalidraw,
+    /// Draw.io file, saved as `.drawio`
     Drawio,
+    /// Text description in hdiag's format, only read
+    Hdiag,
+    /// Mermaid diagram, only read, flowcharts become shapes and arrows
+    Mermaid,
+}
+
+/// How `hdiag text` prints the text of diagrams
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Debug)]
+pub enum TextFormat {
+    /// Every text on a line of its own, prefixed by its file when there are
+    /// several
+    Plain,
+    /// Array of the texts, with their file, page, element id and box
+    Json,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TextOpts {
+    pub files: Vec<PathBuf>,
+    pub format: TextFormat,
+    pub output_file: Option<PathBuf>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConvertOpts {
+    pub input_file: PathBuf,
+    pub output_file: Option<PathBuf>,
+    pub from: Option<ConvertFormat>,
+    pub to: Option<ConvertFormat>,
+    pub page: Pages,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Command {
+    Render(Opts),
+    Mdbook(MdbookCommand),
+    Lint(LintOpts),
+    Convert(ConvertOpts),
+    Text(TextOpts),
+}
+
+/// Format of an input, rendered by its backend
+#[derive(Clone, Copy)]
+pub struct FileType(pub &'static dyn DiagramBackend);
+
+impl fmt::Debug for FileType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.0.name())
+    }
+}
+
+impl PartialEq for FileType {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.name() == other.0.name()
+    }
+}
+
+impl Eq for FileType {}
+
+/// Pages of a draw.io file to export
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Pages {
+    First,
+    /// A page by name, or by its position starting at 1
+    One(String),
+    All,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq)]
@@ -88,80 +373,145 @@ pub struct ExportOpts {
     pub include_background: bool,
     pub embed_source: bool,
     pub scale: u8,
+    pub deterministic: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AccessibilityOpts {
+    pub title: String,
+    /// If unset, the text contained in the diagram is used
+    pub description: Option<String>,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Opts {
     pub input_file: PathBuf,
     pub input_type: FileType,
+    /// Key to decrypt share links with
+    pub share_key: Option<String>,
     pub output_file: PathBuf,
     pub output_format: FontFormat,
+    /// Whether `-f` or the `hdiag.toml` picked the font format, instead of
+    /// it being the default
+    pub font_format_set: bool,
+    pub engine: Engine,
     pub export: ExportOpts,
+    pub accessibility: Option<AccessibilityOpts>,
+    pub cache_dir: Option<PathBuf>,
+    /// Where to look up the images scenes don't embed
+    pub asset_dir: Option<PathBuf>,
+    /// Values of the placeholders in the text of the diagram
+    pub vars: Vars,
+    /// Locales to render the diagram in, each to its own output
+    pub locales: Vec<String>,
+    pub translations_dir: Option<PathBuf>,
+    /// Translations the text is swapped for, set when rendering a locale
+    pub translation: Option<Catalogue>,
+    pub check: bool,
+    /// Export library items to their own files
+    pub split_library: bool,
+    pub pages: Pages,
 }
 
-fn is_valid_json<R: io::Read>(r: R) -> bool {
-    serde_json::from_reader::<R, ()>(r).is_ok()
-}
+impl Command {
+    pub fn parse() -> Self {
+        let cli = Cli::parse();
 
-fn is_valid_xml<R: io::Read>(r: R) -> bool {
-    let mut reader = quick_xml::reader::Reader::from_reader(io::BufReader::new(r));
-    let mut buf = vec![];
-    loop {
-        match reader.read_event_into(&mut buf) {
-            Ok(quick_xml::events::Event::Eof) => return true,
-            Ok(_) => {}
-            Err(_) => return false,
+        match cli.command {
+            Some(Commands::Mdbook { command }) => Self::Mdbook(match command {
+                Some(MdbookCommands::Supports { renderer }) => MdbookCommand::Supports { renderer },
+                None => MdbookCommand::Preprocess,
+            }),
+            Some(Commands::Lint { files, fix }) => Self::Lint(LintOpts { files, fix }),
+            Some(Commands::Convert {
+                input_file,
+                output_path,
+                from,
+                to,
+                page,
+            }) => Self::Convert(ConvertOpts {
+                input_file,
+                output_file: output_path,
+                from,
+                to,
+                page: page.map_or(Pages::First, Pages::One),
+            }),
+            Some(Commands::Text {
+                files,
+                format,
+                output_path,
+            }) => Self::Text(TextOpts {
+                files,
+                format,
+                output_file: output_path,
+            }),
+            None => Self::Render(Opts::from_cli(cli)),
         }
     }
 }
 
-impl Opts {
-    pub fn parse() -> Self {
-        let cli = Cli::parse();
+/// Replaces the extension of the file, taking into account the suffixes of
+/// formats like `.excalidraw.md`, so `a.excalidraw.md` becomes `a.svg`
+pub fn with_output_extension(path: &Path, extension: &str) -> PathBuf {
+    let name = path.file_name().map(|n| n.to_string_lossy());
+    let stem = name
+        .as_deref()
+        .and_then(|name| detect::from_name(name).and_then(|(_, suffix)| name.strip_suffix(suffix)));
+    stem.map_or_else(
+        || path.with_extension(extension),
+        |stem| path.with_file_name(format!("{stem}.{extension}")),
+    )
+}
 
-        let input_file = cli.input_file;
+/// Exits with the error as the reason a value is invalid
+fn invalid_value(error: &color_eyre::Report) -> ! {
+    let reason = error.chain().map(ToString::to_string).collect::<Vec<_>>();
+    Cli::command()
+        .error(ErrorKind::InvalidValue, reason.join(": "))
+        .exit()
+}
 
-        let output_path = cli.output_path.map_or_else(
-            || {
-                let name = input_file
-                    .file_name()
-                    .expect("File had no file name to infert output file");
-                let mut p = PathBuf::from(name);
-                p.set_extension("svg");
-                p
-            },
-            |o| o,
-        );
-
-        let input_type = match cli.input_type {
-            FileTypes::Excalidraw => FileType::Excalidraw,
-            FileTypes::Drawio => FileType::Drawio,
-            FileTypes::Inferred => {
-                match input_file
-                    .extension()
-                    .map(|s| s.to_str().expect("Extension was not UTF-8"))
-                {
-                    Some("excalidraw") => FileType::Excalidraw,
-                    Some("drawio") => FileType::Drawio,
-                    Some(_) | None => {
-                        let f = fs::OpenOptions::new()
-                            .read(true)
-                            .write(false)
-                            .open(&input_file)
-                            .expect("Could not open file to infer file type");
-                        if is_valid_json(&f) {
-                            FileType::Excalidraw
-                        } else if is_valid_xml(&f) {
-                            FileType::Drawio
-                        } else {
-                            panic!("Could not infer filetype for {}", input_file.display());
-                        }
-                    }
-                }
-            }
+impl Opts {
+    fn from_cli(cli: Cli) -> Self {
+        let input_file = cli
+            .input_file
+            .expect("Input file is required when there is no subcommand");
+        let settings = config::settings(&input_file).unwrap_or_else(|e| invalid_value(&e));
+        let output_path = cli
+            .output_path
+            .unwrap_or_else(|| settings.output_path(&input_file));
+        // Svg inputs are named like their output
+        let overwrites_input = fs::canonicalize(&output_path)
+            .is_ok_and(|o| fs::canonicalize(&input_file).is_ok_and(|i| i == o));
+        if overwrites_input {
+            Cli::command()
+                .error(
+                    ErrorKind::ArgumentConflict,
+                    "The output would overwrite the input, pick another path with `-o`",
+                )
+                .exit();
+        }
+
+        let share_key = cli
+            .share_key
+            .as_deref()
+            .map(|link| share_link::parse_key(link).to_owned());
+        let input_type = match cli.input_type.as_str() {
+            // A key only makes sense for a share link
+            INFERRED if share_key.is_some() => FileType(&share_link::ShareLink),
+            INFERRED => detect::file_type(&input_file).unwrap_or_else(|e| invalid_value(&e)),
+            name => FileType(backend::by_name(name).expect("Clap only allows backend names")),
         };
 
-        let output_format = match cli.font_output_format {
+        let vars = template::load(cli.vars_file.as_deref(), &cli.set)
+            .unwrap_or_else(|e| invalid_value(&e));
+
+        let font_format_set = cli.font_output_format.is_some() || settings.font_format.is_some();
+        let font_format = cli
+            .font_output_format
+            .or(settings.font_format)
+            .unwrap_or(FontFormats::Path);
+        let output_format = match font_format {
             FontFormats::Raw => FontFormat::Raw,
             FontFormats::Embed => FontFormat::Embed,
             FontFormats::Path => FontFormat::Path,
@@ -169,18 +519,57 @@ impl Opts {
         };
 
         let export_opts = ExportOpts {
-            theme: cli.output_theme,
-            include_background: cli.output_background,
+            theme: cli
+                .output_theme
+                .or(settings.theme)
+                .unwrap_or(OutputTheme::Dark),
+            include_background: cli.output_background
+                || (!cli.no_background && settings.background.unwrap_or(false)),
             embed_source: cli.embed_source,
-            scale: cli.scale,
+            scale: cli.scale.or(settings.scale).unwrap_or(1),
+            // The output has to be reproducible to be compared against
+            deterministic: cli.deterministic || cli.check,
+        };
+
+        let accessibility = if cli.no_accessibility {
+            None
+        } else {
+            let title = cli.title.unwrap_or_else(|| {
+                input_file
+                    .file_stem()
+                    .expect("File had no file name to infer title")
+                    .to_string_lossy()
+                    .into_owned()
+            });
+            Some(AccessibilityOpts {
+                title,
+                description: cli.description,
+            })
         };
 
         Self {
             input_file,
             input_type,
+            share_key,
             output_file: output_path,
             output_format,
+            font_format_set,
+            engine: cli.engine,
             export: export_opts,
+            accessibility,
+            cache_dir: cli.cache_dir,
+            asset_dir: cli.asset_dir,
+            vars,
+            locales: cli.locales,
+            translations_dir: cli.translations_dir,
+            translation: None,
+            check: cli.check,
+            split_library: cli.library_items,
+            pages: match (cli.page, cli.all_pages) {
+                (_, true) => Pages::All,
+                (Some(page), false) => Pages::One(page),
+                (None, false) => Pages::First,
+            },
         }
     }
 }
@@ -0,0 +1,155 @@
+//! Project defaults from a `hdiag.toml` in the directory of the input or
+//! above it, with overrides for the files matching a glob
+
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+use color_eyre::{
+    eyre::{bail, WrapErr},
+    Result,
+};
+use glob::{MatchOptions, Pattern};
+use serde::Deserialize;
+
+use crate::cli::{self, FontFormats, OutputTheme};
+
+/// Name of the config file
+pub const FILE_NAME: &str = "hdiag.toml";
+
+/// Defaults for the options of a render, the flags on the command line win
+/// over them
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Settings {
+    pub theme: Option<OutputTheme>,
+    pub background: Option<bool>,
+    pub scale: Option<u8>,
+    pub font_format: Option<FontFormats>,
+    /// Directory outputs are saved in, relative to the config file
+    pub output_dir: Option<PathBuf>,
+    /// File name of outputs, where `{name}` is the name of the input without
+    /// its extension
+    pub output_name: Option<String>,
+}
+
+impl Settings {
+    /// Takes the settings `other` sets
+    fn merge(&mut self, other: Self) {
+        self.theme = other.theme.or(self.theme);
+        self.background = other.background.or(self.background);
+        self.scale = other.scale.or(self.scale);
+        self.font_format = other.font_format.or(self.font_format);
+        self.output_dir = other.output_dir.or_else(|| self.output_dir.take());
+        self.output_name = other.output_name.or_else(|| self.output_name.take());
+    }
+
+    /// Where the output of the input is saved, when there is no `-o`
+    pub fn output_path(&self, input_file: &Path) -> PathBuf {
+        let name = input_file
+            .file_name()
+            .expect("File had no file name to infer output file");
+        let default = cli::with_output_extension(Path::new(name), "svg");
+        let file_name = self
+            .output_name
+            .as_ref()
+            .map_or(default.clone(), |template| {
+                let stem = default.file_stem().unwrap_or_default().to_string_lossy();
+                PathBuf::from(template.replace("{name}", &stem))
+            });
+        self.output_dir
+            .as_ref()
+            .map_or(file_name.clone(), |dir| dir.join(&file_name))
+    }
+}
+
+/// Settings for the files matching a glob
+#[derive(Deserialize)]
+struct Override {
+    /// Glob of the paths of the files, relative to the config file
+    files: String,
+    #[serde(flatten)]
+    settings: Settings,
+    /// Keys that are no settings, to report the typos in them
+    #[serde(flatten)]
+    unknown: toml::Table,
+}
+
+#[derive(Deserialize)]
+struct Config {
+    #[serde(default, rename = "override")]
+    overrides: Vec<Override>,
+    #[serde(flatten)]
+    defaults: Settings,
+    #[serde(flatten)]
+    unknown: toml::Table,
+}
+
+fn check_known(unknown: &toml::Table) -> Result<()> {
+    if let Some(key) = unknown.keys().next() {
+        bail!("unknown setting `{key}`");
+    }
+    Ok(())
+}
+
+/// The config file in the directory of the file or the closest one above it
+fn find(file: &Path) -> Option<PathBuf> {
+    file.ancestors()
+        .skip(1)
+        .map(|dir| dir.join(FILE_NAME))
+        .find(|path| path.is_file())
+}
+
+/// Reads the config file, with the output directories resolved against the
+/// directory of the file
+fn load(path: &Path) -> Result<Config> {
+    let contents = fs::read_to_string(path)
+        .wrap_err_with(|| format!("Failed to read file {}", path.display()))?;
+    let mut config: Config = toml::from_str(&contents).wrap_err("Invalid settings")?;
+    check_known(&config.unknown)?;
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    let settings = std::iter::once(&mut config.defaults)
+        .chain(config.overrides.iter_mut().map(|o| &mut o.settings));
+    for settings in settings {
+        if settings.scale == Some(0) {
+            bail!("`scale` must be at least 1");
+        }
+        settings.output_dir = settings.output_dir.as_ref().map(|d| dir.join(d));
+    }
+    Ok(config)
+}
+
+/// Settings of the config file of the input, with the overrides for it
+/// applied in the order they are in the file. No config file gives no
+/// settings
+pub fn settings(input_file: &Path) -> Result<Settings> {
+    let input_file = fs::canonicalize(input_file).or_else(|_| {
+        env::current_dir()
+            .map(|dir| dir.join(input_file))
+            .wrap_err("Failed reading the working directory")
+    })?;
+    let Some(path) = find(&input_file) else {
+        return Ok(Settings::default());
+    };
+    let config = load(&path).wrap_err_with(|| format!("Failed reading {}", path.display()))?;
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    let relative = input_file.strip_prefix(dir).unwrap_or(&input_file);
+    // Like in shells, `*` stays in a directory and `**` goes through them
+    let options = MatchOptions {
+        require_literal_separator: true,
+        ..MatchOptions::new()
+    };
+    let mut settings = config.defaults;
+    for (position, entry) in (1..).zip(config.overrides) {
+        let context = || format!("Failed reading override {position} of {}", path.display());
+        check_known(&entry.unknown).wrap_err_with(context)?;
+        let pattern = Pattern::new(&entry.files)
+            .wrap_err_with(|| format!("`{}` is not a valid glob", entry.files))
+            .wrap_err_with(context)?;
+        if pattern.matches_path_with(relative, options) {
+            settings.merge(entry.settings);
+        }
+    }
+    Ok(settings)
+}
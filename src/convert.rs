@@ -0,0 +1,805 @@
+//! Converts diagrams between excalidraw and draw.io, for the shapes both of
+//! them can draw, and turns hdiag text descriptions and mermaid diagrams
+//! into either of them
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fs,
+    path::Path,
+};
+
+use color_eyre::{
+    eyre::{bail, WrapErr},
+    Result,
+};
+use hdiag::scene::{
+    AppState, Arrowhead, Binding, BoundElement, Element, ElementData, FillStyle, Linear, Roundness,
+    Scene, StrokeStyle, Text, TextAlign, VerticalAlign, FONT_FAMILY_CASCADIA,
+    FONT_FAMILY_HELVETICA, FONT_FAMILY_VIRGIL,
+};
+use serde_json::Map;
+use tokio::runtime::Runtime;
+use tracing::{info, warn};
+
+use crate::{
+    backend::DiagramBackend as _,
+    cli::{self, ConvertFormat},
+    detect,
+    drawio::{self, Cell, CellKind, GraphModel, Point, Style},
+    dsl, embedded, excalidraw,
+    geometry::Rect,
+    mxgraph::{self, Layout},
+    obsidian,
+    svg::escape_xml,
+};
+
+/// Line height of excalidraw text, relative to the font size
+const LINE_HEIGHT: f64 = 1.25;
+
+/// Cells or elements that have no counterpart in the other format, by what
+/// they are
+#[derive(Default)]
+struct Unmapped(BTreeMap<String, Vec<String>>);
+
+impl Unmapped {
+    fn add(&mut self, kind: String, id: &str) {
+        self.0.entry(kind).or_default().push(id.to_owned());
+    }
+
+    fn report(&self) {
+        for (kind, ids) in &self.0 {
+            warn!(ids = ids.join(", "), "Left out {} {kind}", ids.len());
+        }
+    }
+}
+
+const fn extension(format: ConvertFormat) -> &'static str {
+    match format {
+        ConvertFormat::Excalidraw => "excalidraw",
+        ConvertFormat::Drawio => "drawio",
+        ConvertFormat::Hdiag => "hdiag",
+        ConvertFormat::Mermaid => "mmd",
+    }
+}
+
+fn input_format(path: &Path) -> Result<ConvertFormat> {
+    let file_type = detect::file_type(path)?;
+    match file_type.0.name() {
+        "excalidraw" | "image" | "obsidian" => Ok(ConvertFormat::Excalidraw),
+        "drawio" | "drawio-image" => Ok(ConvertFormat::Drawio),
+        "hdiag" => Ok(ConvertFormat::Hdiag),
+        "mermaid" => Ok(ConvertFormat::Mermaid),
+        name => {
+            bail!("Can't convert {name} diagrams, only excalidraw, draw.io, hdiag and mermaid ones")
+        }
+    }
+}
+
+/// Reads a scene, from an `.excalidraw` file or from the files that embed
+/// one
+pub fn read_scene(contents: &[u8]) -> Result<Scene> {
+    let json = if embedded::ExcalidrawImage.detect(contents).is_ok() {
+        embedded::excalidraw_scene(contents)?
+    } else if obsidian::Obsidian.detect(contents).is_ok() {
+        obsidian::extract_scene(contents)?
+    } else {
+        contents.to_vec()
+    };
+    serde_json::from_slice(&json).wrap_err("File is not a valid excalidraw scene")
+}
+
+fn read_drawio(contents: &[u8], page: &cli::Pages) -> Result<GraphModel> {
+    let file = if embedded::DrawioImage.detect(contents).is_ok() {
+        embedded::drawio_file(contents)?
+    } else {
+        contents.to_vec()
+    };
+    let pages = drawio::pages(&file)?;
+    let page = drawio::select_pages(&pages, page)?[0];
+    info!(page = page.name, "Converting page");
+    Ok(page.model.clone())
+}
+
+/// Converts a mermaid diagram with mermaid-to-excalidraw, in the excalidraw
+/// page
+fn read_mermaid(rt: &Runtime, contents: &[u8]) -> Result<Scene> {
+    let json = rt.block_on(excalidraw::mermaid_scene(contents.to_vec()))?;
+    let scene: Scene =
+        serde_json::from_slice(&json).wrap_err("Converted mermaid diagram is not a valid scene")?;
+    let only_images = scene
+        .live_elements()
+        .all(|e| matches!(e.data, ElementData::Image(_)));
+    if only_images {
+        warn!(
+            "Only flowcharts are converted to shapes, the diagram is a single image in the scene"
+        );
+    }
+    Ok(scene)
+}
+
+/// Reads the input as an excalidraw scene, which every conversion goes
+/// through
+fn read_as_scene(
+    rt: &Runtime,
+    from: ConvertFormat,
+    contents: &[u8],
+    page: &cli::Pages,
+    unmapped: &mut Unmapped,
+) -> Result<Scene> {
+    match from {
+        ConvertFormat::Excalidraw => read_scene(contents),
+        ConvertFormat::Drawio => Ok(to_excalidraw(&read_drawio(contents, page)?, unmapped)),
+        ConvertFormat::Hdiag => {
+            let source = std::str::from_utf8(contents).wrap_err("Diagram is not valid UTF-8")?;
+            dsl::compile(source)
+        }
+        ConvertFormat::Mermaid => read_mermaid(rt, contents),
+    }
+}
+
+pub fn run(rt: &Runtime, opts: &cli::ConvertOpts) -> Result<()> {
+    let from = match opts.from {
+        Some(from) => from,
+        None => input_format(&opts.input_file)
+            .wrap_err("Failed inferring the format of the input, pick it with `--from`")?,
+    };
+    let to = opts.to.unwrap_or(match from {
+        ConvertFormat::Excalidraw => ConvertFormat::Drawio,
+        ConvertFormat::Drawio | ConvertFormat::Hdiag | ConvertFormat::Mermaid => {
+            ConvertFormat::Excalidraw
+        }
+    });
+    if matches!(to, ConvertFormat::Hdiag | ConvertFormat::Mermaid) {
+        bail!("hdiag and mermaid are only input formats, pick excalidraw or draw.io with `--to`");
+    }
+    if from == to {
+        bail!(
+            "The input is already {}, pick another format with `--to`",
+            extension(to)
+        );
+    }
+    let output_file = opts.output_file.clone().unwrap_or_else(|| {
+        let name = opts
+            .input_file
+            .file_name()
+            .expect("File had no file name to infer output file");
+        cli::with_output_extension(Path::new(name), extension(to))
+    });
+    let overwrites_input = fs::canonicalize(&output_file)
+        .is_ok_and(|o| fs::canonicalize(&opts.input_file).is_ok_and(|i| i == o));
+    if overwrites_input {
+        bail!("The output would overwrite the input, pick another path with `-o`");
+    }
+
+    let contents = fs::read(&opts.input_file)
+        .wrap_err_with(|| format!("Failed to read file {}", opts.input_file.display()))?;
+    let mut unmapped = Unmapped::default();
+    let scene = read_as_scene(rt, from, &contents, &opts.page, &mut unmapped)?;
+    let converted = if to == ConvertFormat::Drawio {
+        to_drawio(&scene, &mut unmapped)
+    } else {
+        let mut json = serde_json::to_string_pretty(&scene).wrap_err("Failed serializing scene")?;
+        json.push('\n');
+        json
+    };
+    unmapped.report();
+
+    fs::write(&output_file, converted)
+        .wrap_err_with(|| format!("Failed to write file {}", output_file.display()))?;
+    info!(path = %output_file.display(), "Saved converted diagram");
+    Ok(())
+}
+
+fn font_family(name: Option<&str>) -> u32 {
+    let name = name.unwrap_or_default().to_lowercase();
+    if name.contains("courier") || name.contains("mono") || name.contains("cascadia") {
+        FONT_FAMILY_CASCADIA
+    } else if name.contains("virgil") || name.contains("architects daughter") {
+        FONT_FAMILY_VIRGIL
+    } else {
+        FONT_FAMILY_HELVETICA
+    }
+}
+
+const fn font_family_name(family: u32) -> &'static str {
+    match family {
+        FONT_FAMILY_VIRGIL => "Architects Daughter",
+        FONT_FAMILY_CASCADIA => "Courier New",
+        _ => "Helvetica",
+    }
+}
+
+fn arrowhead(style: &Style, start: bool) -> Option<Arrowhead> {
+    let (kind_key, fill_key, default) = if start {
+        ("startArrow", "startFill", "none")
+    } else {
+        ("endArrow", "endFill", "classic")
+    };
+    let filled = style.get(fill_key) != Some("0");
+    let pick = |filled_head, outline_head| if filled { filled_head } else { outline_head };
+    match style.get(kind_key).unwrap_or(default) {
+        "none" | "" => None,
+        "block" | "blockThin" => Some(pick(Arrowhead::Triangle, Arrowhead::TriangleOutline)),
+        "oval" => Some(pick(Arrowhead::Circle, Arrowhead::CircleOutline)),
+        "diamond" | "diamondThin" => Some(pick(Arrowhead::Diamond, Arrowhead::DiamondOutline)),
+        "dash" => Some(Arrowhead::Bar),
+        _ => Some(Arrowhead::Arrow),
+    }
+}
+
+/// The draw.io marker, and whether it is filled
+const fn marker(arrowhead: Option<Arrowhead>) -> (&'static str, bool) {
+    match arrowhead {
+        None => ("none", true),
+        Some(Arrowhead::Arrow) => ("classic", true),
+        Some(Arrowhead::Bar) => ("dash", true),
+        Some(Arrowhead::Dot | Arrowhead::Circle) => ("oval", true),
+        Some(Arrowhead::CircleOutline) => ("oval", false),
+        Some(Arrowhead::Triangle) => ("block", true),
+        Some(Arrowhead::TriangleOutline) => ("block", false),
+        Some(Arrowhead::Diamond) => ("diamond", true),
+        Some(Arrowhead::DiamondOutline) => ("diamond", false),
+    }
+}
+
+/// Maps the parts of a draw.io style every element has
+fn apply_style(element: &mut Element, style: &Style, default_fill: Option<&str>) {
+    let base = &mut element.base;
+    base.stroke_color = style
+        .color("strokeColor", Some(mxgraph::DEFAULT_STROKE))
+        .unwrap_or_else(|| "transparent".to_owned());
+    base.background_color = style
+        .color("fillColor", default_fill)
+        .unwrap_or_else(|| "transparent".to_owned());
+    base.fill_style = match style.get("fillStyle") {
+        Some("hachure") => FillStyle::Hachure,
+        Some("cross-hatch") => FillStyle::CrossHatch,
+        Some("zigzag") => FillStyle::Zigzag,
+        _ => FillStyle::Solid,
+    };
+    base.stroke_width = style.number("strokeWidth", 1.0);
+    base.stroke_style = if !style.flag("dashed") {
+        StrokeStyle::Solid
+    } else if style
+        .get("dashPattern")
+        .is_some_and(|p| p.starts_with("1 "))
+    {
+        StrokeStyle::Dotted
+    } else {
+        StrokeStyle::Dashed
+    };
+    base.opacity = style.number("opacity", 100.0);
+    base.angle = style.number("rotation", 0.0).to_radians();
+    // Draw.io draws clean lines, unless the diagram is sketched
+    base.roughness = if style.flag("sketch") { 1.0 } else { 0.0 };
+}
+
+/// A text element in the rect, placed the way the style aligns it
+fn text_element(id: String, label: &str, style: &Style, rect: Rect) -> Element {
+    let font_size = style.number("fontSize", mxgraph::DEFAULT_FONT_SIZE);
+    let lines = label.lines().collect::<Vec<_>>();
+    let width = lines
+        .iter()
+        .map(|l| mxgraph::text_width(l, font_size))
+        .fold(0.0, f64::max);
+    let height =
+        f64::from(u32::try_from(lines.len().max(1)).unwrap_or(u32::MAX)) * font_size * LINE_HEIGHT;
+    let (text_align, x) = match style.get("align") {
+        Some("left") => (TextAlign::Left, rect.x),
+        Some("right") => (TextAlign::Right, rect.right() - width),
+        _ => (TextAlign::Center, rect.center()[0] - width / 2.0),
+    };
+    let (vertical_align, y) = match style.get("verticalAlign") {
+        Some("top") => (VerticalAlign::Top, rect.y),
+        Some("bottom") => (VerticalAlign::Bottom, rect.bottom() - height),
+        _ => (VerticalAlign::Middle, rect.center()[1] - height / 2.0),
+    };
+
+    let mut element = Element::new(
+        id,
+        ElementData::Text(Text {
+            font_size,
+            font_family: font_family(style.get("fontFamily")),
+            text: label.to_owned(),
+            text_align,
+            vertical_align,
+            container_id: None,
+            original_text: label.to_owned(),
+            line_height: LINE_HEIGHT,
+        }),
+    );
+    apply_style(&mut element, style, None);
+    let base = &mut element.base;
+    base.stroke_color = style
+        .color("fontColor", Some(mxgraph::DEFAULT_FONT_COLOR))
+        .unwrap_or_else(|| "transparent".to_owned());
+    base.background_color = "transparent".to_owned();
+    base.stroke_style = StrokeStyle::Solid;
+    (base.x, base.y, base.width, base.height) = (x, y, width, height);
+    element
+}
+
+fn bind(element: &mut Element, id: &str, kind: &str) {
+    element
+        .base
+        .bound_elements
+        .get_or_insert_with(Vec::new)
+        .push(BoundElement {
+            id: id.to_owned(),
+            kind: kind.to_owned(),
+            extra: Map::new(),
+        });
+}
+
+/// Adds the label of a shape or arrow as text bound to it
+fn add_label(elements: &mut Vec<Element>, container: &mut Element, cell: &Cell, rect: Rect) {
+    let label = cell.label();
+    if label.trim().is_empty() {
+        return;
+    }
+    let id = format!("{}-label", cell.id);
+    let mut text = text_element(id.clone(), &label, &cell.style, rect);
+    if let ElementData::Text(data) = &mut text.data {
+        data.container_id = Some(container.id.clone());
+    }
+    text.base.group_ids.clone_from(&container.base.group_ids);
+    bind(container, &id, "text");
+    elements.push(text);
+}
+
+struct ToExcalidraw<'a> {
+    cells: HashMap<&'a str, &'a Cell>,
+    layout: Layout<'a>,
+    elements: Vec<Element>,
+    /// Where every converted shape is in `elements`, by id
+    shapes: HashMap<String, usize>,
+}
+
+impl<'a> ToExcalidraw<'a> {
+    /// Ids of the vertices the cell is in, which move with it, so are groups
+    /// in excalidraw, the innermost first
+    fn group_ids(&self, cell: &Cell) -> Vec<String> {
+        let mut groups = vec![];
+        let mut parent = cell.parent.as_deref();
+        while let Some(id) = parent.filter(|_| groups.len() < self.cells.len()) {
+            let Some(parent_cell) = self.cells.get(id) else {
+                break;
+            };
+            if parent_cell.kind == CellKind::Vertex {
+                groups.push(id.to_owned());
+            }
+            parent = parent_cell.parent.as_deref();
+        }
+        groups
+    }
+
+    fn has_children(&self, id: &str) -> bool {
+        self.cells.values().any(|c| {
+            c.parent.as_deref() == Some(id) && c.geometry.as_ref().is_some_and(|g| !g.relative)
+        })
+    }
+
+    fn vertex(&mut self, cell: &Cell, unmapped: &mut Unmapped) {
+        let Some(rect) = self.layout.rect(&cell.id) else {
+            // Labels along edges are placed with the edge
+            return;
+        };
+        let style = &cell.style;
+        if style.has_name("group") {
+            return;
+        }
+        let data = match style.shape() {
+            "rectangle" => ElementData::Rectangle,
+            "ellipse" => ElementData::Ellipse,
+            "rhombus" => ElementData::Diamond,
+            "text" if cell.label().trim().is_empty() => return,
+            "text" => {
+                let mut text = text_element(cell.id.clone(), &cell.label(), style, rect);
+                text.base.group_ids = self.group_ids(cell);
+                self.elements.push(text);
+                return;
+            }
+            shape => {
+                unmapped.add(format!("draw.io `{shape}` shapes"), &cell.id);
+                return;
+            }
+        };
+        let mut element = Element::new(cell.id.clone(), data);
+        apply_style(&mut element, style, Some(mxgraph::DEFAULT_FILL));
+        if style.flag("rounded") {
+            element.base.roundness = Some(Roundness {
+                kind: 3,
+                value: None,
+            });
+        }
+        let base = &mut element.base;
+        (base.x, base.y, base.width, base.height) = (rect.x, rect.y, rect.width, rect.height);
+        base.group_ids = self.group_ids(cell);
+        // Shapes move with the cells in them
+        if self.has_children(&cell.id) {
+            base.group_ids.insert(0, cell.id.clone());
+        }
+
+        let mut label = vec![];
+        add_label(&mut label, &mut element, cell, rect);
+        self.shapes.insert(cell.id.clone(), self.elements.len());
+        self.elements.push(element);
+        self.elements.extend(label);
+    }
+
+    fn binding(&mut self, end: Option<&String>, arrow_id: &str) -> Option<Binding> {
+        let shape = *self.shapes.get(end?)?;
+        bind(&mut self.elements[shape], arrow_id, "arrow");
+        Some(Binding {
+            element_id: self.elements[shape].id.clone(),
+            focus: 0.0,
+            gap: 0.0,
+            extra: Map::new(),
+        })
+    }
+
+    fn edge(&mut self, cell: &Cell, unmapped: &mut Unmapped) {
+        let route = self.layout.route(cell).filter(|r| r.len() >= 2);
+        let Some(route) = route else {
+            unmapped.add("draw.io edges missing an end".to_owned(), &cell.id);
+            return;
+        };
+        let [x, y] = route[0];
+        let points = route
+            .iter()
+            .map(|[px, py]| [px - x, py - y])
+            .collect::<Vec<Point>>();
+        let start_binding = self.binding(cell.source.as_ref(), &cell.id);
+        let end_binding = self.binding(cell.target.as_ref(), &cell.id);
+        let mut element = Element::new(
+            cell.id.clone(),
+            ElementData::Arrow(Linear {
+                points: points.clone(),
+                last_committed_point: None,
+                start_binding,
+                end_binding,
+                start_arrowhead: arrowhead(&cell.style, true),
+                end_arrowhead: arrowhead(&cell.style, false),
+            }),
+        );
+        apply_style(&mut element, &cell.style, None);
+        let extent = |axis: usize| {
+            let values = points.iter().map(|p| p[axis]);
+            values.clone().fold(f64::NEG_INFINITY, f64::max) - values.fold(f64::INFINITY, f64::min)
+        };
+        let base = &mut element.base;
+        (base.x, base.y, base.width, base.height) = (x, y, extent(0), extent(1));
+        base.group_ids = self.group_ids(cell);
+
+        let mut labels = vec![];
+        let middle = Rect::at(mxgraph::point_along(&route, 0.5));
+        add_label(&mut labels, &mut element, cell, middle);
+        // Labels in cells of their own can't be bound, the arrow has only one
+        for label in self.cells.values().filter(|c| {
+            c.parent.as_deref() == Some(cell.id.as_str()) && c.kind == CellKind::Vertex && c.visible
+        }) {
+            let geometry = label.geometry.clone().unwrap_or_default();
+            let [x, y] = mxgraph::point_along(&route, (geometry.x + 1.0) / 2.0);
+            let [dx, dy] = geometry.offset.unwrap_or_default();
+            let mut text = text_element(
+                label.id.clone(),
+                &label.label(),
+                &label.style,
+                Rect::at([x + dx, y + dy]),
+            );
+            text.base.group_ids.clone_from(&element.base.group_ids);
+            labels.push(text);
+        }
+        self.elements.push(element);
+        self.elements.extend(labels);
+    }
+}
+
+/// Converts the cells of a draw.io diagram into excalidraw elements
+fn to_excalidraw(model: &GraphModel, unmapped: &mut Unmapped) -> Scene {
+    let mut converter = ToExcalidraw {
+        cells: model.cells.iter().map(|c| (c.id.as_str(), c)).collect(),
+        layout: Layout::new(model),
+        elements: vec![],
+        shapes: HashMap::new(),
+    };
+    // Edges are converted once the shapes they bind to are
+    for kind in [CellKind::Vertex, CellKind::Edge] {
+        for cell in model.cells.iter().filter(|c| c.kind == kind && c.visible) {
+            match kind {
+                CellKind::Vertex => converter.vertex(cell, unmapped),
+                _ => converter.edge(cell, unmapped),
+            }
+        }
+    }
+    // Seeds pick how the strokes wobble, keep them stable between runs
+    for (seed, element) in (1..).zip(&mut converter.elements) {
+        element.base.seed = seed;
+    }
+
+    Scene {
+        source: Some("hdiag".to_owned()),
+        elements: converter.elements,
+        app_state: model.background.clone().map(|background| AppState {
+            view_background_color: Some(background),
+            ..AppState::default()
+        }),
+        ..Scene::default()
+    }
+}
+
+fn escape_attr(value: &str) -> String {
+    // Parsers turn new lines in attributes into spaces
+    escape_xml(value).replace('\n', "&#10;")
+}
+
+fn color(color: &str) -> &str {
+    if color == "transparent" {
+        "none"
+    } else {
+        color
+    }
+}
+
+/// The draw.io style of an element
+fn element_style(element: &Element, names: &[&str]) -> String {
+    let base = &element.base;
+    let mut style = names.iter().fold(String::new(), |mut style, name| {
+        style.push_str(name);
+        style.push(';');
+        style
+    });
+    let (stroked, filled) = match element.data {
+        ElementData::Text(_) => (false, false),
+        ElementData::Line(_) | ElementData::Arrow(_) => (true, false),
+        _ => (true, true),
+    };
+    if stroked {
+        style.push_str(&format!(
+            "strokeColor={};strokeWidth={};",
+            color(&base.stroke_color),
+            base.stroke_width
+        ));
+        match base.stroke_style {
+            StrokeStyle::Solid => {}
+            StrokeStyle::Dashed => style.push_str("dashed=1;"),
+            StrokeStyle::Dotted => style.push_str("dashed=1;dashPattern=1 4;"),
+        }
+    }
+    if filled {
+        style.push_str(&format!("fillColor={};", color(&base.background_color)));
+    }
+    if base.opacity < 100.0 {
+        style.push_str(&format!("opacity={};", base.opacity));
+    }
+    if base.angle != 0.0 {
+        style.push_str(&format!("rotation={};", base.angle.to_degrees()));
+    }
+    if stroked && base.roughness > 0.0 {
+        style.push_str("sketch=1;");
+        let fill_style = match base.fill_style {
+            FillStyle::Solid => None,
+            FillStyle::Hachure => Some("hachure"),
+            FillStyle::CrossHatch => Some("cross-hatch"),
+            FillStyle::Zigzag => Some("zigzag"),
+        };
+        if let Some(fill_style) = fill_style.filter(|_| filled) {
+            style.push_str(&format!("fillStyle={fill_style};"));
+        }
+    }
+    style
+}
+
+/// The draw.io style of the font of a text
+fn text_style(text: &Text, color: &str) -> String {
+    let align = match text.text_align {
+        TextAlign::Left => "left",
+        TextAlign::Center => "center",
+        TextAlign::Right => "right",
+    };
+    let vertical_align = match text.vertical_align {
+        VerticalAlign::Top => "top",
+        VerticalAlign::Middle => "middle",
+        VerticalAlign::Bottom => "bottom",
+    };
+    format!(
+        "fontSize={};fontFamily={};fontColor={};align={align};verticalAlign={vertical_align};",
+        text.font_size,
+        font_family_name(text.font_family),
+        self::color(color)
+    )
+}
+
+fn mx_point(point: Point, as_attr: Option<&str>) -> String {
+    let as_attr = as_attr.map_or_else(String::new, |a| format!(" as=\"{a}\""));
+    format!("<mxPoint x=\"{}\" y=\"{}\"{as_attr}/>", point[0], point[1])
+}
+
+struct ToDrawio<'a> {
+    /// Ids of the shapes that are converted, which arrows can connect to
+    shapes: HashSet<&'a str>,
+    /// Text bound to the shapes and arrows that are converted, by container
+    labels: HashMap<&'a str, (&'a Element, &'a Text)>,
+    cells: String,
+}
+
+impl ToDrawio<'_> {
+    /// The value of the cell, and the style of its font
+    fn label(&self, id: &str) -> (String, String) {
+        self.labels
+            .get(id)
+            .map_or_else(Default::default, |(element, text)| {
+                let value = if text.original_text.is_empty() {
+                    &text.text
+                } else {
+                    &text.original_text
+                };
+                (
+                    escape_attr(value),
+                    text_style(text, &element.base.stroke_color),
+                )
+            })
+    }
+
+    fn vertex(&mut self, element: &Element, value: &str, style: &str) {
+        let base = &element.base;
+        self.cells.push_str(&format!(
+            "<mxCell id=\"{}\" value=\"{value}\" style=\"{}\" vertex=\"1\" parent=\"1\">\
+             <mxGeometry x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" as=\"geometry\"/></mxCell>",
+            escape_attr(&element.id),
+            escape_attr(style),
+            base.x,
+            base.y,
+            base.width,
+            base.height
+        ));
+    }
+
+    fn shape(&mut self, element: &Element, names: &[&str]) {
+        let (value, text_style) = self.label(&element.id);
+        let style = element_style(element, names) + &text_style;
+        self.vertex(element, &value, &style);
+    }
+
+    fn edge(&mut self, element: &Element, linear: &Linear, arrow: bool) {
+        let (value, text_style) = self.label(&element.id);
+        let mut style = element_style(element, &["edgeStyle=none"]);
+        let heads = if arrow {
+            [linear.start_arrowhead, linear.end_arrowhead]
+        } else {
+            [None, None]
+        };
+        for (head, (arrow_key, fill_key)) in heads
+            .into_iter()
+            .zip([("startArrow", "startFill"), ("endArrow", "endFill")])
+        {
+            let (marker, filled) = marker(head);
+            style.push_str(&format!(
+                "{arrow_key}={marker};{fill_key}={};",
+                u8::from(filled)
+            ));
+        }
+        style.push_str(&text_style);
+
+        let mut ends = String::new();
+        for (binding, key) in [
+            (&linear.start_binding, "source"),
+            (&linear.end_binding, "target"),
+        ] {
+            let bound = binding
+                .as_ref()
+                .filter(|b| self.shapes.contains(b.element_id.as_str()));
+            if let Some(binding) = bound {
+                ends.push_str(&format!(" {key}=\"{}\"", escape_attr(&binding.element_id)));
+            }
+        }
+
+        let absolute = |[x, y]: Point| [element.base.x + x, element.base.y + y];
+        let mut geometry = String::new();
+        let ends_points = [
+            (linear.points.first(), "sourcePoint"),
+            (linear.points.last(), "targetPoint"),
+        ];
+        for (point, key) in ends_points {
+            if let Some(point) = point {
+                geometry.push_str(&mx_point(absolute(*point), Some(key)));
+            }
+        }
+        if linear.points.len() > 2 {
+            let waypoints = linear.points[1..linear.points.len() - 1]
+                .iter()
+                .map(|p| mx_point(absolute(*p), None))
+                .collect::<String>();
+            geometry.push_str(&format!("<Array as=\"points\">{waypoints}</Array>"));
+        }
+        self.cells.push_str(&format!(
+            "<mxCell id=\"{}\" value=\"{value}\" style=\"{}\" edge=\"1\" parent=\"1\"{ends}>\
+             <mxGeometry relative=\"1\" as=\"geometry\">{geometry}</mxGeometry></mxCell>",
+            escape_attr(&element.id),
+            escape_attr(&style)
+        ));
+    }
+}
+
+/// Converts the elements of a scene into the cells of a draw.io file
+fn to_drawio(scene: &Scene, unmapped: &mut Unmapped) -> String {
+    let shapes = scene
+        .live_elements()
+        .filter(|e| {
+            matches!(
+                e.data,
+                ElementData::Rectangle | ElementData::Ellipse | ElementData::Diamond
+            )
+        })
+        .map(|e| e.id.as_str())
+        .collect::<HashSet<_>>();
+    let labels = scene
+        .live_elements()
+        .filter_map(|e| match &e.data {
+            ElementData::Text(text) => {
+                let container = text.container_id.as_deref()?;
+                let converted = scene.element(container).is_some_and(|c| {
+                    shapes.contains(container)
+                        || matches!(c.data, ElementData::Arrow(_) | ElementData::Line(_))
+                });
+                converted.then_some((container, (e, text)))
+            }
+            _ => None,
+        })
+        .collect::<HashMap<_, _>>();
+    let mut converter = ToDrawio {
+        shapes,
+        labels,
+        cells: String::new(),
+    };
+
+    let mut grouped = false;
+    for element in scene.live_elements() {
+        grouped |= !element.base.group_ids.is_empty();
+        let rounded = if element.base.roundness.is_some() {
+            "rounded=1"
+        } else {
+            "rounded=0"
+        };
+        match &element.data {
+            ElementData::Rectangle => converter.shape(element, &[rounded, "whiteSpace=wrap"]),
+            ElementData::Ellipse => converter.shape(element, &["ellipse", "whiteSpace=wrap"]),
+            ElementData::Diamond => converter.shape(element, &["rhombus", "whiteSpace=wrap"]),
+            ElementData::Text(text) => {
+                let is_label = text
+                    .container_id
+                    .as_deref()
+                    .is_some_and(|c| converter.labels.contains_key(c));
+                if !is_label {
+                    let style =
+                        element_style(element, &["text", "strokeColor=none", "fillColor=none"])
+                            + &text_style(text, &element.base.stroke_color);
+                    converter.vertex(element, &escape_attr(&text.text), &style);
+                }
+            }
+            ElementData::Arrow(linear) => converter.edge(element, linear, true),
+            ElementData::Line(linear) => converter.edge(element, linear, false),
+            data => unmapped.add(
+                format!("excalidraw `{}` elements", data.kind()),
+                &element.id,
+            ),
+        }
+    }
+    if grouped {
+        warn!("Groups are not converted, their elements are converted on their own");
+    }
+
+    let background = scene
+        .app_state
+        .as_ref()
+        .and_then(|a| a.view_background_color.as_deref())
+        .map_or_else(String::new, |b| {
+            format!(" background=\"{}\"", escape_attr(b))
+        });
+    format!(
+        "<mxfile host=\"hdiag\"><diagram id=\"page-1\" name=\"Page-1\">\
+         <mxGraphModel{background}><root><mxCell id=\"0\"/><mxCell id=\"1\" parent=\"0\"/>\
+         {}</root></mxGraphModel></diagram></mxfile>\n",
+        converter.cells
+    )
+}
@@ -0,0 +1,67 @@
+use std::{fmt::Write as _, fs, path::Path};
+
+use color_eyre::{
+    eyre::{bail, WrapErr},
+    Result,
+};
+use quick_xml::events::Event;
+
+use crate::{backend, cli::FileType};
+
+/// Name of the root element of an XML document
+pub fn xml_root(contents: &[u8]) -> Result<String> {
+    let mut reader = quick_xml::Reader::from_reader(contents);
+    loop {
+        match reader.read_event().wrap_err("not XML")? {
+            Event::Start(e) | Event::Empty(e) => {
+                return Ok(String::from_utf8_lossy(e.local_name().as_ref()).into_owned())
+            }
+            Event::Eof => bail!("XML has no root element"),
+            _ => {}
+        }
+    }
+}
+
+/// Detects the type of a file from its contents, with an error listing every
+/// format that was tried when none match
+fn from_contents(contents: &[u8]) -> Result<FileType> {
+    let mut tried = String::new();
+    for backend in backend::BACKENDS {
+        match backend.detect(contents) {
+            Ok(()) => return Ok(FileType(backend)),
+            Err(e) => {
+                let reason = e.chain().map(ToString::to_string).collect::<Vec<_>>();
+                write!(tried, "\n  {}: {}", backend.name(), reason.join(": "))
+                    .wrap_err("Failed writing detection error")?;
+            }
+        }
+    }
+    bail!("Contents match no known format, tried:{tried}\nSet the type with `-t`")
+}
+
+/// Finds the format with a suffix that ends the file name, and that suffix.
+/// The longest suffix wins, so `a.drawio.svg` is not just an svg
+pub fn from_name(name: &str) -> Option<(FileType, &'static str)> {
+    backend::BACKENDS
+        .iter()
+        .flat_map(|b| b.suffixes().iter().map(move |s| (FileType(*b), *s)))
+        .filter(|(_, suffix)| name.ends_with(suffix))
+        .max_by_key(|(_, suffix)| suffix.len())
+}
+
+/// Detects the type of a file, first from its extension, and then from its
+/// contents
+pub fn file_type(input_file: &Path) -> Result<FileType> {
+    let name = input_file
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    if let Some((file_type, _)) = from_name(&name) {
+        return Ok(file_type);
+    }
+
+    let contents = fs::read(input_file)
+        .wrap_err_with(|| format!("Failed to read {}", input_file.display()))?;
+    from_contents(&contents)
+        .wrap_err_with(|| format!("Could not detect the type of {}", input_file.display()))
+}
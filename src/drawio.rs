@@ -0,0 +1,566 @@
+//! Reads the diagrams of draw.io files
+
+use std::{collections::HashMap, fmt::Write as _, io::Read as _};
+
+use base64::prelude::*;
+use color_eyre::{
+    eyre::{bail, ContextCompat, WrapErr},
+    Result,
+};
+use flate2::read::DeflateDecoder;
+use percent_encoding::percent_decode_str;
+use quick_xml::events::{BytesStart, Event};
+
+use crate::cli;
+
+pub use crate::geometry::Point;
+
+/// An element of an XML document, with only the parts draw.io uses
+#[derive(Clone, Debug, Default)]
+struct Node {
+    name: String,
+    attrs: Vec<(String, String)>,
+    children: Vec<Node>,
+    text: String,
+}
+
+impl Node {
+    fn from_start(start: &BytesStart) -> Result<Self> {
+        let attrs = start
+            .attributes()
+            .map(|attr| {
+                let attr = attr.wrap_err("Invalid XML attribute")?;
+                let key = String::from_utf8_lossy(attr.key.as_ref()).into_owned();
+                let value = attr
+                    .unescape_value()
+                    .wrap_err("Invalid XML attribute value")?
+                    .into_owned();
+                Ok((key, value))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            name: String::from_utf8_lossy(start.name().as_ref()).into_owned(),
+            attrs,
+            ..Self::default()
+        })
+    }
+
+    /// Parses the document, returning its root element
+    fn parse(xml: &str) -> Result<Self> {
+        let mut reader = quick_xml::Reader::from_str(xml);
+        let mut open: Vec<Self> = vec![];
+        let mut root = None;
+        let mut close = |node: Self, open: &mut Vec<Self>| match open.last_mut() {
+            Some(parent) => parent.children.push(node),
+            None => root = root.take().or(Some(node)),
+        };
+        loop {
+            match reader.read_event().wrap_err("Invalid XML")? {
+                Event::Start(start) => open.push(Self::from_start(&start)?),
+                Event::Empty(start) => close(Self::from_start(&start)?, &mut open),
+                Event::End(_) => {
+                    let node = open
+                        .pop()
+                        .context("XML closes an element it never opened")?;
+                    close(node, &mut open);
+                }
+                Event::Text(text) => {
+                    if let Some(node) = open.last_mut() {
+                        node.text
+                            .push_str(&text.unescape().wrap_err("Invalid XML text")?);
+                    }
+                }
+                Event::CData(data) => {
+                    if let Some(node) = open.last_mut() {
+                        node.text
+                            .push_str(&String::from_utf8_lossy(&data.into_inner()));
+                    }
+                }
+                Event::Eof => break,
+                _ => {}
+            }
+        }
+        root.context("XML has no root element")
+    }
+
+    fn attr(&self, name: &str) -> Option<&str> {
+        self.attrs
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn number(&self, name: &str) -> f64 {
+        self.attr(name)
+            .and_then(|n| n.trim().parse().ok())
+            .unwrap_or(0.0)
+    }
+
+    fn child(&self, name: &str) -> Option<&Self> {
+        self.children.iter().find(|c| c.name == name)
+    }
+}
+
+/// The style of a cell, like `ellipse;whiteSpace=wrap;fillColor=#dae8fc;`
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Style {
+    /// Named styles from the stylesheet, like `ellipse` or `text`
+    pub names: Vec<String>,
+    values: HashMap<String, String>,
+}
+
+impl Style {
+    pub fn parse(style: &str) -> Self {
+        let mut parsed = Self::default();
+        for part in style.split(';').map(str::trim).filter(|p| !p.is_empty()) {
+            match part.split_once('=') {
+                Some((key, value)) => {
+                    parsed.values.insert(key.to_owned(), value.to_owned());
+                }
+                None => parsed.names.push(part.to_owned()),
+            }
+        }
+        parsed
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.values.get(key).map(String::as_str)
+    }
+
+    pub fn number(&self, key: &str, default: f64) -> f64 {
+        self.get(key)
+            .and_then(|n| n.parse().ok())
+            .unwrap_or(default)
+    }
+
+    pub fn flag(&self, key: &str) -> bool {
+        self.get(key) == Some("1")
+    }
+
+    pub fn has_name(&self, name: &str) -> bool {
+        self.names.iter().any(|n| n == name)
+    }
+
+    /// The shape a vertex is drawn as
+    pub fn shape(&self) -> &str {
+        // Named styles in the default stylesheet that set a shape
+        const SHAPE_STYLES: [&str; 7] = [
+            "ellipse", "rhombus", "triangle", "swimlane", "image", "line", "text",
+        ];
+        self.get("shape")
+            .or_else(|| {
+                self.names
+                    .iter()
+                    .map(String::as_str)
+                    .find(|n| SHAPE_STYLES.contains(n))
+            })
+            .unwrap_or("rectangle")
+    }
+
+    /// A color of the cell, `None` when it isn't painted
+    pub fn color(&self, key: &str, default: Option<&str>) -> Option<String> {
+        match self.get(key) {
+            Some("none") => None,
+            Some("default") | None => default.map(ToOwned::to_owned),
+            Some(color) => Some(color.to_owned()),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CellKind {
+    Vertex,
+    Edge,
+    /// The root and the layers, which only hold other cells
+    Container,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Geometry {
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
+    /// For labels on edges, `x` is where along the edge it is, from -1 to 1
+    pub relative: bool,
+    /// Waypoints of an edge
+    pub points: Vec<Point>,
+    /// Ends of an edge that is not connected to a cell
+    pub source_point: Option<Point>,
+    pub target_point: Option<Point>,
+    pub offset: Option<Point>,
+}
+
+impl Geometry {
+    fn from_node(node: &Node) -> Self {
+        let point = |node: &Node| [node.number("x"), node.number("y")];
+        let mut geometry = Self {
+            x: node.number("x"),
+            y: node.number("y"),
+            width: node.number("width"),
+            height: node.number("height"),
+            relative: node.attr("relative") == Some("1"),
+            ..Self::default()
+        };
+        for child in &node.children {
+            match (child.name.as_str(), child.attr("as")) {
+                ("mxPoint", Some("sourcePoint")) => geometry.source_point = Some(point(child)),
+                ("mxPoint", Some("targetPoint")) => geometry.target_point = Some(point(child)),
+                ("mxPoint", Some("offset")) => geometry.offset = Some(point(child)),
+                ("Array", Some("points")) => {
+                    geometry.points = child.children.iter().map(point).collect();
+                }
+                _ => {}
+            }
+        }
+        geometry
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Cell {
+    pub id: String,
+    pub parent: Option<String>,
+    /// The label, which is HTML when the style has `html=1`
+    pub value: String,
+    pub style: Style,
+    pub kind: CellKind,
+    pub source: Option<String>,
+    pub target: Option<String>,
+    pub visible: bool,
+    pub geometry: Option<Geometry>,
+}
+
+impl Cell {
+    /// Reads an `<mxCell>`, or an `<object>` or `<UserObject>` wrapping one
+    /// to add properties to it
+    fn from_node(node: &Node) -> Result<Self> {
+        let (cell, id, value) = if node.name == "mxCell" {
+            (node, node.attr("id"), node.attr("value"))
+        } else {
+            let cell = node
+                .child("mxCell")
+                .with_context(|| format!("<{}> has no <mxCell>", node.name))?;
+            (cell, node.attr("id"), node.attr("label"))
+        };
+        let kind = if cell.attr("edge") == Some("1") {
+            CellKind::Edge
+        } else if cell.attr("vertex") == Some("1") {
+            CellKind::Vertex
+        } else {
+            CellKind::Container
+        };
+        Ok(Self {
+            id: id.context("Cell has no id")?.to_owned(),
+            parent: cell.attr("parent").map(ToOwned::to_owned),
+            value: value.unwrap_or_default().to_owned(),
+            style: Style::parse(cell.attr("style").unwrap_or_default()),
+            kind,
+            source: cell.attr("source").map(ToOwned::to_owned),
+            target: cell.attr("target").map(ToOwned::to_owned),
+            visible: cell.attr("visible") != Some("0"),
+            geometry: cell.child("mxGeometry").map(Geometry::from_node),
+        })
+    }
+
+    /// The label as plain text
+    pub fn label(&self) -> String {
+        if self.style.flag("html") {
+            html_to_text(&self.value)
+        } else {
+            self.value.clone()
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct GraphModel {
+    pub background: Option<String>,
+    /// Every cell, in the order they are in the file
+    pub cells: Vec<Cell>,
+}
+
+impl GraphModel {
+    fn from_node(node: &Node) -> Result<Self> {
+        if node.name != "mxGraphModel" {
+            bail!("Expected an <mxGraphModel>, found <{}>", node.name);
+        }
+        let cells = node
+            .child("root")
+            .map(|root| root.children.iter().map(Cell::from_node).collect())
+            .transpose()
+            .wrap_err("Failed reading cells of diagram")?
+            .unwrap_or_default();
+        Ok(Self {
+            background: node
+                .attr("background")
+                .filter(|b| *b != "none")
+                .map(ToOwned::to_owned),
+            cells,
+        })
+    }
+}
+
+/// A page of a draw.io file
+#[derive(Clone, Debug, PartialEq)]
+pub struct Page {
+    pub name: String,
+    pub model: GraphModel,
+}
+
+/// Decompresses the contents of a `<diagram>`, which draw.io saves as base64
+/// of the deflated, URL encoded XML
+fn decompress_diagram(data: &str) -> Result<String> {
+    let data = data
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect::<String>();
+    let deflated = BASE64_STANDARD
+        .decode(data)
+        .wrap_err("Compressed diagram is not valid base64")?;
+    let mut url_encoded = String::new();
+    DeflateDecoder::new(deflated.as_slice())
+        .read_to_string(&mut url_encoded)
+        .wrap_err("Failed inflating compressed diagram")?;
+    let xml = percent_decode_str(&url_encoded)
+        .decode_utf8()
+        .wrap_err("Compressed diagram is not valid UTF-8")?;
+    Ok(xml.into_owned())
+}
+
+fn page_from_diagram(diagram: &Node, index: usize) -> Result<Page> {
+    let name = diagram
+        .attr("name")
+        .map_or_else(|| format!("Page-{}", index + 1), ToOwned::to_owned);
+    let model = match diagram.child("mxGraphModel") {
+        Some(model) => GraphModel::from_node(model),
+        None if diagram.text.trim().is_empty() => Ok(GraphModel {
+            background: None,
+            cells: vec![],
+        }),
+        None => {
+            let xml = if diagram.text.trim_start().starts_with('<') {
+                diagram.text.clone()
+            } else {
+                decompress_diagram(&diagram.text)?
+            };
+            let node = Node::parse(&xml).wrap_err("Compressed diagram is not valid XML")?;
+            GraphModel::from_node(&node)
+        }
+    }
+    .wrap_err_with(|| format!("Failed reading page {name}"))?;
+    Ok(Page { name, model })
+}
+
+/// Reads the pages of a draw.io file, or of a lone `<mxGraphModel>`
+pub fn pages(contents: &[u8]) -> Result<Vec<Page>> {
+    let xml = std::str::from_utf8(contents).wrap_err("Draw.io file is not valid UTF-8")?;
+    let root = Node::parse(xml).wrap_err("Draw.io file is not valid XML")?;
+    match root.name.as_str() {
+        "mxfile" => root
+            .children
+            .iter()
+            .filter(|c| c.name == "diagram")
+            .enumerate()
+            .map(|(i, diagram)| page_from_diagram(diagram, i))
+            .collect(),
+        "mxGraphModel" => Ok(vec![Page {
+            name: "Page-1".to_owned(),
+            model: GraphModel::from_node(&root)?,
+        }]),
+        other => bail!("Expected a draw.io <mxfile>, found <{other}>"),
+    }
+}
+
+/// Picks the pages to export, from their name or their position starting
+/// at 1
+pub fn select_pages<'a>(pages: &'a [Page], selection: &cli::Pages) -> Result<Vec<&'a Page>> {
+    if pages.is_empty() {
+        bail!("Draw.io file has no pages");
+    }
+    let name = match selection {
+        cli::Pages::First => return Ok(vec![&pages[0]]),
+        cli::Pages::All => return Ok(pages.iter().collect()),
+        cli::Pages::One(name) => name,
+    };
+
+    let by_name = pages.iter().find(|p| p.name == *name);
+    let by_index = || {
+        name.parse::<usize>()
+            .ok()
+            .and_then(|i| i.checked_sub(1))
+            .and_then(|i| pages.get(i))
+    };
+    if let Some(page) = by_name.or_else(by_index) {
+        return Ok(vec![page]);
+    }
+    let names = pages
+        .iter()
+        .enumerate()
+        .try_fold(String::new(), |mut names, (i, page)| {
+            write!(names, "\n    {}: {}", i + 1, page.name)
+                .wrap_err("Failed writing page name to string")?;
+            Ok::<String, color_eyre::Report>(names)
+        })?;
+    bail!("Draw.io file has no page `{name}`, its pages are:{names}")
+}
+
+/// Turns an HTML label into plain text lines
+pub fn html_to_text(html: &str) -> String {
+    const LINE_BREAKS: [&str; 5] = ["br", "div", "p", "li", "tr"];
+
+    let mut text = String::with_capacity(html.len());
+    let mut rest = html;
+    while let Some(start) = rest.find('<') {
+        text.push_str(&rest[..start]);
+        let Some(end) = rest[start..].find('>') else {
+            rest = &rest[start..];
+            break;
+        };
+        let tag = rest[start + 1..start + end].trim_start_matches('/');
+        let name = tag
+            .split(|c: char| c.is_whitespace() || c == '/')
+            .next()
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        // A block opening right at the start of the label is no new line
+        if LINE_BREAKS.contains(&name.as_str()) && !text.is_empty() && !text.ends_with('\n') {
+            text.push('\n');
+        }
+        rest = &rest[start + end + 1..];
+    }
+    text.push_str(rest);
+    decode_entities(&text)
+}
+
+fn decode_entities(text: &str) -> String {
+    let mut decoded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let entity = rest
+            .find(';')
+            .filter(|end| *end <= 10)
+            .map(|end| (&rest[1..end], end));
+        let Some((name, end)) = entity else {
+            decoded.push('&');
+            rest = &rest[1..];
+            continue;
+        };
+        let c = match name {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            "nbsp" => Some(' '),
+            _ => name
+                .strip_prefix("#x")
+                .map(|hex| u32::from_str_radix(hex, 16))
+                .or_else(|| name.strip_prefix('#').map(str::parse))
+                .and_then(Result::ok)
+                .and_then(char::from_u32),
+        };
+        if let Some(c) = c {
+            decoded.push(c);
+            rest = &rest[end + 1..];
+        } else {
+            decoded.push('&');
+            rest = &rest[1..];
+        }
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write as _;
+
+    use flate2::{write::DeflateEncoder, Compression};
+    use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+
+    use super::*;
+
+    const MODEL: &str = r#"<mxGraphModel><root><mxCell id="0"/><mxCell id="1" parent="0"/><mxCell id="2" value="Ünïcode &amp; co" vertex="1" parent="1"><mxGeometry x="10" y="20" width="120" height="60" as="geometry"/></mxCell></root></mxGraphModel>"#;
+
+    /// Compresses the XML of a diagram the way draw.io saves it
+    fn compress(xml: &str) -> Result<String> {
+        let url_encoded = utf8_percent_encode(xml, NON_ALPHANUMERIC).to_string();
+        let mut encoder = DeflateEncoder::new(vec![], Compression::default());
+        encoder.write_all(url_encoded.as_bytes())?;
+        Ok(BASE64_STANDARD.encode(encoder.finish()?))
+    }
+
+    #[test]
+    fn decompress_diagram_reverses_compression() -> Result<()> {
+        let compressed = compress(MODEL)?;
+        assert_eq!(decompress_diagram(&compressed)?, MODEL);
+
+        // Files wrap the base64 across lines
+        let (start, end) = compressed.split_at(compressed.len() / 2);
+        let wrapped = format!("\n    {start}\n    {end}\n  ");
+        assert_eq!(decompress_diagram(&wrapped)?, MODEL);
+        Ok(())
+    }
+
+    #[test]
+    fn decompress_diagram_rejects_invalid_data() {
+        assert!(decompress_diagram("not base64!").is_err());
+        // Valid base64 of bytes that are no deflate stream
+        assert!(decompress_diagram(&BASE64_STANDARD.encode([0xff; 8])).is_err());
+    }
+
+    #[test]
+    fn pages_reads_compressed_and_plain_diagrams() -> Result<()> {
+        let file = format!(
+            r#"<mxfile><diagram name="Compressed">{}</diagram><diagram>{MODEL}</diagram></mxfile>"#,
+            compress(MODEL)?
+        );
+        let pages = pages(file.as_bytes())?;
+        let names = pages.iter().map(|p| p.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, ["Compressed", "Page-2"]);
+        assert_eq!(pages[0].model, pages[1].model);
+
+        let cell = &pages[0].model.cells[2];
+        assert_eq!(cell.kind, CellKind::Vertex);
+        assert_eq!(cell.value, "Ünïcode & co");
+        assert_eq!(
+            cell.geometry
+                .as_ref()
+                .map(|g| [g.x, g.y, g.width, g.height]),
+            Some([10.0, 20.0, 120.0, 60.0])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn select_pages_by_name_or_position() -> Result<()> {
+        let pages = pages(
+            br#"<mxfile><diagram name="One"/><diagram name="2"/><diagram name="Three"/></mxfile>"#,
+        )?;
+        let select = |selection: &str| {
+            select_pages(&pages, &cli::Pages::One(selection.to_owned()))
+                .map(|pages| pages.iter().map(|p| p.name.clone()).collect::<Vec<_>>())
+        };
+        assert_eq!(select("Three")?, ["Three"]);
+        assert_eq!(select("3")?, ["Three"]);
+        // A name wins over a position
+        assert_eq!(select("2")?, ["2"]);
+        assert!(select("4").is_err());
+        assert!(select("0").is_err());
+        assert_eq!(select_pages(&pages, &cli::Pages::All)?.len(), 3);
+        Ok(())
+    }
+
+    #[test]
+    fn html_to_text_breaks_lines_and_decodes_entities() {
+        assert_eq!(
+            html_to_text(
+                "<div>First</div><div>Second &amp; <b>bold</b></div>a&lt;b&#33;&#x3F; &unknown; &"
+            ),
+            "First\nSecond & bold\na<b!? &unknown; &"
+        );
+    }
+}
@@ -0,0 +1,1124 @@
+//! A small text format for boxes-and-arrows diagrams, compiled into an
+//! excalidraw scene with an automatic layout.
+//!
+//! ```text
+//! # Comments start with a hash
+//! direction right
+//!
+//! web "Web app"
+//! db "Database" ellipse
+//!
+//! group backend "Backend" {
+//!   api "API" round
+//!   worker
+//! }
+//!
+//! web -> api "REST"
+//! api -> db
+//! api -- worker
+//! ```
+//!
+//! Nodes are declared with an id, an optional label and an optional shape
+//! (`box`, `round`, `ellipse` or `diamond`), or just by using them in an
+//! edge. Edges are `->`, `<-`, `<->` or `--` for no arrowhead, and can be
+//! chained like `a -> b -> c`, with an optional label at the end.
+
+use std::collections::HashMap;
+
+use color_eyre::{
+    eyre::{bail, eyre, WrapErr},
+    Result,
+};
+use hdiag::scene::{
+    Arrowhead, Binding, BoundElement, Element, ElementData, Linear, Roundness, Scene, StrokeStyle,
+    Text, TextAlign, VerticalAlign,
+};
+use serde_json::Map;
+use tokio::runtime::Runtime;
+
+use crate::{
+    backend::{DiagramBackend, Supports},
+    cli, excalidraw,
+    geometry::{Perimeter, Rect},
+    Output,
+};
+
+const FONT_SIZE: f64 = 20.0;
+const LINE_HEIGHT: f64 = 1.25;
+/// Rough width of a character of Virgil relative to the font size, to size
+/// the nodes without measuring the text
+const CHAR_WIDTH: f64 = 0.6;
+const NODE_MIN_WIDTH: f64 = 120.0;
+const NODE_MIN_HEIGHT: f64 = 60.0;
+const NODE_PADDING: f64 = 20.0;
+/// Space between nodes in the same rank
+const NODE_GAP: f64 = 40.0;
+/// Space between ranks
+const RANK_GAP: f64 = 80.0;
+const GROUP_PADDING: f64 = 20.0;
+/// Space at the top of a group for its label
+const GROUP_LABEL_SIZE: f64 = 30.0;
+/// Space between the ends of an arrow and the nodes it connects
+const ARROW_GAP: f64 = 4.0;
+/// Rounds of reordering the nodes of every rank to uncross the edges
+const ORDERING_SWEEPS: usize = 4;
+const STROKE_COLOR: &str = "#1e1e1e";
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+enum Direction {
+    Right,
+    #[default]
+    Down,
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+enum Shape {
+    #[default]
+    Box,
+    Round,
+    Ellipse,
+    Diamond,
+}
+
+impl Shape {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "box" => Some(Self::Box),
+            "round" => Some(Self::Round),
+            "ellipse" | "circle" => Some(Self::Ellipse),
+            "diamond" => Some(Self::Diamond),
+            _ => None,
+        }
+    }
+
+    const fn perimeter(self) -> Perimeter {
+        match self {
+            Self::Box | Self::Round => Perimeter::Rectangle,
+            Self::Ellipse => Perimeter::Ellipse,
+            Self::Diamond => Perimeter::Rhombus,
+        }
+    }
+}
+
+/// Which ends of an edge have an arrowhead
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum EdgeKind {
+    Forward,
+    Backward,
+    Both,
+    Line,
+}
+
+#[derive(Clone, Debug)]
+struct Node {
+    id: String,
+    label: String,
+    shape: Shape,
+    /// Index of the innermost group the node is in
+    group: Option<usize>,
+    /// Set once the node has its own line, not just used in edges
+    declared: bool,
+}
+
+#[derive(Clone, Debug)]
+struct Edge {
+    from: usize,
+    to: usize,
+    kind: EdgeKind,
+    label: Option<String>,
+}
+
+#[derive(Clone, Debug)]
+struct Group {
+    id: String,
+    label: String,
+    parent: Option<usize>,
+}
+
+#[derive(Clone, Debug, Default)]
+struct Diagram {
+    direction: Direction,
+    nodes: Vec<Node>,
+    edges: Vec<Edge>,
+    groups: Vec<Group>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Token {
+    Word(String),
+    Quoted(String),
+    Edge(EdgeKind),
+    Open,
+    Close,
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || matches!(c, '_' | '.' | '-')
+}
+
+fn tokenize(line: &str) -> Result<Vec<Token>> {
+    const EDGES: [(&str, EdgeKind); 4] = [
+        ("<->", EdgeKind::Both),
+        ("->", EdgeKind::Forward),
+        ("<-", EdgeKind::Backward),
+        ("--", EdgeKind::Line),
+    ];
+    let mut tokens = vec![];
+    let mut rest = line.trim_start();
+    while let Some(c) = rest.chars().next() {
+        if let Some((op, kind)) = EDGES.iter().find(|(op, _)| rest.starts_with(op)) {
+            tokens.push(Token::Edge(*kind));
+            rest = &rest[op.len()..];
+        } else if c == '#' {
+            break;
+        } else if c == '{' || c == '}' {
+            tokens.push(if c == '{' { Token::Open } else { Token::Close });
+            rest = &rest[1..];
+        } else if c == '"' {
+            let (text, after) = quoted(&rest[1..])?;
+            tokens.push(Token::Quoted(text));
+            rest = after;
+        } else if is_word_char(c) {
+            // Words can have dashes, but not the start of an edge
+            let end = rest
+                .char_indices()
+                .find(|&(i, c)| {
+                    !is_word_char(c) || rest[i..].starts_with("->") || rest[i..].starts_with("--")
+                })
+                .map_or(rest.len(), |(i, _)| i);
+            tokens.push(Token::Word(rest[..end].to_owned()));
+            rest = &rest[end..];
+        } else {
+            bail!("unexpected character `{c}`");
+        }
+        rest = rest.trim_start();
+    }
+    Ok(tokens)
+}
+
+/// Reads a quoted string up to its closing quote, returning it and what
+/// follows it
+fn quoted(text: &str) -> Result<(String, &str)> {
+    let mut unquoted = String::new();
+    let mut chars = text.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return Ok((unquoted, &text[i + 1..])),
+            '\\' => match chars.next() {
+                Some((_, 'n')) => unquoted.push('\n'),
+                Some((_, c)) => unquoted.push(c),
+                None => break,
+            },
+            c => unquoted.push(c),
+        }
+    }
+    bail!("quote is never closed")
+}
+
+impl Diagram {
+    /// The node with the id, added to the group if it is new
+    fn node(&mut self, id: &str, group: Option<usize>) -> usize {
+        if let Some(index) = self.nodes.iter().position(|n| n.id == id) {
+            return index;
+        }
+        self.nodes.push(Node {
+            id: id.to_owned(),
+            label: id.to_owned(),
+            shape: Shape::default(),
+            group,
+            declared: false,
+        });
+        self.nodes.len() - 1
+    }
+
+    fn declare_node(&mut self, tokens: &[Token], group: Option<usize>) -> Result<()> {
+        let [Token::Word(id), rest @ ..] = tokens else {
+            bail!("expected a node, an edge, a group or a direction");
+        };
+        let (label, shape) = match rest {
+            [] => (None, None),
+            [Token::Quoted(label)] => (Some(label), None),
+            [Token::Word(shape)] => (None, Some(shape)),
+            [Token::Quoted(label), Token::Word(shape)] => (Some(label), Some(shape)),
+            _ => bail!("expected a node like `id \"Label\" shape`"),
+        };
+        let shape = shape
+            .map(|s| {
+                Shape::parse(s).ok_or_else(|| {
+                    eyre!("unknown shape `{s}`, expected box, round, ellipse or diamond")
+                })
+            })
+            .transpose()?;
+
+        let index = self.node(id, group);
+        let node = &mut self.nodes[index];
+        if node.declared {
+            bail!("node `{id}` is declared twice");
+        }
+        node.declared = true;
+        node.group = group;
+        if let Some(label) = label {
+            label.clone_into(&mut node.label);
+        }
+        node.shape = shape.unwrap_or_default();
+        Ok(())
+    }
+
+    fn add_edges(&mut self, tokens: &[Token], group: Option<usize>) -> Result<()> {
+        let (chain, label) = match tokens {
+            [chain @ .., Token::Quoted(label)] => (chain, Some(label)),
+            chain => (chain, None),
+        };
+        let [Token::Word(first), rest @ ..] = chain else {
+            bail!("expected an edge like `a -> b \"Label\"`");
+        };
+        let mut from = self.node(first, group);
+        for pair in rest.chunks(2) {
+            let [Token::Edge(kind), Token::Word(to)] = pair else {
+                bail!("expected an edge like `a -> b \"Label\"`");
+            };
+            let to = self.node(to, group);
+            if to == from {
+                bail!(
+                    "edges from `{}` to itself are not supported",
+                    self.nodes[to].id
+                );
+            }
+            self.edges.push(Edge {
+                from,
+                to,
+                kind: *kind,
+                label: label.cloned(),
+            });
+            from = to;
+        }
+        Ok(())
+    }
+
+    fn parse(source: &str) -> Result<Self> {
+        let mut diagram = Self::default();
+        let mut open_groups = vec![];
+        for (number, line) in (1..).zip(source.lines()) {
+            let group = open_groups.last().copied();
+            let parsed = tokenize(line).and_then(|tokens| match tokens.as_slice() {
+                [] => Ok(()),
+                [Token::Close] => open_groups
+                    .pop()
+                    .map(|_| ())
+                    .ok_or_else(|| eyre!("`}}` closes no group")),
+                [Token::Word(keyword), Token::Word(direction)] if keyword == "direction" => {
+                    diagram.direction = match direction.as_str() {
+                        "right" => Direction::Right,
+                        "down" => Direction::Down,
+                        _ => bail!("unknown direction `{direction}`, expected right or down"),
+                    };
+                    Ok(())
+                }
+                [Token::Word(keyword), Token::Word(id), rest @ ..] if keyword == "group" => {
+                    let label = match rest {
+                        [Token::Open] => id.clone(),
+                        [Token::Quoted(label), Token::Open] => label.clone(),
+                        _ => bail!("expected a group like `group id \"Label\" {{`"),
+                    };
+                    diagram.groups.push(Group {
+                        id: id.clone(),
+                        label,
+                        parent: group,
+                    });
+                    open_groups.push(diagram.groups.len() - 1);
+                    Ok(())
+                }
+                tokens if tokens.iter().any(|t| matches!(t, Token::Edge(_))) => {
+                    diagram.add_edges(tokens, group)
+                }
+                tokens => diagram.declare_node(tokens, group),
+            });
+            parsed.wrap_err_with(|| format!("Line {number}: {}", line.trim()))?;
+        }
+        if let Some(group) = open_groups.last() {
+            bail!("Group `{}` is never closed", diagram.groups[*group].id);
+        }
+        Ok(diagram)
+    }
+
+    /// Groups the node is in, the outermost first
+    fn group_path(&self, node: usize) -> Vec<usize> {
+        let mut path = vec![];
+        let mut group = self.nodes[node].group;
+        while let Some(index) = group {
+            path.push(index);
+            group = self.groups[index].parent;
+        }
+        path.reverse();
+        path
+    }
+}
+
+#[allow(clippy::cast_precision_loss)]
+const fn count(n: usize) -> f64 {
+    n as f64
+}
+
+/// Size of a node that fits its label
+fn node_size(node: &Node) -> [f64; 2] {
+    let lines = node.label.lines().collect::<Vec<_>>();
+    let longest = lines.iter().map(|l| l.chars().count()).max().unwrap_or(0);
+    let text_width = count(longest) * FONT_SIZE * CHAR_WIDTH;
+    let text_height = count(lines.len().max(1)) * FONT_SIZE * LINE_HEIGHT;
+    let width = NODE_PADDING.mul_add(2.0, text_width).max(NODE_MIN_WIDTH);
+    let height = NODE_PADDING.mul_add(2.0, text_height).max(NODE_MIN_HEIGHT);
+    // The text has to fit in the inscribed rectangle
+    let scale = match node.shape {
+        Shape::Box | Shape::Round => 1.0,
+        Shape::Ellipse => 1.3,
+        Shape::Diamond => 1.6,
+    };
+    [width * scale, height * scale]
+}
+
+/// Ranks of the nodes, so that edges go from a rank to a later one, apart
+/// from the ones that close a cycle
+fn ranks(diagram: &Diagram) -> Vec<usize> {
+    #[derive(Copy, Clone, PartialEq, Eq)]
+    enum State {
+        New,
+        OnPath,
+        Done,
+    }
+
+    let count = diagram.nodes.len();
+    let mut out = vec![vec![]; count];
+    for edge in &diagram.edges {
+        // The diagram flows the way the arrows point
+        if edge.kind == EdgeKind::Backward {
+            out[edge.to].push(edge.from);
+        } else {
+            out[edge.from].push(edge.to);
+        }
+    }
+
+    // Depth first search, keeping the edges that don't go back up the path
+    let mut state = vec![State::New; count];
+    let mut kept = vec![vec![]; count];
+    let mut finished = vec![];
+    for root in 0..count {
+        if state[root] != State::New {
+            continue;
+        }
+        state[root] = State::OnPath;
+        let mut stack = vec![(root, 0)];
+        while let Some((node, next)) = stack.pop() {
+            if let Some(&to) = out[node].get(next) {
+                stack.push((node, next + 1));
+                if state[to] != State::OnPath {
+                    kept[node].push(to);
+                }
+                if state[to] == State::New {
+                    state[to] = State::OnPath;
+                    stack.push((to, 0));
+                }
+            } else {
+                state[node] = State::Done;
+                finished.push(node);
+            }
+        }
+    }
+
+    let mut rank = vec![0; count];
+    for &node in finished.iter().rev() {
+        for &to in &kept[node] {
+            rank[to] = rank[to].max(rank[node] + 1);
+        }
+    }
+    rank
+}
+
+/// Where the node sits among the nodes of its rank, from the positions of
+/// the nodes it is connected to in the rank before
+fn barycenter(diagram: &Diagram, node: usize, neighbor_rank: &HashMap<usize, usize>) -> f64 {
+    let positions = diagram
+        .edges
+        .iter()
+        .filter_map(|e| {
+            let other = if e.from == node {
+                e.to
+            } else if e.to == node {
+                e.from
+            } else {
+                return None;
+            };
+            neighbor_rank.get(&other).copied()
+        })
+        .collect::<Vec<_>>();
+    if positions.is_empty() {
+        return f64::NAN;
+    }
+    count(positions.iter().sum::<usize>()) / count(positions.len())
+}
+
+/// Orders the nodes of every rank to uncross the edges, keeping the nodes
+/// of a group next to each other
+fn order_ranks(diagram: &Diagram, ranks: &mut [Vec<usize>]) {
+    let paths = (0..diagram.nodes.len())
+        .map(|n| diagram.group_path(n))
+        .collect::<Vec<_>>();
+    let rank_count = ranks.len();
+    // Down the ranks following the ones before, then up following the ones after
+    let sweeps = (0..ORDERING_SWEEPS).flat_map(|_| {
+        let down = (1..rank_count).map(|r| (r, r - 1));
+        let up = (0..rank_count.saturating_sub(1)).rev().map(|r| (r, r + 1));
+        down.chain(up)
+    });
+    for (rank, neighbor) in sweeps {
+        let positions = ranks[neighbor]
+            .iter()
+            .enumerate()
+            .map(|(i, n)| (*n, i))
+            .collect::<HashMap<_, _>>();
+        let centers = ranks[rank]
+            .iter()
+            .enumerate()
+            .map(|(i, &n)| {
+                let center = barycenter(diagram, n, &positions);
+                // Nodes with nothing to follow stay where they are
+                (n, if center.is_nan() { count(i) } else { center })
+            })
+            .collect::<HashMap<_, _>>();
+        let group_center = |group: usize| {
+            let members = ranks[rank]
+                .iter()
+                .filter(|n| paths[**n].contains(&group))
+                .map(|n| centers[n])
+                .collect::<Vec<_>>();
+            members.iter().sum::<f64>() / count(members.len())
+        };
+        // Sorts by the groups first, so their nodes stay together
+        let keys = ranks[rank]
+            .iter()
+            .map(|&n| {
+                let mut key = paths[n]
+                    .iter()
+                    .map(|&g| (group_center(g), g))
+                    .collect::<Vec<_>>();
+                key.push((centers[&n], diagram.groups.len() + n));
+                (n, key)
+            })
+            .collect::<HashMap<_, _>>();
+        ranks[rank].sort_by(|a, b| {
+            keys[a]
+                .partial_cmp(&keys[b])
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+    }
+}
+
+/// Boxes of the nodes, and of the groups that have nodes
+struct Layout {
+    nodes: Vec<Rect>,
+    groups: Vec<Option<Rect>>,
+}
+
+/// Places the nodes along the ranks, giving every group a band of its own
+/// across the ranks it spans so that no other node ends up in its box
+struct Bands<'a> {
+    diagram: &'a Diagram,
+    rank: Vec<usize>,
+    /// Position of the nodes among the nodes of their rank
+    order: Vec<usize>,
+    /// Sizes of the nodes along the ranks
+    along: Vec<f64>,
+    /// Space in the bands of groups before their first node, and after
+    /// their last one
+    padding: [f64; 2],
+    positions: Vec<f64>,
+}
+
+impl Bands<'_> {
+    /// Nodes in the group, or in the groups it contains
+    fn members(&self, group: usize) -> Vec<usize> {
+        (0..self.diagram.nodes.len())
+            .filter(|&n| self.diagram.group_path(n).contains(&group))
+            .collect()
+    }
+
+    /// Places the nodes and groups directly in the container from the
+    /// positions in the cursor, which it moves past them
+    fn place(&mut self, container: Option<usize>, cursor: &mut [f64]) {
+        let start = cursor.to_vec();
+        let mut items = vec![];
+        for node in 0..self.diagram.nodes.len() {
+            if self.diagram.nodes[node].group == container {
+                items.push((count(self.order[node]), vec![node], None));
+            }
+        }
+        for group in 0..self.diagram.groups.len() {
+            let members = self.members(group);
+            if self.diagram.groups[group].parent == container && !members.is_empty() {
+                let key = members.iter().map(|&n| count(self.order[n])).sum::<f64>()
+                    / count(members.len());
+                items.push((key, members, Some(group)));
+            }
+        }
+        items.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal));
+
+        // Ranks crossed by a group can't be centered without moving it
+        let mut has_group = vec![false; cursor.len()];
+        for (_, members, group) in items {
+            let Some(group) = group else {
+                let node = members[0];
+                let rank = self.rank[node];
+                self.positions[node] = cursor[rank];
+                cursor[rank] += self.along[node] + NODE_GAP;
+                continue;
+            };
+            let ranks = members.iter().map(|&n| self.rank[n]).collect::<Vec<_>>();
+            let (first, last) = (
+                ranks.iter().copied().min().unwrap_or(0),
+                ranks.iter().copied().max().unwrap_or(0),
+            );
+            let band_start = cursor[first..=last]
+                .iter()
+                .copied()
+                .fold(f64::MIN, f64::max);
+            let mut inner = cursor.to_vec();
+            inner[first..=last].fill(band_start + self.padding[0]);
+            self.place(Some(group), &mut inner);
+            let band_end = inner[first..=last].iter().copied().fold(f64::MIN, f64::max) - NODE_GAP
+                + self.padding[1];
+            cursor[first..=last].fill(band_end + NODE_GAP);
+            has_group[first..=last].fill(true);
+        }
+
+        // Only the ranks the container has something in
+        let end = cursor
+            .iter()
+            .zip(&start)
+            .filter(|(position, start)| position > start)
+            .map(|(position, _)| *position)
+            .fold(f64::MIN, f64::max);
+        for node in 0..self.diagram.nodes.len() {
+            let rank = self.rank[node];
+            if self.diagram.nodes[node].group == container && !has_group[rank] {
+                self.positions[node] += (end - cursor[rank]) / 2.0;
+            }
+        }
+        for (rank, position) in cursor.iter_mut().enumerate() {
+            if *position > start[rank] {
+                *position = end;
+            }
+        }
+    }
+}
+
+fn layout(diagram: &Diagram) -> Layout {
+    let rank = ranks(diagram);
+    let mut ranks = vec![vec![]; rank.iter().max().map_or(0, |r| r + 1)];
+    for (node, &r) in rank.iter().enumerate() {
+        ranks[r].push(node);
+    }
+    order_ranks(diagram, &mut ranks);
+
+    let right = diagram.direction == Direction::Right;
+    let sizes = diagram.nodes.iter().map(node_size).collect::<Vec<_>>();
+    let depth = (0..diagram.nodes.len())
+        .map(|n| diagram.group_path(n).len())
+        .max()
+        .unwrap_or(0);
+    let group_margin = GROUP_PADDING + GROUP_LABEL_SIZE;
+
+    let mut order = vec![0; diagram.nodes.len()];
+    for rank in &ranks {
+        for (i, &node) in rank.iter().enumerate() {
+            order[node] = i;
+        }
+    }
+    let mut bands = Bands {
+        diagram,
+        rank,
+        order,
+        along: sizes
+            .iter()
+            .map(|s| if right { s[1] } else { s[0] })
+            .collect(),
+        // The labels of groups are at their top
+        padding: if right {
+            [group_margin, GROUP_PADDING]
+        } else {
+            [GROUP_PADDING, GROUP_PADDING]
+        },
+        positions: vec![0.0; diagram.nodes.len()],
+    };
+    bands.place(None, &mut vec![0.0; ranks.len()]);
+
+    let mut nodes = vec![Rect::default(); diagram.nodes.len()];
+    let mut offset = 0.0;
+    for rank in &ranks {
+        let across = |n: usize| if right { sizes[n][0] } else { sizes[n][1] };
+        let thickness = rank.iter().map(|&n| across(n)).fold(0.0, f64::max);
+        for &node in rank {
+            // Centered across the rank
+            let start = offset + (thickness - across(node)) / 2.0;
+            let position = bands.positions[node];
+            let [x, y] = if right {
+                [start, position]
+            } else {
+                [position, start]
+            };
+            let [width, height] = sizes[node];
+            nodes[node] = Rect {
+                x,
+                y,
+                width,
+                height,
+            };
+        }
+        offset += thickness + count(depth).mul_add(2.0 * group_margin, RANK_GAP);
+    }
+
+    // Nested groups come after the group they are in, so are sized first
+    let mut groups = vec![None; diagram.groups.len()];
+    for index in (0..diagram.groups.len()).rev() {
+        let members = (0..diagram.nodes.len())
+            .filter(|&n| diagram.nodes[n].group == Some(index))
+            .map(|n| nodes[n])
+            .chain(
+                (0..diagram.groups.len())
+                    .filter(|&g| diagram.groups[g].parent == Some(index))
+                    .filter_map(|g| groups[g]),
+            )
+            .collect::<Vec<Rect>>();
+        groups[index] = bounding_box(&members).map(|b| Rect {
+            x: b.x - GROUP_PADDING,
+            y: b.y - group_margin,
+            width: GROUP_PADDING.mul_add(2.0, b.width),
+            height: b.height + GROUP_PADDING + group_margin,
+        });
+    }
+    Layout { nodes, groups }
+}
+
+fn bounding_box(rects: &[Rect]) -> Option<Rect> {
+    let first = rects.first()?;
+    let [mut left, mut top, mut right, mut bottom] =
+        [first.x, first.y, first.right(), first.bottom()];
+    for rect in rects {
+        left = left.min(rect.x);
+        top = top.min(rect.y);
+        right = right.max(rect.right());
+        bottom = bottom.max(rect.bottom());
+    }
+    Some(Rect {
+        x: left,
+        y: top,
+        width: right - left,
+        height: bottom - top,
+    })
+}
+
+/// A text element, centered on the point unless it is in a container
+fn text_element(id: String, label: &str, center: [f64; 2], container: Option<&str>) -> Element {
+    let lines = label.lines().collect::<Vec<_>>();
+    let longest = lines.iter().map(|l| l.chars().count()).max().unwrap_or(0);
+    let width = count(longest) * FONT_SIZE * CHAR_WIDTH;
+    let height = count(lines.len().max(1)) * FONT_SIZE * LINE_HEIGHT;
+    let mut element = Element::new(
+        id,
+        ElementData::Text(Text {
+            font_size: FONT_SIZE,
+            text: label.to_owned(),
+            text_align: TextAlign::Center,
+            vertical_align: VerticalAlign::Middle,
+            container_id: container.map(ToOwned::to_owned),
+            original_text: label.to_owned(),
+            line_height: LINE_HEIGHT,
+            ..Text::default()
+        }),
+    );
+    let base = &mut element.base;
+    (base.x, base.y) = (center[0] - width / 2.0, center[1] - height / 2.0);
+    (base.width, base.height) = (width, height);
+    base.stroke_color = STROKE_COLOR.to_owned();
+    element
+}
+
+fn bind(element: &mut Element, id: &str, kind: &str) {
+    element
+        .base
+        .bound_elements
+        .get_or_insert_with(Vec::new)
+        .push(BoundElement {
+            id: id.to_owned(),
+            kind: kind.to_owned(),
+            extra: Map::new(),
+        });
+}
+
+/// Excalidraw group ids of a node or group, the innermost first
+fn group_ids(diagram: &Diagram, mut group: Option<usize>) -> Vec<String> {
+    let mut ids = vec![];
+    while let Some(index) = group {
+        ids.push(format!("group:{}", diagram.groups[index].id));
+        group = diagram.groups[index].parent;
+    }
+    ids
+}
+
+fn group_elements(diagram: &Diagram, layout: &Layout) -> Vec<Element> {
+    let mut elements = vec![];
+    for (index, group) in diagram.groups.iter().enumerate() {
+        let Some(rect) = layout.groups[index] else {
+            continue;
+        };
+        let ids = group_ids(diagram, Some(index));
+        let mut frame = Element::new(format!("group:{}", group.id), ElementData::Rectangle);
+        let base = &mut frame.base;
+        (base.x, base.y, base.width, base.height) = (rect.x, rect.y, rect.width, rect.height);
+        base.stroke_color = STROKE_COLOR.to_owned();
+        base.stroke_style = StrokeStyle::Dashed;
+        base.roundness = Some(Roundness {
+            kind: 3,
+            value: None,
+        });
+        base.group_ids.clone_from(&ids);
+        elements.push(frame);
+
+        let label_center = [
+            rect.center()[0],
+            rect.y + GROUP_PADDING / 2.0 + GROUP_LABEL_SIZE / 2.0,
+        ];
+        let mut label = text_element(
+            format!("group:{}:label", group.id),
+            &group.label,
+            label_center,
+            None,
+        );
+        label.base.group_ids = ids;
+        elements.push(label);
+    }
+    elements
+}
+
+fn node_elements(diagram: &Diagram, layout: &Layout) -> Vec<Element> {
+    let mut elements = vec![];
+    for (node, rect) in diagram.nodes.iter().zip(&layout.nodes) {
+        let data = match node.shape {
+            Shape::Box | Shape::Round => ElementData::Rectangle,
+            Shape::Ellipse => ElementData::Ellipse,
+            Shape::Diamond => ElementData::Diamond,
+        };
+        let mut shape = Element::new(node.id.clone(), data);
+        let base = &mut shape.base;
+        (base.x, base.y, base.width, base.height) = (rect.x, rect.y, rect.width, rect.height);
+        base.stroke_color = STROKE_COLOR.to_owned();
+        base.group_ids = group_ids(diagram, node.group);
+        base.roundness = match node.shape {
+            Shape::Box => None,
+            Shape::Round => Some(Roundness {
+                kind: 3,
+                value: None,
+            }),
+            Shape::Ellipse | Shape::Diamond => Some(Roundness {
+                kind: 2,
+                value: None,
+            }),
+        };
+
+        let label_id = format!("{}:label", node.id);
+        let mut label = text_element(label_id.clone(), &node.label, rect.center(), Some(&node.id));
+        label.base.group_ids.clone_from(&shape.base.group_ids);
+        bind(&mut shape, &label_id, "text");
+        elements.push(shape);
+        elements.push(label);
+    }
+    elements
+}
+
+/// Moves the point the distance toward the other one
+fn toward(from: [f64; 2], to: [f64; 2], distance: f64) -> [f64; 2] {
+    let length = (to[0] - from[0]).hypot(to[1] - from[1]);
+    if length < f64::EPSILON {
+        return from;
+    }
+    [
+        ((to[0] - from[0]) / length).mul_add(distance, from[0]),
+        ((to[1] - from[1]) / length).mul_add(distance, from[1]),
+    ]
+}
+
+fn edge_elements(diagram: &Diagram, layout: &Layout, nodes: &mut [Element]) -> Vec<Element> {
+    let mut elements = vec![];
+    for (index, edge) in diagram.edges.iter().enumerate() {
+        let id = format!("edge:{index}");
+        let (from, to) = (layout.nodes[edge.from], layout.nodes[edge.to]);
+        let from_perimeter = diagram.nodes[edge.from].shape.perimeter();
+        let to_perimeter = diagram.nodes[edge.to].shape.perimeter();
+        let start = from_perimeter.point(from, to.center());
+        let end = to_perimeter.point(to, from.center());
+        let (start, end) = (toward(start, end, ARROW_GAP), toward(end, start, ARROW_GAP));
+
+        let mut binding = |node: usize| {
+            // Shapes and their labels alternate in the node elements
+            bind(&mut nodes[node * 2], &id, "arrow");
+            Some(Binding {
+                element_id: diagram.nodes[node].id.clone(),
+                focus: 0.0,
+                gap: ARROW_GAP,
+                extra: Map::new(),
+            })
+        };
+        let (start_arrowhead, end_arrowhead) = match edge.kind {
+            EdgeKind::Forward => (None, Some(Arrowhead::Arrow)),
+            EdgeKind::Backward => (Some(Arrowhead::Arrow), None),
+            EdgeKind::Both => (Some(Arrowhead::Arrow), Some(Arrowhead::Arrow)),
+            EdgeKind::Line => (None, None),
+        };
+        let delta = [end[0] - start[0], end[1] - start[1]];
+        let mut arrow = Element::new(
+            id.clone(),
+            ElementData::Arrow(Linear {
+                points: vec![[0.0, 0.0], delta],
+                last_committed_point: None,
+                start_binding: binding(edge.from),
+                end_binding: binding(edge.to),
+                start_arrowhead,
+                end_arrowhead,
+            }),
+        );
+        let base = &mut arrow.base;
+        (base.x, base.y) = (start[0], start[1]);
+        (base.width, base.height) = (delta[0].abs(), delta[1].abs());
+        base.stroke_color = STROKE_COLOR.to_owned();
+        base.roundness = Some(Roundness {
+            kind: 2,
+            value: None,
+        });
+
+        let label = edge.label.as_ref().map(|label| {
+            let label_id = format!("{id}:label");
+            bind(&mut arrow, &label_id, "text");
+            let middle = [(start[0] + end[0]) / 2.0, (start[1] + end[1]) / 2.0];
+            text_element(label_id, label, middle, Some(&id))
+        });
+        elements.push(arrow);
+        elements.extend(label);
+    }
+    elements
+}
+
+/// Compiles the text of a diagram into an excalidraw scene
+pub fn compile(source: &str) -> Result<Scene> {
+    let diagram = Diagram::parse(source)?;
+    let layout = layout(&diagram);
+
+    let mut elements = group_elements(&diagram, &layout);
+    let mut nodes = node_elements(&diagram, &layout);
+    let edges = edge_elements(&diagram, &layout, &mut nodes);
+    elements.extend(nodes);
+    elements.extend(edges);
+    // Seeds pick how the strokes wobble, keep them stable between runs
+    for (seed, element) in (1..).zip(&mut elements) {
+        element.base.seed = seed;
+    }
+
+    Ok(Scene {
+        source: Some("hdiag".to_owned()),
+        elements,
+        ..Scene::default()
+    })
+}
+
+/// Compiles the text of a diagram into the JSON of an excalidraw scene
+pub fn scene_json(contents: &[u8]) -> Result<Vec<u8>> {
+    let source = std::str::from_utf8(contents).wrap_err("Diagram is not valid UTF-8")?;
+    let scene = compile(source).wrap_err("Failed compiling diagram")?;
+    let mut json = serde_json::to_vec_pretty(&scene).wrap_err("Failed serializing scene")?;
+    json.push(b'\n');
+    Ok(json)
+}
+
+pub struct Dsl;
+
+impl DiagramBackend for Dsl {
+    fn name(&self) -> &'static str {
+        "hdiag"
+    }
+
+    fn description(&self) -> &'static str {
+        "Boxes and arrows described in hdiag's text format, laid out automatically"
+    }
+
+    fn suffixes(&self) -> &'static [&'static str] {
+        &[".hdiag"]
+    }
+
+    fn detect(&self, contents: &[u8]) -> Result<()> {
+        let source = std::str::from_utf8(contents).wrap_err("not UTF-8")?;
+        let diagram = Diagram::parse(source)?;
+        if diagram.edges.is_empty() {
+            bail!("text has no edges");
+        }
+        Ok(())
+    }
+
+    fn supports(&self) -> Supports {
+        excalidraw::SCENE_SUPPORTS
+    }
+
+    fn render(&self, rt: &Runtime, opts: &cli::Opts, input_contents: &[u8]) -> Result<Vec<Output>> {
+        let scene = scene_json(input_contents)?;
+        excalidraw::render_scene(rt, opts, scene)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    fn ids(diagram: &Diagram) -> Vec<&str> {
+        diagram.nodes.iter().map(|n| n.id.as_str()).collect()
+    }
+
+    #[test]
+    fn parse_reads_nodes_and_chained_edges() -> Result<()> {
+        let diagram = Diagram::parse(
+            "direction right\n\
+             web \"Web app\"\n\
+             db \"Database\" ellipse # the store\n\
+             web -> api <- db \"Query\"\n",
+        )?;
+        assert_eq!(diagram.direction, Direction::Right);
+        assert_eq!(ids(&diagram), ["web", "db", "api"]);
+        assert_eq!(diagram.nodes[0].label, "Web app");
+        assert_eq!(diagram.nodes[1].shape, Shape::Ellipse);
+        // Nodes only used in edges are labelled with their id
+        assert_eq!(diagram.nodes[2].label, "api");
+        assert!(!diagram.nodes[2].declared);
+
+        let edges = diagram
+            .edges
+            .iter()
+            .map(|e| (e.from, e.to, e.kind, e.label.as_deref()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            edges,
+            [
+                (0, 2, EdgeKind::Forward, Some("Query")),
+                (2, 1, EdgeKind::Backward, Some("Query")),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn parse_nests_groups() -> Result<()> {
+        let diagram = Diagram::parse(
+            "group backend \"Backend\" {\n\
+               api\n\
+               group jobs {\n\
+                 worker -> queue\n\
+               }\n\
+             }\n\
+             web -> api\n",
+        )?;
+        let groups = diagram
+            .groups
+            .iter()
+            .map(|g| (g.id.as_str(), g.label.as_str(), g.parent))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            groups,
+            [("backend", "Backend", None), ("jobs", "jobs", Some(0))]
+        );
+        let node_groups = diagram.nodes.iter().map(|n| n.group).collect::<Vec<_>>();
+        assert_eq!(ids(&diagram), ["api", "worker", "queue", "web"]);
+        assert_eq!(node_groups, [Some(0), Some(1), Some(1), None]);
+        assert_eq!(diagram.group_path(1), [0, 1]);
+        Ok(())
+    }
+
+    #[test]
+    fn parse_rejects_unbalanced_groups() {
+        let unclosed = Diagram::parse("group backend {\napi\n").map(|_| ());
+        assert_eq!(
+            unclosed.map_err(|e| e.to_string()),
+            Err("Group `backend` is never closed".to_owned())
+        );
+        assert!(Diagram::parse("api\n}\n").is_err());
+    }
+
+    #[test]
+    fn parse_rejects_self_edges() {
+        let error = Diagram::parse("a -> b\nb -> b\n").map(|_| ());
+        let error = error.map_err(|e| format!("{e:#}"));
+        assert_eq!(
+            error,
+            Err("Line 2: b -> b: edges from `b` to itself are not supported".to_owned())
+        );
+    }
+
+    #[test]
+    fn parse_rejects_invalid_lines() {
+        for source in [
+            "a \"Label\" hexagon",
+            "a\na",
+            "direction up",
+            "a -> \"unclosed",
+            "a ->",
+            "a ; b",
+        ] {
+            assert!(Diagram::parse(source).is_err(), "{source}");
+        }
+    }
+
+    #[test]
+    fn ranks_follow_the_arrows() -> Result<()> {
+        let diagram = Diagram::parse("a -> b -> c\na -> c\nd <- c\ne\n")?;
+        assert_eq!(ids(&diagram), ["a", "b", "c", "d", "e"]);
+        // `d <- c` points from c to d, so d comes after c
+        assert_eq!(ranks(&diagram), [0, 1, 2, 3, 0]);
+        Ok(())
+    }
+
+    #[test]
+    fn ranks_break_cycles() -> Result<()> {
+        let diagram = Diagram::parse("a -> b -> c -> a\n")?;
+        assert_eq!(ranks(&diagram), [0, 1, 2]);
+        Ok(())
+    }
+
+    #[test]
+    fn compile_makes_shapes_labels_and_arrows() -> Result<()> {
+        let scene = compile("group g {\na \"A\" diamond\n}\na -> b \"Edge\"\n")?;
+        let count_of = |matches: fn(&ElementData) -> bool| {
+            scene.elements.iter().filter(|e| matches(&e.data)).count()
+        };
+        assert_eq!(count_of(|d| matches!(d, ElementData::Diamond)), 1);
+        // The group and the node with the default shape
+        assert_eq!(count_of(|d| matches!(d, ElementData::Rectangle)), 2);
+        assert_eq!(count_of(|d| matches!(d, ElementData::Arrow(_))), 1);
+        let texts = scene
+            .elements
+            .iter()
+            .filter_map(|e| match &e.data {
+                ElementData::Text(text) => Some(text.text.as_str()),
+                _ => None,
+            })
+            .collect::<HashSet<_>>();
+        assert_eq!(texts, HashSet::from(["g", "A", "b", "Edge"]));
+
+        let seeds = scene
+            .elements
+            .iter()
+            .map(|e| e.base.seed)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            seeds,
+            (1..=scene.elements.len())
+                .map(|s| s as u64)
+                .collect::<Vec<_>>()
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,433 @@
+use std::io::Read as _;
+
+use base64::prelude::*;
+use color_eyre::{
+    eyre::{bail, ContextCompat, WrapErr},
+    Result,
+};
+use flate2::read::ZlibDecoder;
+use percent_encoding::percent_decode_str;
+use quick_xml::events::Event;
+use tokio::runtime::Runtime;
+
+use crate::{
+    backend::{DiagramBackend, Supports},
+    cli, detect, excalidraw, mxgraph, Output,
+};
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+/// Keyword of the png text chunk excalidraw saves the scene in
+const EXCALIDRAW_PNG_KEYWORD: &str = "application/vnd.excalidraw+json";
+
+/// Keyword of the png text chunk draw.io saves the file in
+const DRAWIO_PNG_KEYWORD: &str = "mxfile";
+
+pub fn is_png(contents: &[u8]) -> bool {
+    contents.starts_with(PNG_SIGNATURE)
+}
+
+/// Reads the text of the first `tEXt`, `zTXt` or `iTXt` chunk of a png with
+/// the keyword
+pub fn png_text(png: &[u8], keyword: &str) -> Result<Option<String>> {
+    let mut rest = png
+        .strip_prefix(PNG_SIGNATURE)
+        .context("File is not a png")?;
+    while rest.len() >= 12 {
+        let (header, after) = rest.split_at(8);
+        let length = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+        let kind = &header[4..8];
+        let data = after.get(..length).context("Png chunk is truncated")?;
+        // The chunk is followed by its CRC
+        rest = after.get(length + 4..).unwrap_or_default();
+
+        let Some(separator) = data.iter().position(|b| *b == 0) else {
+            continue;
+        };
+        let (chunk_keyword, text) = (&data[..separator], &data[separator + 1..]);
+        if !matches!(kind, b"tEXt" | b"zTXt" | b"iTXt") || chunk_keyword != keyword.as_bytes() {
+            continue;
+        }
+        let text = match kind {
+            b"tEXt" => latin1(text),
+            b"zTXt" => latin1(&inflate(text.get(1..).unwrap_or_default())?),
+            _ => itxt_text(text)?,
+        };
+        return Ok(Some(text));
+    }
+    Ok(None)
+}
+
+/// Text of an `iTXt` chunk after its keyword, which is UTF-8 and may be
+/// compressed
+fn itxt_text(data: &[u8]) -> Result<String> {
+    let [compressed, _method, rest @ ..] = data else {
+        bail!("Png iTXt chunk is truncated");
+    };
+    // Skip the language tag and translated keyword
+    let text = rest
+        .splitn(3, |b| *b == 0)
+        .nth(2)
+        .context("Png iTXt chunk is truncated")?;
+    let text = if *compressed == 0 {
+        text.to_vec()
+    } else {
+        inflate(text)?
+    };
+    String::from_utf8(text).wrap_err("Png iTXt chunk is not valid UTF-8")
+}
+
+fn inflate(compressed: &[u8]) -> Result<Vec<u8>> {
+    let mut inflated = vec![];
+    ZlibDecoder::new(compressed)
+        .read_to_end(&mut inflated)
+        .wrap_err("Failed inflating compressed data")?;
+    Ok(inflated)
+}
+
+fn latin1(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| char::from(*b)).collect()
+}
+
+/// Turns a string where every character stands for a byte back into bytes
+fn byte_string(s: &str) -> Result<Vec<u8>> {
+    s.chars()
+        .map(|c| u8::try_from(c).wrap_err("Byte string has a character that is not a byte"))
+        .collect()
+}
+
+/// Decodes the JSON excalidraw wraps embedded scenes in, which holds the
+/// scene as a byte string that may be compressed
+fn decode_scene_json(json: &str) -> Result<Vec<u8>> {
+    let wrapper: serde_json::Value =
+        serde_json::from_str(json).wrap_err("Embedded scene is not JSON")?;
+    // Old exports embed the scene itself
+    if wrapper.get("type").is_some() {
+        return Ok(json.as_bytes().to_vec());
+    }
+    let encoded = wrapper
+        .get("encoded")
+        .and_then(serde_json::Value::as_str)
+        .context("Embedded scene has no `encoded` data")?;
+    let bytes = byte_string(encoded)?;
+    let compressed = wrapper
+        .get("compressed")
+        .and_then(serde_json::Value::as_bool)
+        .unwrap_or(false);
+    if compressed {
+        inflate(&bytes).wrap_err("Failed inflating embedded scene")
+    } else {
+        Ok(bytes)
+    }
+}
+
+/// Finds the payload excalidraw embeds in the comments of an exported svg
+fn svg_payload(svg: &str) -> Option<(&str, &str)> {
+    let payload = svg
+        .split_once("<!-- payload-type:application/vnd.excalidraw+json -->")?
+        .1;
+    let version = payload
+        .split_once("<!-- payload-version:")
+        .and_then(|(_, v)| v.split_once(" -->"))
+        .map_or("1", |(v, _)| v);
+    let data = payload.split_once("<!-- payload-start -->")?.1;
+    let data = data.split_once("<!-- payload-end -->")?.0;
+    Some((version, data.trim()))
+}
+
+pub fn has_excalidraw_svg_payload(svg: &str) -> bool {
+    svg_payload(svg).is_some()
+}
+
+/// Extracts the excalidraw scene embedded in an svg or png exported with
+/// "Embed scene"
+pub fn excalidraw_scene(contents: &[u8]) -> Result<Vec<u8>> {
+    if is_png(contents) {
+        let json = png_text(contents, EXCALIDRAW_PNG_KEYWORD)
+            .wrap_err("Failed reading png chunks")?
+            .context("Png has no embedded excalidraw scene")?;
+        return decode_scene_json(&json);
+    }
+
+    let svg = std::str::from_utf8(contents).wrap_err("Svg is not valid UTF-8")?;
+    let (version, data) = svg_payload(svg).context("Svg has no embedded excalidraw scene")?;
+    let bytes = BASE64_STANDARD
+        .decode(data)
+        .wrap_err("Embedded scene is not valid base64")?;
+    // The first version encoded the JSON as UTF-8, later ones as a byte string
+    let json = if version == "1" {
+        String::from_utf8(bytes).wrap_err("Embedded scene is not valid UTF-8")?
+    } else {
+        latin1(&bytes)
+    };
+    decode_scene_json(&json)
+}
+
+pub fn has_excalidraw_png_scene(png: &[u8]) -> Result<bool> {
+    Ok(png_text(png, EXCALIDRAW_PNG_KEYWORD)?.is_some())
+}
+
+/// Reads the `content` attribute of the root element of an svg, where
+/// draw.io keeps the file
+fn svg_content(svg: &[u8]) -> Result<Option<String>> {
+    let mut reader = quick_xml::Reader::from_reader(svg);
+    loop {
+        match reader.read_event().wrap_err("Svg is not valid XML")? {
+            Event::Start(e) | Event::Empty(e) => {
+                let Some(content) = e
+                    .try_get_attribute("content")
+                    .wrap_err("Svg has an invalid attribute")?
+                else {
+                    return Ok(None);
+                };
+                let content = content
+                    .unescape_value()
+                    .wrap_err("Svg `content` attribute is not valid")?;
+                return Ok(Some(content.into_owned()));
+            }
+            Event::Eof => bail!("Svg has no root element"),
+            _ => {}
+        }
+    }
+}
+
+/// Finds the draw.io file embedded in an svg or png, if there is one
+fn find_drawio_file(contents: &[u8]) -> Result<Option<String>> {
+    let file = if is_png(contents) {
+        png_text(contents, DRAWIO_PNG_KEYWORD).wrap_err("Failed reading png chunks")?
+    } else {
+        svg_content(contents)?
+    };
+    // Pngs store the file URL encoded
+    let file = file
+        .map(|f| {
+            if f.trim_start().starts_with('<') {
+                Ok(f)
+            } else {
+                percent_decode_str(&f)
+                    .decode_utf8()
+                    .map(std::borrow::Cow::into_owned)
+                    .wrap_err("Embedded draw.io file is not valid UTF-8")
+            }
+        })
+        .transpose()?;
+    Ok(file.filter(|f| f.trim_start().starts_with("<mxfile")))
+}
+
+pub fn has_drawio_file(contents: &[u8]) -> Result<bool> {
+    Ok(find_drawio_file(contents)?.is_some())
+}
+
+/// Extracts the draw.io file embedded in a `.drawio.svg` or `.drawio.png`
+pub fn drawio_file(contents: &[u8]) -> Result<Vec<u8>> {
+    let file = find_drawio_file(contents)?.context("Image has no embedded draw.io file")?;
+    Ok(file.into_bytes())
+}
+
+/// Checks the root of the contents is `<svg>`
+fn check_svg(contents: &[u8]) -> Result<()> {
+    let root = detect::xml_root(contents)?;
+    if root != "svg" {
+        bail!("root element is <{root}>, not <svg>");
+    }
+    Ok(())
+}
+
+pub struct ExcalidrawImage;
+
+impl DiagramBackend for ExcalidrawImage {
+    fn name(&self) -> &'static str {
+        "image"
+    }
+
+    fn description(&self) -> &'static str {
+        "Svg or png exported from Excalidraw with the scene embedded"
+    }
+
+    fn suffixes(&self) -> &'static [&'static str] {
+        &[".excalidraw.svg", ".excalidraw.png"]
+    }
+
+    fn detect(&self, contents: &[u8]) -> Result<()> {
+        if is_png(contents) {
+            if !has_excalidraw_png_scene(contents)? {
+                bail!("png has no embedded scene");
+            }
+            return Ok(());
+        }
+        check_svg(contents)?;
+        let svg = std::str::from_utf8(contents).wrap_err("svg is not valid UTF-8")?;
+        if !has_excalidraw_svg_payload(svg) {
+            bail!("svg has no embedded scene");
+        }
+        Ok(())
+    }
+
+    fn supports(&self) -> Supports {
+        excalidraw::SCENE_SUPPORTS
+    }
+
+    fn render(&self, rt: &Runtime, opts: &cli::Opts, input_contents: &[u8]) -> Result<Vec<Output>> {
+        let scene = excalidraw_scene(input_contents)
+            .wrap_err("Failed extracting scene embedded in image")?;
+        excalidraw::render_scene(rt, opts, scene)
+    }
+}
+
+pub struct DrawioImage;
+
+impl DiagramBackend for DrawioImage {
+    fn name(&self) -> &'static str {
+        "drawio-image"
+    }
+
+    fn description(&self) -> &'static str {
+        "Draw.io diagram saved as `.drawio.svg` or `.drawio.png`, which embed the file"
+    }
+
+    fn suffixes(&self) -> &'static [&'static str] {
+        &[".drawio.svg", ".drawio.png"]
+    }
+
+    fn detect(&self, contents: &[u8]) -> Result<()> {
+        if !is_png(contents) {
+            check_svg(contents)?;
+        }
+        if !has_drawio_file(contents)? {
+            bail!("image has no embedded draw.io file");
+        }
+        Ok(())
+    }
+
+    fn supports(&self) -> Supports {
+        mxgraph::Drawio.supports()
+    }
+
+    fn render(&self, rt: &Runtime, opts: &cli::Opts, input_contents: &[u8]) -> Result<Vec<Output>> {
+        let file = drawio_file(input_contents)
+            .wrap_err("Failed extracting draw.io file embedded in image")?;
+        mxgraph::Drawio.render(rt, opts, &file)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write as _;
+
+    use flate2::{write::ZlibEncoder, Compression};
+
+    use super::*;
+
+    /// A png of the chunks, the CRCs are left zero as they aren't checked
+    fn png(chunks: &[(&[u8; 4], Vec<u8>)]) -> Vec<u8> {
+        let mut png = PNG_SIGNATURE.to_vec();
+        for (kind, data) in chunks {
+            let length = u32::try_from(data.len()).unwrap_or(u32::MAX);
+            png.extend(length.to_be_bytes());
+            png.extend(*kind);
+            png.extend(data);
+            png.extend([0; 4]);
+        }
+        png
+    }
+
+    fn deflate(data: &[u8]) -> Result<Vec<u8>> {
+        let mut encoder = ZlibEncoder::new(vec![], Compression::default());
+        encoder.write_all(data)?;
+        Ok(encoder.finish()?)
+    }
+
+    fn chunk_data(parts: &[&[u8]]) -> Vec<u8> {
+        parts.concat()
+    }
+
+    #[test]
+    fn png_text_reads_every_text_chunk_kind() -> Result<()> {
+        let png = png(&[
+            (b"IHDR", vec![0; 13]),
+            (b"tEXt", chunk_data(&[b"latin\0caf\xe9"])),
+            (
+                b"zTXt",
+                chunk_data(&[b"zipped\0\0", &deflate(b"squeezed")?]),
+            ),
+            (
+                b"iTXt",
+                chunk_data(&[b"intl\0\0\0de\0Intl\0", "grüß".as_bytes()]),
+            ),
+            (
+                b"iTXt",
+                chunk_data(&[b"intl-zipped\0\x01\0\0\0", &deflate("süß".as_bytes())?]),
+            ),
+            (b"IEND", vec![]),
+        ]);
+        assert_eq!(png_text(&png, "latin")?.as_deref(), Some("café"));
+        assert_eq!(png_text(&png, "zipped")?.as_deref(), Some("squeezed"));
+        assert_eq!(png_text(&png, "intl")?.as_deref(), Some("grüß"));
+        assert_eq!(png_text(&png, "intl-zipped")?.as_deref(), Some("süß"));
+        assert_eq!(png_text(&png, "missing")?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn png_text_takes_the_first_chunk_with_the_keyword() -> Result<()> {
+        let png = png(&[
+            (b"tEXt", chunk_data(&[b"key\0first"])),
+            (b"tEXt", chunk_data(&[b"key\0second"])),
+        ]);
+        assert_eq!(png_text(&png, "key")?.as_deref(), Some("first"));
+        Ok(())
+    }
+
+    #[test]
+    fn png_text_rejects_broken_pngs() {
+        assert!(png_text(b"GIF89a", "key").is_err());
+
+        let mut truncated = png(&[(b"tEXt", chunk_data(&[b"key\0text"]))]);
+        truncated.truncate(truncated.len() - 8);
+        assert!(png_text(&truncated, "key").is_err());
+
+        let unterminated = png(&[(b"iTXt", chunk_data(&[b"key\0\0\0no terminators"]))]);
+        assert!(png_text(&unterminated, "key").is_err());
+        let not_zlib = png(&[(b"zTXt", chunk_data(&[b"key\0\0not zlib"]))]);
+        assert!(png_text(&not_zlib, "key").is_err());
+    }
+
+    #[test]
+    fn excalidraw_scene_reads_compressed_png_scenes() -> Result<()> {
+        let scene = br#"{"type":"excalidraw","elements":[]}"#;
+        let encoded = latin1(&deflate(scene)?);
+        let wrapper = serde_json::json!({
+            "version": "1",
+            "encoding": "bstring",
+            "compressed": true,
+            "encoded": encoded,
+        });
+        let png = png(&[(
+            b"tEXt",
+            [
+                EXCALIDRAW_PNG_KEYWORD.as_bytes(),
+                b"\0",
+                // Text chunks are latin-1, a byte per character
+                &byte_string(&wrapper.to_string())?,
+            ]
+            .concat(),
+        )]);
+        assert!(has_excalidraw_png_scene(&png)?);
+        assert_eq!(excalidraw_scene(&png)?, scene);
+        Ok(())
+    }
+
+    #[test]
+    fn drawio_file_reads_url_encoded_png_files() -> Result<()> {
+        let drawio_png = png(&[(
+            b"tEXt",
+            chunk_data(&[b"mxfile\0%3Cmxfile%3E%3Cdiagram%20name%3D%22A%22%2F%3E%3C%2Fmxfile%3E"]),
+        )]);
+        assert_eq!(
+            drawio_file(&drawio_png)?,
+            br#"<mxfile><diagram name="A"/></mxfile>"#
+        );
+        assert!(!has_drawio_file(&png(&[(b"IEND", vec![])]))?);
+        Ok(())
+    }
+}
@@ -1,78 +1,102 @@
-use std::{fmt::Write as _, io::Cursor, net::SocketAddr, sync::Arc};
+use std::{fmt::Write as _, io::Cursor};
 
 use base64::prelude::*;
 use color_eyre::{
     eyre::{bail, ContextCompat, WrapErr},
     Result,
 };
-use tokio::sync::mpsc;
+use hdiag::scene::{self, ElementData};
+use serde::Deserialize;
 use tracing::{info, warn};
 use zip::ZipArchive;
 
+use tokio::runtime::Runtime;
+
 use crate::{
-    cli,
-    serve_zip::{goto_page_chrome, http_serve},
+    assets::{self, Assets},
+    backend::{DiagramBackend, Supports},
+    cli, i18n, native, serve_zip, svg, template, Output,
 };
 
+/// Version of the excalidraw package bundled in the binary, a different
+/// version might render the same scene differently
+pub const VERSION: &str = env!("HDIAG_EXCALIDRAW_VERSION");
+
 const EXCALIDRAW_APP_ASSETS: &[u8] =
     include_bytes!(concat!(env!("OUT_DIR"), "/excalidraw-app.zip"));
 
 const EXCALIDRAW_FONTS: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/excalidraw-fonts.zip"));
 
+/// What the excalidraw page does with the input it is given
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PageMode {
+    /// Export a scene to a single svg
+    Scene,
+    /// Export every item of a library to its own svg, returned as a JSON
+    /// array
+    Library,
+    /// Convert a mermaid diagram to a scene, returned as its JSON
+    Mermaid,
+}
+
+impl PageMode {
+    const fn name(self) -> &'static str {
+        match self {
+            Self::Scene => "scene",
+            Self::Library => "library",
+            Self::Mermaid => "mermaid",
+        }
+    }
+
+    const fn input_name(self) -> &'static str {
+        match self {
+            Self::Scene => "input.excalidraw",
+            Self::Library => "input.excalidrawlib",
+            Self::Mermaid => "input.mmd",
+        }
+    }
+}
+
+/// The scales excalidraw can export at
+pub fn export_scale(scale: u8) -> u8 {
+    if matches!(scale, 1..=3) {
+        scale
+    } else {
+        let clamped = scale.clamp(1, 3);
+        warn!("Scale must be one of {{1, 2, 3}}, was {scale}, clamped to {clamped}");
+        clamped
+    }
+}
+
 pub async fn get_svg_from(
     excalidraw_assets_zip: &'static [u8],
     input_contents: Vec<u8>,
     export_opts: cli::ExportOpts,
+    mode: PageMode,
+    assets: Assets,
 ) -> Result<Vec<u8>> {
-    let addr = SocketAddr::from(([127, 0, 0, 1], 0));
-    let listener = tokio::net::TcpListener::bind(addr).await?;
-    let addr = listener
-        .local_addr()
-        .expect("The listener is already bound");
-
     let export_opts = {
         let is_dark_mode = export_opts.theme == cli::OutputTheme::Dark;
-        let scale = export_opts.scale;
-        let scale = if matches!(scale, 1..=3) {
-            scale
-        } else {
-            let clamped = scale.clamp(1, 3);
-            warn!("Scale must be one of {{1, 2, 3}}, was {scale}, clamped to {clamped}");
-            clamped
-        };
+        let scale = export_scale(export_opts.scale);
         serde_json::json!({
             "exportBackground": export_opts.include_background,
             "exportEmbedScene": export_opts.embed_source,
             "exportWithDarkMode": is_dark_mode,
-            "exportScale": scale
+            "exportScale": scale,
+            "deterministic": export_opts.deterministic,
+            "mode": mode.name(),
+            "assets": assets.keys().collect::<Vec<_>>(),
         })
     };
-    let (tx, mut rx) = mpsc::channel(1);
-    let http_server = tokio::spawn(async move {
-        http_serve(
-            listener,
-            "excalidraw",
-            "input.excalidraw",
-            &input_contents,
-            excalidraw_assets_zip,
-            Arc::new(tx),
-            export_opts,
-        )
-        .await
-    });
-
-    let chrome = tokio::task::spawn_blocking(move || {
-        goto_page_chrome(addr).expect("Failed to navigate to the page with chrome");
-    });
-
-    let Some(bytes) = rx.recv().await else {
-        bail!("Sender for svg dropped unexpectedly");
-    };
-
-    http_server.abort();
-    chrome.abort();
-
-    Ok(bytes)
+    serve_zip::render_in_browser(
+        "excalidraw",
+        mode.input_name(),
+        input_contents,
+        excalidraw_assets_zip,
+        export_opts,
+        assets,
+    )
+    .await
 }
 
 fn split_svg_with_fonts(raw_svg: &str) -> Result<(&str, &str, &str)> {
@@ -166,14 +190,98 @@ fn embed_fonts_as_base64(style: &str) -> Result<String> {
     Ok(fonts_str)
 }
 
-pub async fn raw_svg(input_contents: Vec<u8>, export_opts: cli::ExportOpts) -> Result<String> {
-    let result = get_svg_from(EXCALIDRAW_APP_ASSETS, input_contents, export_opts)
-        .await
-        .wrap_err("Failed to get svg from excalidraw app")?;
+pub async fn raw_svg(
+    input_contents: Vec<u8>,
+    export_opts: cli::ExportOpts,
+    assets: Assets,
+) -> Result<String> {
+    let result = get_svg_from(
+        EXCALIDRAW_APP_ASSETS,
+        input_contents,
+        export_opts,
+        PageMode::Scene,
+        assets,
+    )
+    .await
+    .wrap_err("Failed to get svg from excalidraw app")?;
 
     String::from_utf8(result).wrap_err("Response from excalidraw was not valid UTF-8")
 }
 
+pub struct LibraryItemSvg {
+    pub name: Option<String>,
+    pub svg: String,
+}
+
+/// Exports every item in an excalidraw library, in the order they are in the
+/// library
+pub async fn raw_library_svgs(
+    input_contents: Vec<u8>,
+    export_opts: cli::ExportOpts,
+) -> Result<Vec<LibraryItemSvg>> {
+    let result = get_svg_from(
+        EXCALIDRAW_APP_ASSETS,
+        input_contents,
+        export_opts,
+        PageMode::Library,
+        Assets::new(),
+    )
+    .await
+    .wrap_err("Failed to get svgs from excalidraw app")?;
+
+    let items: serde_json::Value =
+        serde_json::from_slice(&result).wrap_err("Response from excalidraw was not JSON")?;
+    let items = items
+        .as_array()
+        .context("Response from excalidraw was not an array")?;
+    items
+        .iter()
+        .map(|item| {
+            let name = item
+                .get("name")
+                .and_then(serde_json::Value::as_str)
+                .map(ToOwned::to_owned);
+            let svg = item
+                .get("svg")
+                .and_then(serde_json::Value::as_str)
+                .context("Library item from excalidraw had no svg")?
+                .to_owned();
+            Ok(LibraryItemSvg { name, svg })
+        })
+        .collect()
+}
+
+/// What the app returns instead of a scene when the diagram is invalid
+#[derive(Deserialize)]
+struct ConvertError {
+    error: String,
+}
+
+/// Converts a mermaid diagram to the JSON of an excalidraw scene, with
+/// mermaid-to-excalidraw in the excalidraw page
+pub async fn mermaid_scene(input_contents: Vec<u8>) -> Result<Vec<u8>> {
+    let mode = PageMode::Mermaid;
+    // Ids and seeds are random, keep them the same between runs
+    let export_opts = serde_json::json!({
+        "mode": mode.name(),
+        "deterministic": true,
+    });
+    let result = serve_zip::render_in_browser(
+        "excalidraw",
+        mode.input_name(),
+        input_contents,
+        EXCALIDRAW_APP_ASSETS,
+        export_opts,
+        Assets::new(),
+    )
+    .await
+    .wrap_err("Failed to convert mermaid diagram in excalidraw app")?;
+    if let Ok(failed) = serde_json::from_slice::<ConvertError>(&result) {
+        bail!("Mermaid failed parsing the diagram: {}", failed.error);
+    }
+    Ok(result)
+}
+
 pub fn embed_fonts(style: &str) -> Result<String> {
     embed_fonts_as_base64(style).wrap_err("Failed getting fonts used in file")
 }
@@ -182,16 +290,25 @@ pub const fn remove_fonts(_style: &str) -> String {
     String::new()
 }
 
-pub async fn render_svg(
-    input_contents: Vec<u8>,
-    output_format: &cli::FontFormat,
-    export_opts: cli::ExportOpts,
-) -> Result<String> {
-    let raw_svg = raw_svg(input_contents, export_opts)
-        .await
-        .wrap_err("Failed getting svg from excalidraw")?;
-    info!("Finished rendering raw svg");
+/// Collects the contents of all the text elements in a scene, in the
+/// order they are drawn
+pub fn scene_text(input_contents: &[u8]) -> Result<Vec<String>> {
+    let scene: scene::Scene =
+        serde_json::from_slice(input_contents).wrap_err("Scene is not a valid excalidraw scene")?;
+
+    let texts = scene
+        .live_elements()
+        .filter_map(|e| match &e.data {
+            ElementData::Text(text) => Some(text.text.trim()),
+            _ => None,
+        })
+        .filter(|t| !t.is_empty())
+        .map(ToOwned::to_owned)
+        .collect();
+    Ok(texts)
+}
 
+pub fn apply_font_format(raw_svg: String, output_format: &cli::FontFormat) -> Result<String> {
     if *output_format == cli::FontFormat::Raw {
         return Ok(raw_svg);
     }
@@ -213,3 +330,177 @@ pub async fn render_svg(
 
     todo!("Unsupported output format {output_format:?} for excalidraw");
 }
+
+/// Turns the svg straight from a renderer into the final output, with its
+/// fonts, ids and accessibility metadata
+pub fn finish_svg(
+    raw_svg: String,
+    input_contents: &[u8],
+    output_format: &cli::FontFormat,
+    deterministic: bool,
+    accessibility: Option<&cli::AccessibilityOpts>,
+) -> Result<String> {
+    let description = match accessibility.map(|a| a.description.as_ref()) {
+        Some(Some(description)) => Some(description.clone()),
+        Some(None) => {
+            let texts = scene_text(input_contents).wrap_err("Failed reading text from scene")?;
+            Some(texts.join("\n")).filter(|d| !d.is_empty())
+        }
+        None => None,
+    };
+
+    let svg = apply_font_format(raw_svg, output_format)?;
+    let svg = if deterministic {
+        svg::normalize_ids(&svg)
+    } else {
+        svg
+    };
+
+    if let Some(accessibility) = accessibility {
+        let svg = svg::add_accessibility(&svg, &accessibility.title, description.as_deref())
+            .wrap_err("Failed adding accessibility metadata to svg")?;
+        info!("Finished adding accessibility metadata to svg");
+        return Ok(svg);
+    }
+
+    Ok(svg)
+}
+
+pub async fn render_svg(
+    input_contents: Vec<u8>,
+    output_format: &cli::FontFormat,
+    export_opts: cli::ExportOpts,
+    accessibility: Option<&cli::AccessibilityOpts>,
+    assets: Assets,
+) -> Result<String> {
+    let deterministic = export_opts.deterministic;
+    let raw_svg = raw_svg(input_contents.clone(), export_opts, assets)
+        .await
+        .wrap_err("Failed getting svg from excalidraw")?;
+    info!("Finished rendering raw svg");
+
+    finish_svg(
+        raw_svg,
+        &input_contents,
+        output_format,
+        deterministic,
+        accessibility,
+    )
+}
+
+/// Renders the scene without a browser, or returns `None` if it has elements
+/// only the browser can render
+fn render_native(
+    opts: &cli::Opts,
+    mut scene: scene::Scene,
+    scene_bytes: &[u8],
+    assets: &Assets,
+) -> Result<Option<String>> {
+    let unsupported = native::unsupported_kinds(&scene);
+    if !unsupported.is_empty() {
+        warn!(
+            kinds = unsupported.join(", "),
+            "Scene has elements the native engine can't render, falling back to the browser"
+        );
+        return Ok(None);
+    }
+
+    assets::inject(&mut scene, assets).wrap_err("Failed adding images to scene")?;
+    let raw_svg = native::raw_svg(&scene, scene_bytes, &opts.export)
+        .wrap_err("Failed rendering excalidraw svg natively")?;
+    info!("Finished rendering raw svg");
+    finish_svg(
+        raw_svg,
+        scene_bytes,
+        &opts.output_format,
+        opts.export.deterministic,
+        opts.accessibility.as_ref(),
+    )
+    .map(Some)
+}
+
+/// Renders a scene with the engine picked in the options, for every format
+/// that holds an excalidraw scene
+pub fn render_scene(rt: &Runtime, opts: &cli::Opts, mut scene: Vec<u8>) -> Result<Vec<Output>> {
+    let mut parsed: scene::Scene =
+        serde_json::from_slice(&scene).wrap_err("Scene is not a valid excalidraw scene")?;
+    if let Some(catalogue) = &opts.translation {
+        i18n::translate_scene(&mut parsed, catalogue);
+    }
+    if !opts.vars.is_empty() {
+        template::fill_scene(&mut parsed, &opts.vars);
+    }
+    if opts.translation.is_some() || !opts.vars.is_empty() {
+        scene = serde_json::to_vec(&parsed).wrap_err("Failed serializing filled scene")?;
+    }
+    let assets = assets::resolve(&parsed, opts.asset_dir.as_deref())
+        .wrap_err("Failed looking for the images of the scene")?;
+    if opts.engine == cli::Engine::Native {
+        if let Some(svg) = render_native(opts, parsed, &scene, &assets)? {
+            return Ok(Output::single(svg));
+        }
+    }
+    let svg = rt
+        .block_on(render_svg(
+            scene,
+            &opts.output_format,
+            opts.export.clone(),
+            opts.accessibility.as_ref(),
+            assets,
+        ))
+        .wrap_err("Failed rendering excalidraw svg")?;
+    Ok(Output::single(svg))
+}
+
+/// What the formats that hold an excalidraw scene support
+pub const SCENE_SUPPORTS: Supports = Supports {
+    fonts: true,
+    embed_source: true,
+    native_engine: true,
+    assets: true,
+    templates: true,
+    translations: true,
+    pages: false,
+    items: false,
+};
+
+/// The `type` of an excalidraw JSON file
+pub fn json_type(contents: &[u8]) -> Result<String> {
+    let json: serde_json::Value = serde_json::from_slice(contents).wrap_err("not JSON")?;
+    json.get("type")
+        .and_then(serde_json::Value::as_str)
+        .map(ToOwned::to_owned)
+        .context("JSON has no `type`")
+}
+
+pub struct Excalidraw;
+
+impl DiagramBackend for Excalidraw {
+    fn name(&self) -> &'static str {
+        "excalidraw"
+    }
+
+    fn description(&self) -> &'static str {
+        "Excalidraw JSON file (https://excalidraw.com)"
+    }
+
+    fn suffixes(&self) -> &'static [&'static str] {
+        &[".excalidraw"]
+    }
+
+    fn detect(&self, contents: &[u8]) -> Result<()> {
+        let kind = json_type(contents)?;
+        if kind != "excalidraw" {
+            bail!("JSON has type {kind:?}");
+        }
+        Ok(())
+    }
+
+    fn supports(&self) -> Supports {
+        SCENE_SUPPORTS
+    }
+
+    fn render(&self, rt: &Runtime, opts: &cli::Opts, input_contents: &[u8]) -> Result<Vec<Output>> {
+        render_scene(rt, opts, input_contents.to_vec())
+    }
+}
@@ -0,0 +1,66 @@
+//! Boxes and outlines shared by the layouts of the diagram formats
+
+// The geometry reads closer to what it draws without `mul_add`
+#![allow(clippy::suboptimal_flops)]
+
+pub type Point = [f64; 2];
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Rect {
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
+}
+
+impl Rect {
+    pub const fn at([x, y]: Point) -> Self {
+        Self {
+            x,
+            y,
+            width: 0.0,
+            height: 0.0,
+        }
+    }
+
+    pub fn right(self) -> f64 {
+        self.x + self.width
+    }
+
+    pub fn bottom(self) -> f64 {
+        self.y + self.height
+    }
+
+    pub fn center(self) -> Point {
+        [self.x + self.width / 2.0, self.y + self.height / 2.0]
+    }
+}
+
+/// Outline of a shape, where the lines connected to it stop
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Perimeter {
+    Rectangle,
+    Ellipse,
+    Rhombus,
+}
+
+impl Perimeter {
+    /// Where the line from the center of the rect to `toward` leaves it
+    pub fn point(self, rect: Rect, toward: Point) -> Point {
+        let [cx, cy] = rect.center();
+        let (dx, dy) = (toward[0] - cx, toward[1] - cy);
+        let (half_width, half_height) = (rect.width / 2.0, rect.height / 2.0);
+        if (dx.abs() < f64::EPSILON && dy.abs() < f64::EPSILON)
+            || half_width < f64::EPSILON
+            || half_height < f64::EPSILON
+        {
+            return [cx, cy];
+        }
+        let scale = match self {
+            Self::Rectangle => (half_width / dx.abs()).min(half_height / dy.abs()),
+            Self::Ellipse => 1.0 / (dx / half_width).hypot(dy / half_height),
+            Self::Rhombus => 1.0 / (dx.abs() / half_width + dy.abs() / half_height),
+        };
+        [cx + dx * scale, cy + dy * scale]
+    }
+}
@@ -0,0 +1,96 @@
+use color_eyre::{
+    eyre::{bail, ContextCompat, WrapErr},
+    Result,
+};
+use serde::Deserialize;
+use tokio::runtime::Runtime;
+use tracing::info;
+
+use crate::{assets::Assets, backend::DiagramBackend, cli, serve_zip, svg, Output};
+
+/// Version of the viz.js package bundled in the binary, which has its own
+/// build of Graphviz
+pub const VERSION: &str = env!("HDIAG_VIZ_VERSION");
+
+const GRAPHVIZ_APP_ASSETS: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/graphviz-app.zip"));
+
+/// What the graphviz page returns
+#[derive(Deserialize)]
+struct RawSvg {
+    /// Unset when the graph could not be laid out
+    svg: Option<String>,
+    errors: Vec<String>,
+}
+
+async fn raw_svg(input_contents: Vec<u8>, export_opts: &cli::ExportOpts) -> Result<String> {
+    let export_opts = serde_json::json!({
+        "exportBackground": export_opts.include_background,
+    });
+    let result = serve_zip::render_in_browser(
+        "graphviz",
+        "input.dot",
+        input_contents,
+        GRAPHVIZ_APP_ASSETS,
+        export_opts,
+        Assets::new(),
+    )
+    .await
+    .wrap_err("Failed to get svg from graphviz app")?;
+
+    let raw: RawSvg =
+        serde_json::from_slice(&result).wrap_err("Response from graphviz was not valid")?;
+    match raw.svg {
+        Some(svg) => Ok(svg),
+        None => bail!(
+            "Graphviz failed laying out the graph: {}",
+            raw.errors.join(", ")
+        ),
+    }
+}
+
+pub struct Graphviz;
+
+impl DiagramBackend for Graphviz {
+    fn name(&self) -> &'static str {
+        "graphviz"
+    }
+
+    fn description(&self) -> &'static str {
+        "Graphviz DOT graph (https://graphviz.org)"
+    }
+
+    fn suffixes(&self) -> &'static [&'static str] {
+        &[".dot", ".gv"]
+    }
+
+    fn detect(&self, contents: &[u8]) -> Result<()> {
+        let source = std::str::from_utf8(contents).wrap_err("not UTF-8")?;
+        let first = source
+            .lines()
+            .map(str::trim)
+            .find(|l| !l.is_empty() && !l.starts_with("//") && !l.starts_with('#'))
+            .context("text is empty")?;
+        let mut words = first
+            .split(|c: char| c.is_whitespace() || c == '{')
+            .filter(|w| !w.is_empty())
+            .map(str::to_lowercase);
+        let mut keyword = words.next().unwrap_or_default();
+        if keyword == "strict" {
+            keyword = words.next().unwrap_or_default();
+        }
+        if !matches!(keyword.as_str(), "graph" | "digraph") || !source.contains('{') {
+            bail!("text does not start with `graph {{` or `digraph {{`");
+        }
+        Ok(())
+    }
+
+    fn render(&self, rt: &Runtime, opts: &cli::Opts, input_contents: &[u8]) -> Result<Vec<Output>> {
+        let raw = rt
+            .block_on(raw_svg(input_contents.to_vec(), &opts.export))
+            .wrap_err("Failed rendering graphviz svg")?;
+        info!("Finished rendering raw svg");
+        let svg = svg::apply_export(&raw, &opts.export, opts.accessibility.as_ref())
+            .wrap_err("Failed applying export options to svg")?;
+        Ok(Output::single(svg))
+    }
+}
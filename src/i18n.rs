@@ -0,0 +1,413 @@
+//! Swaps the text of diagrams for its translation, from catalogues keyed by
+//! the ids of text elements and draw.io cells
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use color_eyre::{
+    eyre::{bail, eyre, WrapErr},
+    Result,
+};
+use hdiag::scene::{ElementData, Scene};
+use tracing::{debug, info, warn};
+
+use crate::{cli, detect, drawio::GraphModel, svg::escape_xml, template};
+
+/// Translations of the texts of a diagram to a locale, by element id
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Catalogue {
+    pub locale: String,
+    pub texts: BTreeMap<String, String>,
+}
+
+/// Unquotes a string of a `.po` file
+fn po_string(quoted: &str) -> Result<String> {
+    let inner = quoted
+        .trim()
+        .strip_prefix('"')
+        .and_then(|q| q.strip_suffix('"'))
+        .ok_or_else(|| eyre!("expected a quoted string"))?;
+    let mut unquoted = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unquoted.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => unquoted.push('\n'),
+            Some('t') => unquoted.push('\t'),
+            Some(c) => unquoted.push(c),
+            None => bail!("string ends with a lone `\\`"),
+        }
+    }
+    Ok(unquoted)
+}
+
+/// Strings of an entry of a `.po` file
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum PoField {
+    Context,
+    Id,
+    Translation,
+    /// Plurals, which diagrams have no use for
+    Ignored,
+}
+
+/// An entry of a `.po` file, being read
+#[derive(Default)]
+struct PoEntry {
+    context: Option<String>,
+    id: Option<String>,
+    translation: Option<String>,
+    fuzzy: bool,
+}
+
+impl PoEntry {
+    fn field(&mut self, field: PoField) -> Option<&mut String> {
+        match field {
+            PoField::Context => self.context.as_mut(),
+            PoField::Id => self.id.as_mut(),
+            PoField::Translation => self.translation.as_mut(),
+            PoField::Ignored => None,
+        }
+    }
+
+    /// Adds the entry to the texts, keyed by its context if it has one, or
+    /// else by its source
+    fn finish(self, texts: &mut BTreeMap<String, String>) {
+        let key = self.context.or(self.id).filter(|k| !k.is_empty());
+        let translation = self.translation.filter(|t| !t.is_empty());
+        // Fuzzy entries are guesses nobody checked yet
+        if let (Some(key), Some(translation), false) = (key, translation, self.fuzzy) {
+            texts.insert(key, translation);
+        }
+    }
+}
+
+/// Reads a line of a `.po` file into the entry, returning the field the
+/// next continuation line adds to
+fn parse_po_line(
+    line: &str,
+    entry: &mut PoEntry,
+    last: Option<PoField>,
+    texts: &mut BTreeMap<String, String>,
+) -> Result<Option<PoField>> {
+    if line.starts_with('"') {
+        let Some(field) = last else {
+            bail!("string continues no keyword");
+        };
+        let value = po_string(line)?;
+        if let Some(string) = entry.field(field) {
+            string.push_str(&value);
+        }
+        return Ok(last);
+    }
+    let (keyword, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+    let field = match keyword {
+        "msgctxt" => PoField::Context,
+        "msgid" => PoField::Id,
+        "msgstr" | "msgstr[0]" => PoField::Translation,
+        "msgid_plural" => PoField::Ignored,
+        _ if keyword.starts_with("msgstr[") => PoField::Ignored,
+        _ => bail!("unknown keyword `{keyword}`"),
+    };
+    // A new entry starts, even without a blank line before it
+    if matches!(field, PoField::Context | PoField::Id) && entry.translation.is_some() {
+        std::mem::take(entry).finish(texts);
+    }
+    let value = po_string(rest)?;
+    match field {
+        PoField::Context => entry.context = Some(value),
+        PoField::Id => entry.id = Some(value),
+        PoField::Translation => entry.translation = Some(value),
+        PoField::Ignored => {}
+    }
+    Ok(Some(field))
+}
+
+/// Reads the translations of a gettext catalogue, where an entry is keyed by
+/// the element id in its `msgctxt`, or in its `msgid` when it has none
+fn parse_po(contents: &str) -> Result<BTreeMap<String, String>> {
+    let mut texts = BTreeMap::new();
+    let mut entry = PoEntry::default();
+    let mut last = None;
+    for (number, line) in (1..).zip(contents.lines()) {
+        let line = line.trim();
+        if line.is_empty() {
+            std::mem::take(&mut entry).finish(&mut texts);
+            last = None;
+        } else if line.starts_with('#') {
+            // Comments belong to the entry after them
+            if entry.translation.is_some() {
+                std::mem::take(&mut entry).finish(&mut texts);
+                last = None;
+            }
+            if line.starts_with("#,") && line.contains("fuzzy") {
+                entry.fuzzy = true;
+            }
+        } else {
+            last = parse_po_line(line, &mut entry, last, &mut texts)
+                .wrap_err_with(|| format!("Line {number}: {line}"))?;
+        }
+    }
+    entry.finish(&mut texts);
+    Ok(texts)
+}
+
+/// Reads the translations of a JSON object of element ids to texts
+fn parse_json(contents: &str) -> Result<BTreeMap<String, String>> {
+    let json: serde_json::Map<String, serde_json::Value> =
+        serde_json::from_str(contents).wrap_err("Catalogue is not a JSON object")?;
+    json.into_iter()
+        .map(|(id, text)| match text {
+            serde_json::Value::String(text) => Ok((id, text)),
+            _ => bail!("Translation of `{id}` is not a string"),
+        })
+        .collect()
+}
+
+/// Name of the diagram without its extension, like `arch` for
+/// `arch.excalidraw.md`
+fn diagram_name(input_file: &Path) -> String {
+    let name = input_file
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    detect::from_name(&name)
+        .and_then(|(_, suffix)| name.strip_suffix(suffix))
+        .map_or_else(
+            || {
+                input_file
+                    .file_stem()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .into_owned()
+            },
+            ToOwned::to_owned,
+        )
+}
+
+/// Finds the catalogue of the diagram for the locale, in a directory of the
+/// locale with catalogues named after the diagrams, or else in a catalogue
+/// named after the locale
+pub fn load(dir: &Path, locale: &str, input_file: &Path) -> Result<Catalogue> {
+    let name = diagram_name(input_file);
+    let candidates = ["po", "json"]
+        .iter()
+        .map(|ext| dir.join(locale).join(format!("{name}.{ext}")))
+        .chain(
+            ["po", "json"]
+                .iter()
+                .map(|ext| dir.join(format!("{locale}.{ext}"))),
+        )
+        .collect::<Vec<PathBuf>>();
+    let Some(path) = candidates.iter().find(|p| p.is_file()) else {
+        bail!(
+            "No {locale} catalogue in {}, looked for {}",
+            dir.display(),
+            candidates
+                .iter()
+                .map(|p| p.display().to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    };
+    debug!(path = %path.display(), "Reading catalogue");
+    let contents = fs::read_to_string(path)
+        .wrap_err_with(|| format!("Failed to read file {}", path.display()))?;
+    let texts = if path.extension().is_some_and(|e| e == "po") {
+        parse_po(&contents)
+    } else {
+        parse_json(&contents)
+    }
+    .wrap_err_with(|| format!("Failed reading catalogue {}", path.display()))?;
+    Ok(Catalogue {
+        locale: locale.to_owned(),
+        texts,
+    })
+}
+
+/// Path of the output of a locale, like `arch.de.svg` for `arch.svg`
+fn localized_path(output_file: &Path, locale: &str) -> PathBuf {
+    let stem = output_file
+        .file_stem()
+        .unwrap_or_default()
+        .to_string_lossy();
+    let mut file_name = format!("{stem}.{locale}");
+    if let Some(extension) = output_file.extension() {
+        file_name.push('.');
+        file_name.push_str(&extension.to_string_lossy());
+    }
+    output_file.with_file_name(file_name)
+}
+
+/// Options that render the diagram in the locale, to its own output
+pub fn localize(opts: &cli::Opts, locale: &str) -> Result<cli::Opts> {
+    let dir = opts
+        .translations_dir
+        .as_deref()
+        .ok_or_else(|| eyre!("`--locale` needs a `--translations` directory"))?;
+    let catalogue = load(dir, locale, &opts.input_file)?;
+    info!(locale, count = catalogue.texts.len(), "Read translations");
+    Ok(cli::Opts {
+        output_file: localized_path(&opts.output_file, locale),
+        translation: Some(catalogue),
+        ..opts.clone()
+    })
+}
+
+fn report(catalogue: &Catalogue, missing: &[&str]) {
+    if !missing.is_empty() {
+        warn!(
+            locale = catalogue.locale,
+            ids = missing.join(", "),
+            "{} texts have no translation, they are left as they are",
+            missing.len()
+        );
+    }
+}
+
+/// Swaps the text elements of the scene for their translations
+pub fn translate_scene(scene: &mut Scene, catalogue: &Catalogue) {
+    let mut missing = vec![];
+    for element in scene.elements.iter_mut().filter(|e| !e.base.is_deleted) {
+        if !matches!(element.data, ElementData::Text(_)) {
+            continue;
+        }
+        match catalogue.texts.get(&element.id) {
+            Some(text) => template::replace_text(element, text.clone(), text.clone()),
+            None => missing.push(element.id.as_str()),
+        }
+    }
+    report(catalogue, &missing);
+}
+
+/// Swaps the labels of the cells for their translations, as HTML for the
+/// cells whose labels are
+pub fn translate_model(model: &mut GraphModel, catalogue: &Catalogue) {
+    let mut missing = vec![];
+    for cell in model.cells.iter_mut().filter(|c| c.visible) {
+        if cell.label().trim().is_empty() {
+            continue;
+        }
+        let Some(text) = catalogue.texts.get(&cell.id) else {
+            missing.push(cell.id.as_str());
+            continue;
+        };
+        cell.value = if cell.style.flag("html") {
+            escape_xml(text).replace('\n', "<br>")
+        } else {
+            text.clone()
+        };
+    }
+    report(catalogue, &missing);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn texts<const N: usize>(pairs: [(&str, &str); N]) -> BTreeMap<String, String> {
+        pairs
+            .iter()
+            .map(|&(k, v)| (k.to_owned(), v.to_owned()))
+            .collect()
+    }
+
+    #[test]
+    fn parse_po_keys_by_context_or_id() -> Result<()> {
+        let po = r#"
+msgid ""
+msgstr ""
+"Language: de\n"
+"Content-Type: text/plain; charset=UTF-8\n"
+
+# The title
+msgctxt "title"
+msgid "Architecture"
+msgstr "Architektur"
+
+msgid "Database"
+msgstr "Datenbank"
+msgctxt "note"
+msgid "Hello"
+msgstr "Hallo\tdu \"da\""
+"#;
+        assert_eq!(
+            parse_po(po)?,
+            texts([
+                ("title", "Architektur"),
+                ("Database", "Datenbank"),
+                ("note", "Hallo\tdu \"da\""),
+            ])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn parse_po_joins_continuation_lines() -> Result<()> {
+        let po = r#"
+msgctxt "label"
+msgid ""
+"Line one\n"
+"line two"
+msgstr ""
+"Zeile eins\n"
+"Zeile zwei"
+"#;
+        assert_eq!(parse_po(po)?, texts([("label", "Zeile eins\nZeile zwei")]));
+        Ok(())
+    }
+
+    #[test]
+    fn parse_po_skips_fuzzy_untranslated_and_plural_entries() -> Result<()> {
+        let po = r#"
+#, fuzzy
+msgctxt "guess"
+msgid "Guess"
+msgstr "Vermutung"
+
+msgctxt "todo"
+msgid "Todo"
+msgstr ""
+
+msgid "file"
+msgid_plural "files"
+msgstr[0] "Datei"
+msgstr[1] "Dateien"
+#, c-format
+msgctxt "kept"
+msgid "Kept"
+msgstr "Behalten"
+"#;
+        assert_eq!(
+            parse_po(po)?,
+            texts([("file", "Datei"), ("kept", "Behalten")])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn parse_po_reports_the_line() {
+        let error = parse_po("msgid \"a\"\nmsgstr \"b\nfoo \"c\"\n").map_err(|e| e.to_string());
+        assert_eq!(error, Err("Line 2: msgstr \"b".to_owned()));
+        assert!(parse_po("\"orphan\"\n").is_err());
+        assert!(parse_po("msgstr \"trailing \\\"\n").is_err());
+    }
+
+    #[test]
+    fn localized_path_adds_the_locale() {
+        assert_eq!(
+            localized_path(Path::new("out/arch.svg"), "de"),
+            Path::new("out/arch.de.svg")
+        );
+        assert_eq!(
+            localized_path(Path::new("arch"), "fr"),
+            Path::new("arch.fr")
+        );
+    }
+}
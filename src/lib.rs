@@ -0,0 +1,11 @@
+//! Parts of hdiag that are useful on their own, to read and write diagrams
+//! from other programs.
+
+#![deny(
+    clippy::enum_glob_use,
+    clippy::pedantic,
+    clippy::nursery,
+    clippy::unwrap_used
+)]
+
+pub mod scene;
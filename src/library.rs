@@ -0,0 +1,230 @@
+use std::fmt::Write as _;
+
+use color_eyre::{
+    eyre::{bail, WrapErr},
+    Result,
+};
+use tokio::runtime::Runtime;
+use tracing::info;
+
+use crate::{
+    backend::{DiagramBackend, Supports},
+    cli, excalidraw, svg, Output, Slugs,
+};
+
+/// Space around every item of the contact sheet
+const PADDING: f64 = 24.0;
+/// Space under every item of the contact sheet for its name
+const LABEL_HEIGHT: f64 = 28.0;
+const LABEL_FONT_SIZE: f64 = 14.0;
+
+pub struct Item {
+    /// Name of the item in the library, or its position if it has none
+    pub name: String,
+    /// Name that can be used in a file name, unique in the library
+    pub slug: String,
+    pub svg: String,
+}
+
+/// Renders every item of the library to its own svg
+pub async fn render_items(
+    input_contents: Vec<u8>,
+    output_format: &cli::FontFormat,
+    export_opts: cli::ExportOpts,
+) -> Result<Vec<Item>> {
+    let deterministic = export_opts.deterministic;
+    let raw_items = excalidraw::raw_library_svgs(input_contents, export_opts)
+        .await
+        .wrap_err("Failed getting svgs from excalidraw")?;
+    info!(
+        items = raw_items.len(),
+        "Finished rendering raw library svgs"
+    );
+
+    let mut slugs = Slugs::default();
+    let mut items = Vec::with_capacity(raw_items.len());
+    for (i, raw_item) in raw_items.into_iter().enumerate() {
+        let position = i + 1;
+        let name = raw_item
+            .name
+            .filter(|n| !n.trim().is_empty())
+            .unwrap_or_else(|| format!("item {position}"));
+        let slug = slugs.unique(&name, position);
+
+        let svg = excalidraw::apply_font_format(raw_item.svg, output_format)
+            .wrap_err_with(|| format!("Failed processing fonts of library item `{name}`"))?;
+        let svg = if deterministic {
+            svg::normalize_ids(&svg)
+        } else {
+            svg
+        };
+        items.push(Item { name, slug, svg });
+    }
+    Ok(items)
+}
+
+fn to_f64(n: usize) -> f64 {
+    f64::from(u32::try_from(n).expect("Library has less than 2^32 items"))
+}
+
+/// Lays out every item of the library in a grid, with its name under it
+pub fn contact_sheet(items: &[Item], export_opts: &cli::ExportOpts) -> Result<String> {
+    let sizes = items
+        .iter()
+        .map(|item| {
+            svg::dimensions(&item.svg)
+                .wrap_err_with(|| format!("Failed getting size of library item `{}`", item.name))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let max_width = sizes.iter().map(|s| s.0).fold(0.0, f64::max);
+    let max_height = sizes.iter().map(|s| s.1).fold(0.0, f64::max);
+
+    // As close to a square as we can get
+    let mut columns = 1;
+    while columns * columns < items.len() {
+        columns += 1;
+    }
+    let rows = items.len().div_ceil(columns).max(1);
+
+    let cell_width = 2.0f64.mul_add(PADDING, max_width);
+    let cell_height = 2.0f64.mul_add(PADDING, max_height) + LABEL_HEIGHT;
+    let width = to_f64(columns) * cell_width;
+    let height = to_f64(rows) * cell_height;
+
+    let (background, text_color) = match export_opts.theme {
+        cli::OutputTheme::Dark => ("#121212", "#e3e3e3"),
+        cli::OutputTheme::Light => ("#ffffff", "#1e1e1e"),
+    };
+
+    let mut sheet = format!(
+        "<svg version=\"1.1\" xmlns=\"http://www.w3.org/2000/svg\" \
+        viewBox=\"0 0 {width} {height}\" width=\"{width}\" height=\"{height}\">"
+    );
+    if export_opts.include_background {
+        write!(
+            sheet,
+            "<rect x=\"0\" y=\"0\" width=\"{width}\" height=\"{height}\" fill=\"{background}\"/>"
+        )
+        .wrap_err("Failed writing contact sheet background")?;
+    }
+
+    for (i, (item, (item_width, item_height))) in items.iter().zip(sizes).enumerate() {
+        let cell_x = to_f64(i % columns) * cell_width;
+        let cell_y = to_f64(i / columns) * cell_height;
+        let x = cell_x + (cell_width - item_width) / 2.0;
+        let y = cell_y + PADDING + (max_height - item_height) / 2.0;
+
+        // Items are rendered on their own, and have the same ids when
+        // they are deterministic. Slugs can start with a digit, which ids
+        // in CSS selectors can't
+        let item_svg = svg::prefix_ids(&item.svg, &format!("item-{}", item.slug));
+        let positioned = svg::with_position(&item_svg, x, y)
+            .wrap_err_with(|| format!("Failed positioning library item `{}`", item.name))?;
+        sheet.push_str(&positioned);
+
+        let label_x = cell_x + cell_width / 2.0;
+        let label_y = cell_y + PADDING + max_height + LABEL_HEIGHT / 2.0;
+        write!(
+            sheet,
+            "<text x=\"{label_x}\" y=\"{label_y}\" fill=\"{text_color}\" \
+            font-family=\"Helvetica, sans-serif\" font-size=\"{LABEL_FONT_SIZE}\" \
+            text-anchor=\"middle\" dominant-baseline=\"middle\">{}</text>",
+            svg::escape_xml(&item.name)
+        )
+        .wrap_err("Failed writing library item label")?;
+    }
+
+    sheet.push_str("</svg>");
+    Ok(sheet)
+}
+
+/// Renders a library as a contact sheet, or as one output per item
+pub async fn render(
+    input_contents: Vec<u8>,
+    output_format: &cli::FontFormat,
+    export_opts: cli::ExportOpts,
+    accessibility: Option<&cli::AccessibilityOpts>,
+    split: bool,
+) -> Result<Vec<Output>> {
+    let items = render_items(input_contents, output_format, export_opts.clone()).await?;
+
+    if split {
+        return items
+            .into_iter()
+            .map(|item| {
+                let svg = if accessibility.is_some() {
+                    svg::add_accessibility(&item.svg, &item.name, None)
+                        .wrap_err("Failed adding accessibility metadata to svg")?
+                } else {
+                    item.svg
+                };
+                Ok(Output {
+                    name: Some(item.slug),
+                    svg: svg.into_bytes(),
+                })
+            })
+            .collect();
+    }
+
+    let sheet = contact_sheet(&items, &export_opts).wrap_err("Failed laying out contact sheet")?;
+    info!("Finished laying out contact sheet");
+    let sheet = if let Some(accessibility) = accessibility {
+        let names = items.iter().map(|i| i.name.as_str()).collect::<Vec<_>>();
+        let description = accessibility
+            .description
+            .clone()
+            .unwrap_or_else(|| names.join("\n"));
+        svg::add_accessibility(&sheet, &accessibility.title, Some(&description))
+            .wrap_err("Failed adding accessibility metadata to svg")?
+    } else {
+        sheet
+    };
+
+    Ok(vec![Output {
+        name: None,
+        svg: sheet.into_bytes(),
+    }])
+}
+
+pub struct Library;
+
+impl DiagramBackend for Library {
+    fn name(&self) -> &'static str {
+        "library"
+    }
+
+    fn description(&self) -> &'static str {
+        "Excalidraw library (https://libraries.excalidraw.com)"
+    }
+
+    fn suffixes(&self) -> &'static [&'static str] {
+        &[".excalidrawlib"]
+    }
+
+    fn detect(&self, contents: &[u8]) -> Result<()> {
+        let kind = excalidraw::json_type(contents)?;
+        if kind != "excalidrawlib" {
+            bail!("JSON has type {kind:?}");
+        }
+        Ok(())
+    }
+
+    fn supports(&self) -> Supports {
+        Supports {
+            fonts: true,
+            items: true,
+            ..Supports::default()
+        }
+    }
+
+    fn render(&self, rt: &Runtime, opts: &cli::Opts, input_contents: &[u8]) -> Result<Vec<Output>> {
+        rt.block_on(render(
+            input_contents.to_vec(),
+            &opts.output_format,
+            opts.export.clone(),
+            opts.accessibility.as_ref(),
+            opts.split_library,
+        ))
+        .wrap_err("Failed rendering excalidraw library")
+    }
+}
@@ -0,0 +1,292 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt, fs,
+    path::Path,
+};
+
+use color_eyre::{
+    eyre::{bail, WrapErr},
+    Result,
+};
+use hdiag::scene::{self, BoundElement, Element, ElementData, FileData, Scene, FONT_FAMILY_VIRGIL};
+use serde_json::Map;
+use tracing::info;
+
+use crate::cli;
+
+/// Font families known to the bundled excalidraw
+const FONT_FAMILIES: [u32; 3] = [
+    scene::FONT_FAMILY_VIRGIL,
+    scene::FONT_FAMILY_HELVETICA,
+    scene::FONT_FAMILY_CASCADIA,
+];
+
+pub struct Problem {
+    element_id: String,
+    message: String,
+    fixed: bool,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "element {}: {}", self.element_id, self.message)?;
+        if self.fixed {
+            write!(f, " (fixed)")?;
+        }
+        Ok(())
+    }
+}
+
+struct Linter {
+    fix: bool,
+    problems: Vec<Problem>,
+}
+
+impl Linter {
+    /// Records a problem, and returns whether it should be fixed
+    fn report(&mut self, element_id: &str, message: String, fixable: bool) -> bool {
+        let fixed = self.fix && fixable;
+        self.problems.push(Problem {
+            element_id: element_id.to_owned(),
+            message,
+            fixed,
+        });
+        fixed
+    }
+
+    fn lint(&mut self, scene: &mut Scene) {
+        let mut seen = HashSet::new();
+        for element in &scene.elements {
+            if !seen.insert(element.id.as_str()) {
+                self.report(&element.id, "id is used by several elements".into(), false);
+            }
+        }
+
+        // Deleted elements are kept in the file, but are not drawn, so
+        // anything pointing at them points at nothing
+        let live = scene
+            .live_elements()
+            .map(|e| (e.id.clone(), e.is_frame()))
+            .collect();
+        let bound_to = scene
+            .elements
+            .iter()
+            .map(|e| {
+                let bound = e
+                    .base
+                    .bound_elements
+                    .iter()
+                    .flatten()
+                    .map(|b| b.id.clone())
+                    .collect();
+                (e.id.clone(), bound)
+            })
+            .collect();
+        let mut refs = References {
+            live,
+            bound_to,
+            files: scene.files.as_ref(),
+            missing_bindings: vec![],
+        };
+
+        for element in scene.elements.iter_mut().filter(|e| !e.base.is_deleted) {
+            self.lint_container(element, &mut refs);
+            self.lint_bound_elements(element, &refs);
+            self.lint_frame(element, &refs);
+            self.lint_bindings(element, &refs);
+            self.lint_image(element, &refs);
+            self.lint_font(element);
+        }
+
+        // Only done after, because it needs to change another element
+        for (container_id, text_id) in refs.missing_bindings {
+            let container = scene
+                .elements
+                .iter_mut()
+                .find(|e| e.id == container_id)
+                .expect("Container exists, it was checked before");
+            container
+                .base
+                .bound_elements
+                .get_or_insert_with(Vec::new)
+                .push(BoundElement {
+                    id: text_id,
+                    kind: "text".into(),
+                    extra: Map::new(),
+                });
+        }
+    }
+
+    fn lint_container(&mut self, element: &mut Element, refs: &mut References<'_>) {
+        let ElementData::Text(text) = &mut element.data else {
+            return;
+        };
+        let Some(container_id) = &text.container_id else {
+            return;
+        };
+
+        if !refs.live.contains_key(container_id) {
+            let message = format!("text is bound to missing container {container_id}");
+            if self.report(&element.id, message, true) {
+                text.container_id = None;
+            }
+        } else if !refs
+            .bound_to
+            .get(container_id)
+            .is_some_and(|bound| bound.contains(&element.id))
+        {
+            let message = format!("container {container_id} does not list its bound text");
+            if self.report(&element.id, message, true) {
+                refs.missing_bindings
+                    .push((container_id.clone(), element.id.clone()));
+            }
+        }
+    }
+
+    fn lint_bound_elements(&mut self, element: &mut Element, refs: &References<'_>) {
+        let Some(bound) = &mut element.base.bound_elements else {
+            return;
+        };
+
+        let mut dangling = vec![];
+        for b in bound.iter() {
+            if !refs.live.contains_key(&b.id) {
+                let message = format!("has missing bound element {}", b.id);
+                if self.report(&element.id, message, true) {
+                    dangling.push(b.id.clone());
+                }
+            }
+        }
+        bound.retain(|b| !dangling.contains(&b.id));
+    }
+
+    fn lint_frame(&mut self, element: &mut Element, refs: &References<'_>) {
+        let Some(frame_id) = &element.base.frame_id else {
+            return;
+        };
+
+        if refs.live.get(frame_id) != Some(&true) {
+            let message = format!("is in missing frame {frame_id}");
+            if self.report(&element.id, message, true) {
+                element.base.frame_id = None;
+            }
+        }
+    }
+
+    fn lint_bindings(&mut self, element: &mut Element, refs: &References<'_>) {
+        let (ElementData::Line(linear) | ElementData::Arrow(linear)) = &mut element.data else {
+            return;
+        };
+
+        for (end, binding) in [
+            ("start", &mut linear.start_binding),
+            ("end", &mut linear.end_binding),
+        ] {
+            let Some(b) = binding else {
+                continue;
+            };
+            if !refs.live.contains_key(&b.element_id) {
+                let message = format!("{end} is bound to missing element {}", b.element_id);
+                if self.report(&element.id, message, true) {
+                    *binding = None;
+                }
+            }
+        }
+    }
+
+    fn lint_image(&mut self, element: &Element, refs: &References<'_>) {
+        let ElementData::Image(image) = &element.data else {
+            return;
+        };
+
+        match &image.file_id {
+            Some(file_id) if !refs.files.is_some_and(|f| f.contains_key(file_id)) => {
+                let message = format!("image file {file_id} is not in the scene's files");
+                self.report(&element.id, message, false);
+            }
+            Some(_) => {}
+            None => {
+                self.report(&element.id, "image has no file".into(), false);
+            }
+        }
+    }
+
+    fn lint_font(&mut self, element: &mut Element) {
+        let ElementData::Text(text) = &mut element.data else {
+            return;
+        };
+
+        if FONT_FAMILIES.contains(&text.font_family) {
+            return;
+        }
+        let message = format!("text has unknown font family {}", text.font_family);
+        if self.report(&element.id, message, true) {
+            text.font_family = FONT_FAMILY_VIRGIL;
+        }
+    }
+}
+
+/// What elements in the scene can point to
+struct References<'a> {
+    /// Whether the elements that are not deleted are frames, by id
+    live: HashMap<String, bool>,
+    /// Ids of the elements bound to every element
+    bound_to: HashMap<String, HashSet<String>>,
+    files: Option<&'a BTreeMap<String, FileData>>,
+    /// Containers that need text added to their bound elements, found while
+    /// linting the text
+    missing_bindings: Vec<(String, String)>,
+}
+
+/// Lints a scene, returning the problems found, and the fixed scene if any
+/// were fixed
+fn lint_scene(contents: &[u8], fix: bool) -> Result<(Vec<Problem>, Option<String>)> {
+    let mut scene: Scene =
+        serde_json::from_slice(contents).wrap_err("File is not a valid excalidraw scene")?;
+
+    let mut linter = Linter {
+        fix,
+        problems: vec![],
+    };
+    linter.lint(&mut scene);
+
+    let fixed = if linter.problems.iter().any(|p| p.fixed) {
+        let mut fixed =
+            serde_json::to_string_pretty(&scene).wrap_err("Failed serializing fixed scene")?;
+        fixed.push('\n');
+        Some(fixed)
+    } else {
+        None
+    };
+    Ok((linter.problems, fixed))
+}
+
+fn lint_file(path: &Path, fix: bool) -> Result<usize> {
+    let contents =
+        fs::read(path).wrap_err_with(|| format!("Failed to read file {}", path.display()))?;
+    let (problems, fixed) = lint_scene(&contents, fix)?;
+
+    for problem in &problems {
+        println!("{}: {problem}", path.display());
+    }
+    if let Some(fixed) = fixed {
+        fs::write(path, fixed)
+            .wrap_err_with(|| format!("Failed to write fixed file {}", path.display()))?;
+        info!(path = %path.display(), "Saved fixed scene");
+    }
+
+    Ok(problems.iter().filter(|p| !p.fixed).count())
+}
+
+pub fn run(opts: &cli::LintOpts) -> Result<()> {
+    let mut remaining = 0;
+    for path in &opts.files {
+        remaining += lint_file(path, opts.fix)
+            .wrap_err_with(|| format!("Failed linting {}", path.display()))?;
+    }
+
+    if remaining > 0 {
+        bail!("Problems left unfixed: {remaining}");
+    }
+    Ok(())
+}
@@ -6,74 +6,323 @@
 )]
 
 use std::{
+    collections::HashSet,
     fs,
-    io::{Read as _, Write as _},
+    io::{self, Read as _, Write as _},
+    path::{Path, PathBuf},
 };
 
-use color_eyre::{eyre::WrapErr as _, Result};
-use tracing::info;
+use color_eyre::{
+    eyre::{bail, WrapErr as _},
+    Result,
+};
+use tokio::runtime::Runtime;
+use tracing::{info, warn};
 use tracing_subscriber::{layer::SubscriberExt as _, util::SubscriberInitExt as _};
 
+mod assets;
+mod backend;
+mod cache;
 mod cli;
+mod config;
+mod convert;
+mod detect;
+mod drawio;
+mod dsl;
+mod embedded;
 mod excalidraw;
+mod geometry;
+mod graphviz;
+mod i18n;
+mod library;
+mod lint;
+mod mdbook;
+mod mermaid;
+mod mxgraph;
+mod native;
+mod obsidian;
+mod plantuml;
+mod rough;
 mod serve_zip;
+mod share_link;
+mod svg;
+mod template;
+mod text;
 
 fn main() -> Result<()> {
-    let cli = cli::Opts::parse();
+    // Parsing can already create reports, while detecting the input type
+    color_eyre::install()?;
+    let command = cli::Command::parse();
 
     tracing_subscriber::registry()
         .with(
             tracing_subscriber::EnvFilter::try_from_default_env()
                 .unwrap_or_else(|_| "hdiag=debug".into()),
         )
-        .with(tracing_subscriber::fmt::layer())
+        // stdout is reserved for output, like the book for mdBook
+        .with(tracing_subscriber::fmt::layer().with_writer(io::stderr))
         .init();
-    color_eyre::install()?;
 
     let rt = tokio::runtime::Builder::new_multi_thread()
         .enable_all()
         .build()
         .wrap_err("Failed to build tokio runtime")?;
-    match cli.input_type {
-        cli::FileType::Excalidraw => {
-            let mut input_file = fs::OpenOptions::new()
-                .read(true)
-                .write(false)
-                .open(&cli.input_file)
-                .wrap_err_with(|| {
-                    format!(
-                        "Failed to open file {input}",
-                        input = cli.input_file.display()
-                    )
-                })?;
-
-            let input_contents = {
-                let mut buf = vec![];
-                input_file
-                    .read_to_end(&mut buf)
-                    .wrap_err("Failed reading file contents")?;
-                buf
-            };
-
-            let svg = rt.block_on(async move {
-                excalidraw::render_svg(input_contents, &cli.output_format, cli.export)
-                    .await
-                    .wrap_err("Failed rendering excalidraw svg")
-            })?;
-
-            let mut f = fs::OpenOptions::new()
-                .write(true)
-                .read(false)
-                .truncate(true)
-                .create(true)
-                .open(&cli.output_file)
-                .wrap_err("Failed to open output file")?;
-            f.write_all(&svg.into_bytes())
-                .wrap_err("Failed to write svg to file")?;
-
-            info!(output_path = %cli.output_file.display(), "Saved svg");
+    match command {
+        cli::Command::Render(cli) => render_file(&rt, &cli),
+        cli::Command::Mdbook(command) => mdbook::run(&rt, &command),
+        cli::Command::Lint(opts) => lint::run(&opts),
+        cli::Command::Convert(opts) => convert::run(&rt, &opts),
+        cli::Command::Text(opts) => text::run(&opts),
+    }
+}
+
+/// A rendered svg
+pub struct Output {
+    /// Set when a file renders to several outputs, to tell them apart
+    pub name: Option<String>,
+    pub svg: Vec<u8>,
+}
+
+impl Output {
+    /// The only output of a file
+    #[must_use]
+    pub fn single(svg: String) -> Vec<Self> {
+        vec![Self {
+            name: None,
+            svg: svg.into_bytes(),
+        }]
+    }
+}
+
+/// Turns a name into something that can be used in a file name
+fn slugify(name: &str) -> String {
+    let slug = name
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || c == '_' {
+                c
+            } else {
+                '-'
+            }
+        })
+        .collect::<String>();
+    slug.split('-')
+        .filter(|s| !s.is_empty())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// Names of outputs that can be used in file names, unique among each other
+#[derive(Default)]
+pub struct Slugs(HashSet<String>);
+
+impl Slugs {
+    /// A slug of the name, made unique with the position of what it names
+    /// when it is taken
+    pub fn unique(&mut self, name: &str, position: usize) -> String {
+        let mut slug = slugify(name);
+        if slug.is_empty() || !self.0.insert(slug.clone()) {
+            slug = format!("{slug}-{position}");
+            self.0.insert(slug.clone());
         }
-        cli::FileType::Drawio => todo!("Export drawio files"),
+        slug
+    }
+}
+
+/// Path to save an output to, `name` is appended to the stem of the path
+fn output_path(output_file: &Path, name: Option<&str>) -> PathBuf {
+    let Some(name) = name else {
+        return output_file.to_path_buf();
+    };
+    let stem = output_file
+        .file_stem()
+        .unwrap_or_default()
+        .to_string_lossy();
+    let mut file_name = format!("{stem}-{name}");
+    if let Some(extension) = output_file.extension() {
+        file_name.push('.');
+        file_name.push_str(&extension.to_string_lossy());
+    }
+    output_file.with_file_name(file_name)
+}
+
+fn render_file(rt: &Runtime, cli: &cli::Opts) -> Result<()> {
+    if cli.locales.is_empty() {
+        let stale = render_outputs(rt, cli)?;
+        return report_stale(&stale);
+    }
+    // Every locale is rendered or checked, even after one fails, so that all
+    // the problems are reported at once
+    let mut stale = vec![];
+    let mut failed = vec![];
+    for locale in &cli.locales {
+        match i18n::localize(cli, locale).and_then(|opts| render_outputs(rt, &opts)) {
+            Ok(locale_stale) => stale.extend(locale_stale),
+            Err(e) => {
+                warn!(locale, "Failed rendering: {e:#}");
+                failed.push(locale.as_str());
+            }
+        }
+    }
+    report_stale(&stale)?;
+    if !failed.is_empty() {
+        bail!("Failed rendering in {}", failed.join(", "));
+    }
+    Ok(())
+}
+
+/// Renders the file and writes its outputs, or checks them with `--check`,
+/// returning the ones that are out of date
+fn render_outputs(rt: &Runtime, cli: &cli::Opts) -> Result<Vec<PathBuf>> {
+    let input_contents = read_input(&cli.input_file)?;
+    let outputs = render(rt, cli, &input_contents)?;
+    let outputs = outputs
+        .iter()
+        .map(|o| {
+            (
+                output_path(&cli.output_file, o.name.as_deref()),
+                o.svg.as_slice(),
+            )
+        })
+        .collect::<Vec<_>>();
+
+    if cli.check {
+        check_outputs(&outputs)
+    } else {
+        outputs
+            .iter()
+            .try_for_each(|(path, svg)| write_output(path, svg))?;
+        Ok(vec![])
+    }
+}
+
+fn read_input(input_path: &Path) -> Result<Vec<u8>> {
+    let mut input_file = fs::OpenOptions::new()
+        .read(true)
+        .write(false)
+        .open(input_path)
+        .wrap_err_with(|| format!("Failed to open file {input}", input = input_path.display()))?;
+
+    let mut buf = vec![];
+    input_file
+        .read_to_end(&mut buf)
+        .wrap_err("Failed reading file contents")?;
+    Ok(buf)
+}
+
+fn render(rt: &Runtime, cli: &cli::Opts, input_contents: &[u8]) -> Result<Vec<Output>> {
+    let backend = cli.input_type.0;
+    if cli.cache_dir.is_some() && !backend.cacheable() {
+        info!(
+            "{} diagrams are rendered by an external program and never cached",
+            backend.name()
+        );
+    }
+    let cache = cli
+        .cache_dir
+        .as_deref()
+        .filter(|_| backend.cacheable())
+        .and_then(|dir| {
+            cache::Cache::new(dir)
+                .map_err(|e| warn!("Failed opening render cache, not caching: {e:#}"))
+                .ok()
+        });
+    let cache_key = cache::Key {
+        input_type: &cli.input_type,
+        input_contents,
+        output_format: &cli.output_format,
+        export: &cli.export,
+        accessibility: cli.accessibility.as_ref(),
+        split_library: cli.split_library,
+        pages: &cli.pages,
+        engine: cli.engine,
+        share_link: cli.share_key.as_deref(),
+        asset_dir: cli.asset_dir.as_deref(),
+        vars: &cli.vars,
+        translation: cli.translation.as_ref(),
+    };
+    let cached = cache.as_ref().and_then(|c| {
+        c.get(&cache_key).unwrap_or_else(|e| {
+            warn!("Failed reading from render cache, rendering again: {e:#}");
+            None
+        })
+    });
+    if let Some(outputs) = cached {
+        info!("Using cached render");
+        return Ok(outputs);
+    }
+
+    backend::warn_unsupported(backend, cli);
+    let outputs = backend.render(rt, cli, input_contents)?;
+
+    // The render succeeded, a cache that can't be written to only makes the
+    // next one slower
+    if let Some(cache) = &cache {
+        if let Err(e) = cache.put(&cache_key, &outputs) {
+            warn!("Failed writing to render cache: {e:#}");
+        }
+    }
+    Ok(outputs)
+}
+
+fn write_output(output_path: &Path, svg: &[u8]) -> Result<()> {
+    // The output directory of the config may not exist yet
+    if let Some(dir) = output_path.parent().filter(|d| !d.as_os_str().is_empty()) {
+        fs::create_dir_all(dir)
+            .wrap_err_with(|| format!("Failed to create directory {}", dir.display()))?;
+    }
+    let mut f = fs::OpenOptions::new()
+        .write(true)
+        .read(false)
+        .truncate(true)
+        .create(true)
+        .open(output_path)
+        .wrap_err("Failed to open output file")?;
+    f.write_all(svg).wrap_err("Failed to write svg to file")?;
+
+    info!(output_path = %output_path.display(), "Saved svg");
+    Ok(())
+}
+
+/// Compares rendered outputs against the ones already on disk, ignoring
+/// generated ids, and returns the ones that are missing or differ
+fn check_outputs(outputs: &[(PathBuf, &[u8])]) -> Result<Vec<PathBuf>> {
+    let mut stale = vec![];
+    for (output_path, svg) in outputs {
+        let up_to_date = match fs::read(output_path) {
+            Ok(existing) => {
+                // Ids are normalized before the accessibility metadata adds
+                // its own, so both sides are normalized again to compare
+                let existing = String::from_utf8_lossy(&existing);
+                let rendered = String::from_utf8_lossy(svg);
+                svg::normalize_ids(&existing) == svg::normalize_ids(&rendered)
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => false,
+            Err(e) => {
+                return Err(e).wrap_err_with(|| {
+                    format!("Failed to read output file {}", output_path.display())
+                })
+            }
+        };
+        if up_to_date {
+            info!(output_path = %output_path.display(), "Output is up to date");
+        } else {
+            warn!(output_path = %output_path.display(), "Output is out of date");
+            stale.push(output_path.clone());
+        }
+    }
+    Ok(stale)
+}
+
+/// Fails if any outputs are out of date
+fn report_stale(stale: &[PathBuf]) -> Result<()> {
+    if !stale.is_empty() {
+        let list = stale.iter().fold(String::new(), |mut list, p| {
+            list.push_str("\n    ");
+            list.push_str(&p.display().to_string());
+            list
+        });
+        bail!("{} outputs are out of date:{list}", stale.len());
     }
 
     Ok(())
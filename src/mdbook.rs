@@ -0,0 +1,321 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use clap::ValueEnum as _;
+use color_eyre::{
+    eyre::{eyre, ContextCompat, WrapErr},
+    Result,
+};
+use serde_json::Value;
+use tokio::runtime::Runtime;
+use tracing::info;
+
+use crate::{cli, detect, svg, template::Vars};
+
+/// Info string of the fenced code blocks that list diagrams to render
+const FENCE_INFO: &str = "hdiag";
+
+/// Options read from the `[preprocessor.hdiag]` table of `book.toml`
+struct Config {
+    src_dir: PathBuf,
+    /// Put the svg markup in the chapter instead of linking to a file
+    inline: bool,
+    theme: cli::OutputTheme,
+    background: bool,
+    cache_dir: PathBuf,
+}
+
+impl Config {
+    fn from_context(context: &Value) -> Result<Self> {
+        let root = context
+            .get("root")
+            .and_then(Value::as_str)
+            .context("Preprocessor context has no book root")?;
+        let root = PathBuf::from(root);
+        let config = &context["config"];
+        let src_dir = root.join(config["book"]["src"].as_str().unwrap_or("src"));
+
+        let table = &config["preprocessor"]["hdiag"];
+        let inline = table["inline"].as_bool().unwrap_or(false);
+        let background = table["background"].as_bool().unwrap_or(false);
+        let theme = match table["theme"].as_str() {
+            Some(theme) => cli::OutputTheme::from_str(theme, true)
+                .map_err(|e| eyre!("Invalid theme `{theme}` in book.toml: {e}"))?,
+            None => cli::OutputTheme::Dark,
+        };
+        let cache_dir = root.join(table["cache-dir"].as_str().unwrap_or(".hdiag-cache"));
+
+        Ok(Self {
+            src_dir,
+            inline,
+            theme,
+            background,
+            cache_dir,
+        })
+    }
+}
+
+pub fn run(rt: &Runtime, command: &cli::MdbookCommand) -> Result<()> {
+    match command {
+        cli::MdbookCommand::Supports { renderer } => {
+            // Every renderer can show an svg, linked or inline
+            info!(renderer, "Renderer is supported");
+            Ok(())
+        }
+        cli::MdbookCommand::Preprocess => preprocess(rt),
+    }
+}
+
+fn preprocess(rt: &Runtime) -> Result<()> {
+    let input: Value =
+        serde_json::from_reader(io::stdin().lock()).wrap_err("Failed to read book from stdin")?;
+    let Value::Array(input) = input else {
+        return Err(eyre!("Expected an array of [context, book] from mdBook"));
+    };
+    let [context, mut book] = <[Value; 2]>::try_from(input)
+        .map_err(|_| eyre!("Expected an array of [context, book] from mdBook"))?;
+
+    let preprocessor = Preprocessor {
+        rt,
+        config: Config::from_context(&context).wrap_err("Failed reading book config")?,
+    };
+    if let Some(sections) = book.get_mut("sections").and_then(Value::as_array_mut) {
+        preprocessor.process_items(sections)?;
+    }
+
+    serde_json::to_writer(io::stdout().lock(), &book).wrap_err("Failed to write book to stdout")
+}
+
+/// Whether the link is to a local file hdiag renders, from the suffixes of
+/// its formats
+fn is_diagram(target: &str) -> bool {
+    !target.contains("://") && detect::from_name(target).is_some()
+}
+
+/// Returns the fence marker and info string if the line opens a fenced code
+/// block
+fn code_fence(line: &str) -> Option<(&str, &str)> {
+    let line = line.trim();
+    let c = line.chars().next().filter(|c| matches!(c, '`' | '~'))?;
+    let len = line.len() - line.trim_start_matches(c).len();
+    if len < 3 {
+        return None;
+    }
+    Some((&line[..len], line[len..].trim()))
+}
+
+fn closes_fence(line: &str, marker: &str) -> bool {
+    let line = line.trim();
+    line.starts_with(marker) && line.trim_start_matches(&marker[..1]).is_empty()
+}
+
+struct Preprocessor<'a> {
+    rt: &'a Runtime,
+    config: Config,
+}
+
+impl Preprocessor<'_> {
+    fn process_items(&self, items: &mut [Value]) -> Result<()> {
+        for item in items {
+            let Some(chapter) = item.get_mut("Chapter") else {
+                continue;
+            };
+
+            // Draft chapters have no file, and so no content
+            if let Some(path) = chapter["path"].as_str() {
+                let chapter_dir = self
+                    .config
+                    .src_dir
+                    .join(path)
+                    .parent()
+                    .context("Chapter path has no parent")?
+                    .to_path_buf();
+                let content = chapter["content"]
+                    .as_str()
+                    .context("Chapter has no content")?;
+                let content = self
+                    .process_chapter(content, &chapter_dir)
+                    .wrap_err_with(|| format!("Failed processing chapter {path}"))?;
+                chapter["content"] = Value::String(content);
+            }
+
+            if let Some(sub_items) = chapter.get_mut("sub_items").and_then(Value::as_array_mut) {
+                self.process_items(sub_items)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn process_chapter(&self, content: &str, chapter_dir: &Path) -> Result<String> {
+        let mut output = String::with_capacity(content.len());
+        let mut lines = content.split_inclusive('\n');
+        while let Some(line) = lines.next() {
+            let Some((marker, info)) = code_fence(line) else {
+                output.push_str(&self.replace_image_links(line, chapter_dir)?);
+                continue;
+            };
+
+            if info != FENCE_INFO {
+                // Links in other code blocks are just code
+                output.push_str(line);
+                for line in lines.by_ref() {
+                    output.push_str(line);
+                    if closes_fence(line, marker) {
+                        break;
+                    }
+                }
+                continue;
+            }
+
+            let mut diagrams = vec![];
+            for line in lines.by_ref() {
+                if closes_fence(line, marker) {
+                    break;
+                }
+                let target = line.trim();
+                if !target.is_empty() {
+                    diagrams.push(self.render_diagram(target, "", chapter_dir)?);
+                }
+            }
+            output.push_str(&diagrams.join("\n\n"));
+            output.push('\n');
+        }
+        Ok(output)
+    }
+
+    fn replace_image_links(&self, line: &str, chapter_dir: &Path) -> Result<String> {
+        let mut output = String::with_capacity(line.len());
+        let mut slice = line;
+        while let Some(idx) = slice.find("![") {
+            let rest = &slice[idx..];
+            let Some(alt_end) = rest.find("](") else {
+                break;
+            };
+            let Some(target_end) = rest[alt_end..].find(')') else {
+                break;
+            };
+            let target_end = target_end + alt_end;
+
+            let alt = &rest[2..alt_end];
+            // Drop the title, if there is one
+            let target = rest[(alt_end + 2)..target_end]
+                .split_whitespace()
+                .next()
+                .unwrap_or_default();
+            if !is_diagram(target) {
+                output.push_str(&slice[..(idx + 2)]);
+                slice = &slice[(idx + 2)..];
+                continue;
+            }
+
+            output.push_str(&slice[..idx]);
+            output.push_str(&self.render_diagram(target, alt, chapter_dir)?);
+            slice = &rest[(target_end + 1)..];
+        }
+        output.push_str(slice);
+        Ok(output)
+    }
+
+    /// Renders the diagram, and returns the markdown to replace its link with
+    fn render_diagram(&self, target: &str, alt: &str, chapter_dir: &Path) -> Result<String> {
+        let input_file = chapter_dir.join(target);
+        let output_file = cli::with_output_extension(&input_file, "svg");
+        let title = if alt.is_empty() {
+            input_file
+                .file_stem()
+                .context("Diagram has no file name")?
+                .to_string_lossy()
+                .into_owned()
+        } else {
+            alt.to_owned()
+        };
+
+        let opts = cli::Opts {
+            input_type: detect::file_type(&input_file)?,
+            share_key: None,
+            input_file,
+            output_file,
+            output_format: cli::FontFormat::Embed,
+            font_format_set: false,
+            engine: cli::Engine::Browser,
+            export: cli::ExportOpts {
+                theme: self.config.theme,
+                include_background: self.config.background,
+                embed_source: false,
+                scale: 1,
+                // Unchanged diagrams have to give unchanged svgs, otherwise
+                // `mdbook serve` rebuilds forever
+                deterministic: true,
+            },
+            accessibility: Some(cli::AccessibilityOpts {
+                title,
+                description: None,
+            }),
+            cache_dir: Some(self.config.cache_dir.clone()),
+            asset_dir: None,
+            vars: Vars::new(),
+            locales: vec![],
+            translations_dir: None,
+            translation: None,
+            check: false,
+            split_library: false,
+            pages: cli::Pages::First,
+        };
+
+        let input_contents = crate::read_input(&opts.input_file)?;
+        let outputs = crate::render(self.rt, &opts, &input_contents)
+            .wrap_err_with(|| format!("Failed rendering {}", opts.input_file.display()))?;
+        let svg = outputs
+            .into_iter()
+            .next()
+            .context("Rendering the diagram gave no output")?
+            .svg;
+
+        if self.config.inline {
+            let svg = String::from_utf8(svg).wrap_err("Rendered svg was not valid UTF-8")?;
+            // Deterministic svgs have the same ids, which would clash with
+            // the other diagrams of the page
+            let svg = svg::prefix_ids(&svg, &format!("hdiag-{}", crate::slugify(target)));
+            // A blank line would end the html block in the middle of the svg
+            let svg = svg
+                .lines()
+                .filter(|l| !l.trim().is_empty())
+                .collect::<Vec<_>>()
+                .join("\n");
+            return Ok(format!("<div class=\"hdiag\">\n{svg}\n</div>"));
+        }
+
+        // Rewriting an unchanged file would also make `mdbook serve` rebuild
+        if fs::read(&opts.output_file).ok().as_deref() != Some(svg.as_slice()) {
+            crate::write_output(&opts.output_file, &svg)?;
+        }
+        let link = cli::with_output_extension(Path::new(target), "svg");
+        Ok(format!("![{alt}]({})", link.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn is_diagram_matches_every_format() {
+        for target in [
+            "arch.excalidraw",
+            "flow.drawio",
+            "flow.drawio.svg",
+            "note.excalidraw.md",
+            "seq.mmd",
+            "graph.dot",
+            "uml.puml",
+            "../diagrams/boxes.hdiag",
+        ] {
+            assert!(is_diagram(target), "{target}");
+        }
+        for target in ["photo.png", "plain.svg", "https://example.com/a.excalidraw"] {
+            assert!(!is_diagram(target), "{target}");
+        }
+    }
+}
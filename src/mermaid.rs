@@ -0,0 +1,198 @@
+use color_eyre::{
+    eyre::{bail, ContextCompat, WrapErr},
+    Result,
+};
+use serde::Deserialize;
+use tokio::runtime::Runtime;
+use tracing::info;
+
+use crate::{
+    assets::Assets,
+    backend::{DiagramBackend, Supports},
+    cli, serve_zip, svg, Output,
+};
+
+/// Version of the mermaid package bundled in the binary, a different version
+/// might render the same diagram differently
+pub const VERSION: &str = env!("HDIAG_MERMAID_VERSION");
+
+const MERMAID_APP_ASSETS: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/mermaid-app.zip"));
+
+/// Keywords a mermaid diagram starts with, naming its type
+const DIAGRAM_TYPES: [&str; 25] = [
+    "graph",
+    "flowchart",
+    "sequenceDiagram",
+    "classDiagram",
+    "classDiagram-v2",
+    "stateDiagram",
+    "stateDiagram-v2",
+    "erDiagram",
+    "journey",
+    "gantt",
+    "pie",
+    "quadrantChart",
+    "requirementDiagram",
+    "gitGraph",
+    "C4Context",
+    "C4Container",
+    "C4Component",
+    "C4Dynamic",
+    "C4Deployment",
+    "mindmap",
+    "timeline",
+    "zenuml",
+    "sankey-beta",
+    "xychart-beta",
+    "block-beta",
+];
+
+/// What the mermaid page returns
+#[derive(Deserialize)]
+struct RawSvg {
+    /// Unset when the diagram could not be parsed
+    svg: Option<String>,
+    /// Text of every label, read before they are turned into paths
+    texts: Vec<String>,
+    error: Option<String>,
+}
+
+/// An svg the mermaid page rendered
+struct Rendered {
+    svg: String,
+    texts: Vec<String>,
+}
+
+const fn font_format_name(output_format: &cli::FontFormat) -> &'static str {
+    match output_format {
+        cli::FontFormat::Raw => "raw",
+        cli::FontFormat::Embed => "embed",
+        cli::FontFormat::Path => "path",
+        cli::FontFormat::NoFont => "no-font",
+    }
+}
+
+async fn raw_svg(
+    input_contents: Vec<u8>,
+    output_format: &cli::FontFormat,
+    export_opts: &cli::ExportOpts,
+) -> Result<Rendered> {
+    let theme = match export_opts.theme {
+        cli::OutputTheme::Dark => "dark",
+        cli::OutputTheme::Light => "light",
+    };
+    let export_opts = serde_json::json!({
+        "theme": theme,
+        "exportBackground": export_opts.include_background,
+        "exportScale": export_opts.scale,
+        "fontFormat": font_format_name(output_format),
+        "deterministic": export_opts.deterministic,
+    });
+    let result = serve_zip::render_in_browser(
+        "mermaid",
+        "input.mmd",
+        input_contents,
+        MERMAID_APP_ASSETS,
+        export_opts,
+        Assets::new(),
+    )
+    .await
+    .wrap_err("Failed to get svg from mermaid app")?;
+
+    let raw: RawSvg =
+        serde_json::from_slice(&result).wrap_err("Response from mermaid was not valid")?;
+    match raw.svg {
+        Some(svg) => Ok(Rendered {
+            svg,
+            texts: raw.texts,
+        }),
+        None => bail!(
+            "Mermaid failed rendering the diagram: {}",
+            raw.error.unwrap_or_default()
+        ),
+    }
+}
+
+pub async fn render_svg(
+    input_contents: Vec<u8>,
+    output_format: &cli::FontFormat,
+    export_opts: cli::ExportOpts,
+    accessibility: Option<&cli::AccessibilityOpts>,
+) -> Result<String> {
+    let raw = raw_svg(input_contents, output_format, &export_opts)
+        .await
+        .wrap_err("Failed getting svg from mermaid")?;
+    info!("Finished rendering raw svg");
+
+    let svg = if export_opts.deterministic {
+        svg::normalize_ids(&raw.svg)
+    } else {
+        raw.svg
+    };
+
+    let Some(accessibility) = accessibility else {
+        return Ok(svg);
+    };
+    let description = accessibility
+        .description
+        .clone()
+        .or_else(|| Some(raw.texts.join("\n")).filter(|d| !d.is_empty()));
+    let svg = svg::add_accessibility(&svg, &accessibility.title, description.as_deref())
+        .wrap_err("Failed adding accessibility metadata to svg")?;
+    info!("Finished adding accessibility metadata to svg");
+    Ok(svg)
+}
+
+pub struct Mermaid;
+
+impl DiagramBackend for Mermaid {
+    fn name(&self) -> &'static str {
+        "mermaid"
+    }
+
+    fn description(&self) -> &'static str {
+        "Mermaid diagram (https://mermaid.js.org)"
+    }
+
+    fn suffixes(&self) -> &'static [&'static str] {
+        &[".mmd", ".mermaid"]
+    }
+
+    fn detect(&self, contents: &[u8]) -> Result<()> {
+        let source = std::str::from_utf8(contents).wrap_err("not UTF-8")?;
+        let mut lines = source.lines().map(str::trim);
+        // Skip the front matter with the diagram's config
+        if source.trim_start().starts_with("---") {
+            lines.find(|l| *l == "---");
+            lines.find(|l| *l == "---");
+        }
+        let first = lines
+            .find(|l| !l.is_empty() && !l.starts_with("%%"))
+            .context("text is empty")?;
+        let keyword = first.split_whitespace().next().unwrap_or_default();
+        // Graphviz graphs start with `graph` too, but then open a brace
+        if !DIAGRAM_TYPES.contains(&keyword) || first.contains('{') {
+            bail!("first line does not start with a diagram type");
+        }
+        Ok(())
+    }
+
+    fn supports(&self) -> Supports {
+        Supports {
+            fonts: true,
+            ..Supports::default()
+        }
+    }
+
+    fn render(&self, rt: &Runtime, opts: &cli::Opts, input_contents: &[u8]) -> Result<Vec<Output>> {
+        let svg = rt
+            .block_on(render_svg(
+                input_contents.to_vec(),
+                &opts.output_format,
+                opts.export.clone(),
+                opts.accessibility.as_ref(),
+            ))
+            .wrap_err("Failed rendering mermaid svg")?;
+        Ok(Output::single(svg))
+    }
+}
@@ -0,0 +1,1235 @@
+//! Renders draw.io diagrams to svg, drawing the cells the way mxGraph, the
+//! library draw.io is built on, does
+
+// The geometry reads closer to what it draws without `mul_add`
+#![allow(clippy::suboptimal_flops)]
+
+use std::collections::{BTreeSet, HashMap};
+
+use color_eyre::{
+    eyre::{bail, WrapErr},
+    Result,
+};
+use tokio::runtime::Runtime;
+use tracing::{info, warn};
+
+use crate::{
+    backend::{DiagramBackend, Supports},
+    cli, detect,
+    drawio::{self, Cell, CellKind, GraphModel, Style},
+    geometry::{Perimeter, Point, Rect},
+    i18n,
+    svg::{self, escape_xml, DARK_THEME_FILTER},
+    template, Output, Slugs,
+};
+
+/// Space around the diagram
+const BORDER: f64 = 1.0;
+pub const DEFAULT_FONT_SIZE: f64 = 12.0;
+const DEFAULT_FONT_FAMILY: &str = "Helvetica";
+pub const LINE_HEIGHT: f64 = 1.2;
+/// Rough width of a character relative to the font size, to wrap and place
+/// labels without measuring the text
+const CHAR_WIDTH: f64 = 0.55;
+const DEFAULT_SPACING: f64 = 2.0;
+const DEFAULT_MARKER_SIZE: f64 = 6.0;
+/// Default `arcSize` of rounded rectangles, in percent of the shorter side
+const DEFAULT_ARC_SIZE: f64 = 15.0;
+const DEFAULT_SWIMLANE_START_SIZE: f64 = 23.0;
+const DEFAULT_BACKGROUND: &str = "#ffffff";
+pub const DEFAULT_FILL: &str = "#ffffff";
+pub const DEFAULT_STROKE: &str = "#000000";
+pub const DEFAULT_FONT_COLOR: &str = "#000000";
+
+/// Smallest box around everything drawn
+struct Bounds {
+    min: Point,
+    max: Point,
+}
+
+impl Bounds {
+    const fn new() -> Self {
+        Self {
+            min: [f64::INFINITY; 2],
+            max: [f64::NEG_INFINITY; 2],
+        }
+    }
+
+    fn include(&mut self, [x, y]: Point) {
+        self.min = [self.min[0].min(x), self.min[1].min(y)];
+        self.max = [self.max[0].max(x), self.max[1].max(y)];
+    }
+
+    fn include_rect(&mut self, rect: Rect) {
+        self.include([rect.x, rect.y]);
+        self.include([rect.right(), rect.bottom()]);
+    }
+
+    fn rect(&self) -> Rect {
+        if self.min[0] > self.max[0] {
+            return Rect::default();
+        }
+        Rect {
+            x: self.min[0],
+            y: self.min[1],
+            width: self.max[0] - self.min[0],
+            height: self.max[1] - self.min[1],
+        }
+    }
+}
+
+/// Outline of the shape of the style
+fn perimeter_of(style: &Style) -> Perimeter {
+    match style.shape() {
+        "ellipse" | "doubleEllipse" => Perimeter::Ellipse,
+        "rhombus" => Perimeter::Rhombus,
+        _ => Perimeter::Rectangle,
+    }
+}
+
+/// One end of an edge
+struct Terminal {
+    rect: Rect,
+    perimeter: Perimeter,
+    /// Set when the edge is pinned to a point, instead of the closest point
+    /// on the outline
+    fixed: Option<Point>,
+}
+
+impl Terminal {
+    fn point(&self, toward: Point) -> Point {
+        self.fixed
+            .unwrap_or_else(|| self.perimeter.point(self.rect, toward))
+    }
+
+    fn on_horizontal_side(&self, [x, y]: Point) -> bool {
+        let near = |a: f64, b: f64| (a - b).abs() < 0.5;
+        (near(y, self.rect.y) || near(y, self.rect.bottom()))
+            && !near(x, self.rect.x)
+            && !near(x, self.rect.right())
+    }
+}
+
+fn fmt_points(points: &[Point]) -> String {
+    points
+        .iter()
+        .map(|[x, y]| format!("{x},{y}"))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn dash_array(style: &Style, stroke_width: f64) -> Option<String> {
+    if !style.flag("dashed") {
+        return None;
+    }
+    let pattern = style.get("dashPattern").unwrap_or("3 3");
+    let dashes = pattern
+        .split_whitespace()
+        .filter_map(|n| n.parse::<f64>().ok())
+        .map(|n| (n * stroke_width).to_string())
+        .collect::<Vec<_>>();
+    Some(dashes.join(" ")).filter(|d| !d.is_empty())
+}
+
+fn opacity_attr(style: &Style, key: &str, attr: &str) -> String {
+    let opacity = style.number(key, 100.0);
+    if opacity < 100.0 {
+        format!(" {attr}=\"{}\"", opacity / 100.0)
+    } else {
+        String::new()
+    }
+}
+
+/// Stroke attributes of a shape or an edge
+fn stroke_attrs(style: &Style, default_stroke: Option<&str>) -> String {
+    let Some(stroke) = style.color("strokeColor", default_stroke) else {
+        return " stroke=\"none\"".to_owned();
+    };
+    let stroke_width = style.number("strokeWidth", 1.0);
+    let mut attrs = format!(
+        " stroke=\"{}\" stroke-width=\"{stroke_width}\"",
+        escape_xml(&stroke)
+    );
+    if let Some(dashes) = dash_array(style, stroke_width) {
+        attrs.push_str(&format!(" stroke-dasharray=\"{dashes}\""));
+    }
+    attrs.push_str(&opacity_attr(style, "strokeOpacity", "stroke-opacity"));
+    attrs
+}
+
+/// Fill and stroke attributes of a shape
+fn paint(style: &Style, default_fill: Option<&str>, default_stroke: Option<&str>) -> String {
+    let fill = style.color("fillColor", default_fill);
+    format!(
+        " fill=\"{}\"{}{}",
+        escape_xml(fill.as_deref().unwrap_or("none")),
+        opacity_attr(style, "fillOpacity", "fill-opacity"),
+        stroke_attrs(style, default_stroke)
+    )
+}
+
+/// Default fill and stroke of a vertex, from the default stylesheet
+fn vertex_paint_defaults(style: &Style) -> (Option<&'static str>, Option<&'static str>) {
+    let unpainted = ["text", "edgeLabel", "group"]
+        .iter()
+        .any(|name| style.has_name(name));
+    match style.shape() {
+        _ if unpainted => (None, None),
+        "image" => (None, None),
+        "line" => (None, Some(DEFAULT_STROKE)),
+        _ => (Some(DEFAULT_FILL), Some(DEFAULT_STROKE)),
+    }
+}
+
+pub fn text_width(text: &str, font_size: f64) -> f64 {
+    f64::from(u32::try_from(text.chars().count()).unwrap_or(u32::MAX)) * font_size * CHAR_WIDTH
+}
+
+/// Breaks the lines so they fit in the width, between words
+fn wrap_lines(text: &str, width: f64, font_size: f64) -> Vec<String> {
+    let mut lines = vec![];
+    for paragraph in text.lines() {
+        let mut line = String::new();
+        for word in paragraph.split(' ') {
+            let candidate = if line.is_empty() {
+                word.to_owned()
+            } else {
+                format!("{line} {word}")
+            };
+            if !line.is_empty() && text_width(&candidate, font_size) > width {
+                lines.push(std::mem::replace(&mut line, word.to_owned()));
+            } else {
+                line = candidate;
+            }
+        }
+        lines.push(line);
+    }
+    lines
+}
+
+/// The point a fraction of the way along the route
+pub fn point_along(route: &[Point], fraction: f64) -> Point {
+    let segment_length = |[a, b]: [Point; 2]| (b[0] - a[0]).hypot(b[1] - a[1]);
+    let segments = route.windows(2).map(|w| [w[0], w[1]]);
+    let total = segments.clone().map(segment_length).sum::<f64>();
+    let mut remaining = total * fraction.clamp(0.0, 1.0);
+    for [a, b] in segments {
+        let length = segment_length([a, b]);
+        if remaining <= length && length > 0.0 {
+            let t = remaining / length;
+            return [a[0] + (b[0] - a[0]) * t, a[1] + (b[1] - a[1]) * t];
+        }
+        remaining -= length;
+    }
+    route.last().copied().unwrap_or_default()
+}
+
+/// The arrow at an end of an edge, and where the line should stop so it
+/// doesn't poke through
+struct Marker {
+    svg: String,
+    line_end: Point,
+}
+
+fn marker(
+    style: &Style,
+    start: bool,
+    tip: Point,
+    from: Point,
+    unsupported: &mut BTreeSet<String>,
+) -> Option<Marker> {
+    let (kind_key, size_key, fill_key, default) = if start {
+        ("startArrow", "startSize", "startFill", "none")
+    } else {
+        ("endArrow", "endSize", "endFill", "classic")
+    };
+    let kind = style.get(kind_key).unwrap_or(default);
+    let length = (tip[0] - from[0]).hypot(tip[1] - from[1]);
+    if kind == "none" || kind.is_empty() || length < f64::EPSILON {
+        return None;
+    }
+    let stroke = style.color("strokeColor", Some(DEFAULT_STROKE))?;
+    let stroke_width = style.number("strokeWidth", 1.0);
+    // mxGraph makes markers longer for thicker lines
+    let unit = style.number(size_key, DEFAULT_MARKER_SIZE) + stroke_width;
+    let (ux, uy) = (
+        (tip[0] - from[0]) / length * unit,
+        (tip[1] - from[1]) / length * unit,
+    );
+    let [x, y] = tip;
+    let width_factor = if kind.ends_with("Thin") { 3.0 } else { 2.0 };
+    let (nx, ny) = (uy / width_factor, ux / width_factor);
+    let back = |fraction: f64| [x - ux * fraction, y - uy * fraction];
+
+    let (path, closed, line_end) = match kind {
+        "open" | "openThin" => (
+            format!(
+                "M {} {} L {x} {y} L {} {}",
+                x - ux - nx,
+                y - uy + ny,
+                x - ux + nx,
+                y - uy - ny
+            ),
+            false,
+            tip,
+        ),
+        "oval" => {
+            let radius = unit / 2.0;
+            let [cx, cy] = back(0.5);
+            (
+                format!(
+                    "M {} {cy} a {radius} {radius} 0 1 0 {} 0 a {radius} {radius} 0 1 0 {} 0 Z",
+                    cx - radius,
+                    radius * 2.0,
+                    -radius * 2.0
+                ),
+                true,
+                back(1.0),
+            )
+        }
+        "diamond" | "diamondThin" => {
+            let [mx, my] = back(0.5);
+            let [bx, by] = back(1.0);
+            (
+                format!(
+                    "M {x} {y} L {} {} L {bx} {by} L {} {} Z",
+                    mx - nx,
+                    my + ny,
+                    mx + nx,
+                    my - ny
+                ),
+                true,
+                back(1.0),
+            )
+        }
+        _ => {
+            if !matches!(kind, "classic" | "classicThin" | "block" | "blockThin") {
+                unsupported.insert(format!("{kind} arrow"));
+            }
+            let classic = !kind.starts_with("block");
+            let notch = if classic {
+                let [mx, my] = back(0.75);
+                format!(" L {mx} {my}")
+            } else {
+                String::new()
+            };
+            (
+                format!(
+                    "M {x} {y} L {} {}{notch} L {} {} Z",
+                    x - ux - nx,
+                    y - uy + ny,
+                    x - ux + nx,
+                    y - uy - ny
+                ),
+                true,
+                back(if classic { 0.75 } else { 1.0 }),
+            )
+        }
+    };
+    let fill = if closed && style.get(fill_key) != Some("0") {
+        escape_xml(&stroke)
+    } else {
+        "none".to_owned()
+    };
+    Some(Marker {
+        svg: format!(
+            "<path d=\"{path}\" fill=\"{fill}\" stroke=\"{}\" stroke-width=\"{stroke_width}\" \
+             stroke-miterlimit=\"10\"/>",
+            escape_xml(&stroke)
+        ),
+        line_end,
+    })
+}
+
+/// Connects the points with horizontal and vertical segments
+fn orthogonal_route(source: &Terminal, target: &Terminal, waypoints: &[Point]) -> Vec<Point> {
+    let (s, t) = (source.rect, target.rect);
+    if waypoints.is_empty() && source.fixed.is_none() && target.fixed.is_none() {
+        let overlap_x = (s.x.max(t.x), s.right().min(t.right()));
+        let overlap_y = (s.y.max(t.y), s.bottom().min(t.bottom()));
+        if overlap_x.0 <= overlap_x.1 && (t.y > s.bottom() || s.y > t.bottom()) {
+            let x = (overlap_x.0 + overlap_x.1) / 2.0;
+            let (from, to) = if t.y > s.bottom() {
+                (s.bottom(), t.y)
+            } else {
+                (s.y, t.bottom())
+            };
+            return vec![[x, from], [x, to]];
+        }
+        if overlap_y.0 <= overlap_y.1 && (t.x > s.right() || s.x > t.right()) {
+            let y = (overlap_y.0 + overlap_y.1) / 2.0;
+            let (from, to) = if t.x > s.right() {
+                (s.right(), t.x)
+            } else {
+                (s.x, t.right())
+            };
+            return vec![[from, y], [to, y]];
+        }
+    }
+
+    let next = waypoints
+        .first()
+        .copied()
+        .unwrap_or_else(|| target.rect.center());
+    let start = source.fixed.unwrap_or_else(|| side_point(s, next));
+    let previous = waypoints.last().copied().unwrap_or(start);
+    let end = target.fixed.unwrap_or_else(|| side_point(t, previous));
+
+    let mut points = vec![start];
+    points.extend_from_slice(waypoints);
+    points.push(end);
+    let last = points.len() - 2;
+    let mut route = vec![start];
+    for (i, pair) in points.windows(2).enumerate() {
+        let ([ax, ay], [bx, by]) = (pair[0], pair[1]);
+        if (ax - bx).abs() > f64::EPSILON && (ay - by).abs() > f64::EPSILON {
+            // Leave and enter the cells at a right angle to their sides
+            let vertical_first = i == 0 && source.on_horizontal_side(start);
+            let horizontal_last = i == last && !target.on_horizontal_side(end);
+            route.push(if vertical_first || horizontal_last {
+                [ax, by]
+            } else {
+                [bx, ay]
+            });
+        }
+        route.push([bx, by]);
+    }
+    route
+}
+
+/// The middle of the side of the rect that faces the point, or the point
+/// straight across from it when it is level with the rect
+fn side_point(rect: Rect, [x, y]: Point) -> Point {
+    if (rect.x..=rect.right()).contains(&x) && rect.height > 0.0 {
+        return [x, if y < rect.y { rect.y } else { rect.bottom() }];
+    }
+    let side_x = if x < rect.x { rect.x } else { rect.right() };
+    if (rect.y..=rect.bottom()).contains(&y) {
+        [side_x, y]
+    } else {
+        [side_x, rect.center()[1]]
+    }
+}
+
+struct Renderer<'a> {
+    cells: HashMap<&'a str, &'a Cell>,
+    children: HashMap<&'a str, Vec<&'a Cell>>,
+    /// Where each vertex is in the diagram, after adding the positions of
+    /// the cells it is in
+    boxes: HashMap<&'a str, Rect>,
+    background: String,
+    bounds: Bounds,
+    /// Labels in the order they are drawn, to describe the diagram
+    texts: Vec<String>,
+    unsupported: BTreeSet<String>,
+}
+
+impl<'a> Renderer<'a> {
+    fn new(model: &'a GraphModel, background: String) -> Self {
+        let mut renderer = Self {
+            cells: model.cells.iter().map(|c| (c.id.as_str(), c)).collect(),
+            children: HashMap::new(),
+            boxes: HashMap::new(),
+            background,
+            bounds: Bounds::new(),
+            texts: vec![],
+            unsupported: BTreeSet::new(),
+        };
+        for cell in &model.cells {
+            let parent = cell.parent.as_deref().unwrap_or_default();
+            renderer.children.entry(parent).or_default().push(cell);
+        }
+        renderer.layout("", [0.0, 0.0], 0);
+        renderer
+    }
+
+    fn children(&self, id: &str) -> Vec<&'a Cell> {
+        self.children.get(id).cloned().unwrap_or_default()
+    }
+
+    /// Whether the cells at the depth can only be reached through a loop of
+    /// parents, which would never end
+    fn too_deep(&self, depth: usize) -> bool {
+        depth > self.cells.len()
+    }
+
+    fn layout(&mut self, parent: &str, origin: Point, depth: usize) {
+        if self.too_deep(depth) {
+            return;
+        }
+        for cell in self.children(parent) {
+            let mut child_origin = origin;
+            if let (CellKind::Vertex, Some(geometry)) = (cell.kind, &cell.geometry) {
+                if !geometry.relative {
+                    let rect = Rect {
+                        x: origin[0] + geometry.x,
+                        y: origin[1] + geometry.y,
+                        width: geometry.width,
+                        height: geometry.height,
+                    };
+                    self.boxes.insert(&cell.id, rect);
+                    child_origin = [rect.x, rect.y];
+                }
+            }
+            self.layout(&cell.id, child_origin, depth + 1);
+        }
+    }
+
+    fn draw(
+        &mut self,
+        parent: &str,
+        origin: Point,
+        route: Option<&[Point]>,
+        depth: usize,
+        svg: &mut String,
+    ) {
+        if self.too_deep(depth) {
+            return;
+        }
+        for cell in self.children(parent) {
+            if !cell.visible {
+                continue;
+            }
+            match cell.kind {
+                CellKind::Vertex => {
+                    let child_origin = if let Some(rect) = self.boxes.get(cell.id.as_str()) {
+                        let rect = *rect;
+                        svg.push_str(&self.vertex(cell, rect));
+                        [rect.x, rect.y]
+                    } else {
+                        if let Some(route) = route {
+                            svg.push_str(&self.edge_label(cell, route));
+                        }
+                        origin
+                    };
+                    self.draw(&cell.id, child_origin, None, depth + 1, svg);
+                }
+                CellKind::Edge => {
+                    let route = self.edge(cell, origin, svg);
+                    self.draw(&cell.id, origin, route.as_deref(), depth + 1, svg);
+                }
+                CellKind::Container => self.draw(&cell.id, origin, None, depth + 1, svg),
+            }
+        }
+    }
+
+    fn vertex(&mut self, cell: &Cell, rect: Rect) -> String {
+        let style = &cell.style;
+        let shape = self.shape(style, rect);
+        let label_box = if style.shape() == "swimlane" {
+            swimlane_header(style, rect)
+        } else {
+            rect
+        };
+        let label = self.label(cell, label_box, false);
+
+        let rotation = style.number("rotation", 0.0);
+        let [cx, cy] = rect.center();
+        let (sin, cos) = rotation.to_radians().sin_cos();
+        for [x, y] in [
+            [rect.x, rect.y],
+            [rect.right(), rect.y],
+            [rect.right(), rect.bottom()],
+            [rect.x, rect.bottom()],
+        ] {
+            let (dx, dy) = (x - cx, y - cy);
+            self.bounds
+                .include([cx + dx * cos - dy * sin, cy + dx * sin + dy * cos]);
+        }
+
+        let mut attrs = String::new();
+        if rotation.abs() > f64::EPSILON {
+            attrs.push_str(&format!(" transform=\"rotate({rotation} {cx} {cy})\""));
+        }
+        attrs.push_str(&opacity_attr(style, "opacity", "opacity"));
+        format!("<g{attrs}>{shape}{label}</g>")
+    }
+
+    fn shape(&mut self, style: &Style, rect: Rect) -> String {
+        let (default_fill, default_stroke) = vertex_paint_defaults(style);
+        let paint = paint(style, default_fill, default_stroke);
+        let Rect {
+            x,
+            y,
+            width: w,
+            height: h,
+        } = rect;
+        let [cx, cy] = rect.center();
+        let polygon =
+            |points: &[Point]| format!("<polygon points=\"{}\"{paint}/>", fmt_points(points));
+
+        match style.shape() {
+            "ellipse" => format!(
+                "<ellipse cx=\"{cx}\" cy=\"{cy}\" rx=\"{}\" ry=\"{}\"{paint}/>",
+                w / 2.0,
+                h / 2.0
+            ),
+            "doubleEllipse" => {
+                let inset = 4.0_f64.min(w / 4.0).min(h / 4.0);
+                format!(
+                    "<ellipse cx=\"{cx}\" cy=\"{cy}\" rx=\"{}\" ry=\"{}\"{paint}/>\
+                     <ellipse cx=\"{cx}\" cy=\"{cy}\" rx=\"{}\" ry=\"{}\"{paint}/>",
+                    w / 2.0,
+                    h / 2.0,
+                    w / 2.0 - inset,
+                    h / 2.0 - inset
+                )
+            }
+            "rhombus" => polygon(&[[cx, y], [x + w, cy], [cx, y + h], [x, cy]]),
+            "triangle" => polygon(&match style.get("direction") {
+                Some("north") => [[x, y + h], [cx, y], [x + w, y + h]],
+                Some("south") => [[x, y], [cx, y + h], [x + w, y]],
+                Some("west") => [[x + w, y], [x, cy], [x + w, y + h]],
+                _ => [[x, y], [x + w, cy], [x, y + h]],
+            }),
+            "hexagon" => {
+                let size = style.number("size", 0.25) * w;
+                polygon(&[
+                    [x + size, y],
+                    [x + w - size, y],
+                    [x + w, cy],
+                    [x + w - size, y + h],
+                    [x + size, y + h],
+                    [x, cy],
+                ])
+            }
+            "parallelogram" => {
+                let size = style.number("size", 0.2) * w;
+                polygon(&[[x + size, y], [x + w, y], [x + w - size, y + h], [x, y + h]])
+            }
+            "cylinder" | "cylinder3" => cylinder(style, rect, &paint, default_stroke),
+            "swimlane" => swimlane(style, rect, &paint, default_stroke),
+            "line" => format!("<path d=\"M {x} {cy} L {} {cy}\"{paint}/>", x + w),
+            "image" => image(style, rect),
+            "rectangle" | "label" | "text" => rectangle(style, rect, &paint),
+            other => {
+                self.unsupported.insert(format!("{other} shape"));
+                rectangle(style, rect, &paint)
+            }
+        }
+    }
+
+    fn label(&mut self, cell: &Cell, rect: Rect, on_edge: bool) -> String {
+        let style = &cell.style;
+        let text = cell.label();
+        if text.trim().is_empty() || style.flag("noLabel") {
+            return String::new();
+        }
+        self.texts.push(text.trim().to_owned());
+
+        let font_size = style.number("fontSize", DEFAULT_FONT_SIZE);
+        let line_height = font_size * LINE_HEIGHT;
+        let rect = Rect {
+            x: match style.get("labelPosition") {
+                Some("left") => rect.x - rect.width,
+                Some("right") => rect.right(),
+                _ => rect.x,
+            },
+            y: match style.get("verticalLabelPosition") {
+                Some("top") => rect.y - rect.height,
+                Some("bottom") => rect.bottom(),
+                _ => rect.y,
+            },
+            ..rect
+        };
+        let spacing = style.number("spacing", DEFAULT_SPACING);
+        let spacing_of = |side: &str| spacing + style.number(side, 0.0);
+
+        let lines = if !on_edge && style.get("whiteSpace") == Some("wrap") {
+            let width = rect.width - spacing_of("spacingLeft") - spacing_of("spacingRight");
+            wrap_lines(&text, width, font_size)
+        } else {
+            text.lines().map(ToOwned::to_owned).collect()
+        };
+        let block_width = lines
+            .iter()
+            .map(|line| text_width(line, font_size))
+            .fold(0.0, f64::max);
+        let block_height = lines.iter().map(|_| line_height).sum::<f64>();
+
+        let (x, anchor, block_x) = match style.get("align") {
+            Some("left") => {
+                let x = rect.x + spacing_of("spacingLeft");
+                (x, "start", x)
+            }
+            Some("right") => {
+                let x = rect.right() - spacing_of("spacingRight");
+                (x, "end", x - block_width)
+            }
+            _ => {
+                let x = rect.center()[0];
+                (x, "middle", x - block_width / 2.0)
+            }
+        };
+        let top = match style.get("verticalAlign") {
+            Some("top") => rect.y + spacing_of("spacingTop"),
+            Some("bottom") => rect.bottom() - spacing_of("spacingBottom") - block_height,
+            _ => rect.y + (rect.height - block_height) / 2.0,
+        };
+        let block = Rect {
+            x: block_x,
+            y: top,
+            width: block_width,
+            height: block_height,
+        };
+        self.bounds.include_rect(block);
+
+        let mut svg = self.label_background(style, block, on_edge);
+        svg.push_str(&label_text_group(style, font_size, anchor));
+        let mut y = top + (line_height - font_size) / 2.0;
+        for line in lines {
+            svg.push_str(&format!(
+                "<text x=\"{x}\" y=\"{y}\">{}</text>",
+                escape_xml(&line)
+            ));
+            y += line_height;
+        }
+        svg.push_str("</g>");
+        svg
+    }
+
+    fn label_background(&self, style: &Style, block: Rect, on_edge: bool) -> String {
+        // Labels on edges hide the line behind them by default
+        let default = on_edge.then_some(self.background.as_str());
+        let fill = style.color("labelBackgroundColor", default);
+        let stroke = style.color("labelBorderColor", None);
+        if fill.is_none() && stroke.is_none() {
+            return String::new();
+        }
+        format!(
+            "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"{}\" stroke=\"{}\"/>",
+            block.x - 1.0,
+            block.y - 1.0,
+            block.width + 2.0,
+            block.height + 2.0,
+            escape_xml(fill.as_deref().unwrap_or("none")),
+            escape_xml(stroke.as_deref().unwrap_or("none"))
+        )
+    }
+
+    fn terminal(&self, edge: &Cell, source: bool, origin: Point) -> Option<Terminal> {
+        let (id, point, prefix) = if source {
+            (
+                &edge.source,
+                edge.geometry.as_ref().and_then(|g| g.source_point),
+                "exit",
+            )
+        } else {
+            (
+                &edge.target,
+                edge.geometry.as_ref().and_then(|g| g.target_point),
+                "entry",
+            )
+        };
+        let cell = id
+            .as_deref()
+            .and_then(|id| self.boxes.get(id).map(|rect| (id, *rect)));
+        if let Some((id, rect)) = cell {
+            let style = self.cells.get(id).map(|c| &c.style);
+            let constraint = |axis: &str| {
+                edge.style
+                    .get(&format!("{prefix}{axis}"))
+                    .and_then(|n| n.parse::<f64>().ok())
+            };
+            let fixed = constraint("X").zip(constraint("Y")).map(|(fx, fy)| {
+                [
+                    rect.x + fx * rect.width + edge.style.number(&format!("{prefix}Dx"), 0.0),
+                    rect.y + fy * rect.height + edge.style.number(&format!("{prefix}Dy"), 0.0),
+                ]
+            });
+            return Some(Terminal {
+                rect,
+                perimeter: style.map_or(Perimeter::Rectangle, perimeter_of),
+                fixed,
+            });
+        }
+        point.map(|[x, y]| {
+            let point = [origin[0] + x, origin[1] + y];
+            Terminal {
+                rect: Rect::at(point),
+                perimeter: Perimeter::Rectangle,
+                fixed: Some(point),
+            }
+        })
+    }
+
+    fn route(&self, edge: &Cell, origin: Point) -> Option<Vec<Point>> {
+        let source = self.terminal(edge, true, origin)?;
+        let target = self.terminal(edge, false, origin)?;
+        let waypoints = edge
+            .geometry
+            .iter()
+            .flat_map(|g| &g.points)
+            .map(|[x, y]| [origin[0] + x, origin[1] + y])
+            .collect::<Vec<_>>();
+
+        let orthogonal = matches!(
+            edge.style.get("edgeStyle"),
+            Some(
+                "orthogonalEdgeStyle"
+                    | "elbowEdgeStyle"
+                    | "entityRelationEdgeStyle"
+                    | "segmentEdgeStyle"
+            )
+        );
+        if orthogonal {
+            return Some(orthogonal_route(&source, &target, &waypoints));
+        }
+        let start = source.point(
+            waypoints
+                .first()
+                .copied()
+                .unwrap_or_else(|| target.fixed.unwrap_or_else(|| target.rect.center())),
+        );
+        let end = target.point(waypoints.last().copied().unwrap_or(start));
+        let mut route = vec![start];
+        route.extend(waypoints);
+        route.push(end);
+        Some(route)
+    }
+
+    /// Draws the edge, and returns the route it takes for its labels
+    fn edge(&mut self, cell: &Cell, origin: Point, svg: &mut String) -> Option<Vec<Point>> {
+        let Some(mut route) = self.route(cell, origin) else {
+            warn!(edge = cell.id, "Edge has no ends, and is not drawn");
+            return None;
+        };
+        route.dedup();
+        if route.len() < 2 {
+            return None;
+        }
+        let style = &cell.style;
+        let mut markers = String::new();
+        for start in [true, false] {
+            let (tip, from) = if start {
+                (route[0], route[1])
+            } else {
+                (route[route.len() - 1], route[route.len() - 2])
+            };
+            if let Some(marker) = marker(style, start, tip, from, &mut self.unsupported) {
+                markers.push_str(&marker.svg);
+                let end = if start { 0 } else { route.len() - 1 };
+                route[end] = marker.line_end;
+            }
+        }
+        for point in &route {
+            self.bounds.include(*point);
+        }
+
+        let path = route
+            .iter()
+            .enumerate()
+            .map(|(i, [x, y])| format!("{} {x} {y}", if i == 0 { "M" } else { "L" }))
+            .collect::<Vec<_>>()
+            .join(" ");
+        svg.push_str(&format!(
+            "<g{}><path d=\"{path}\" fill=\"none\"{} stroke-miterlimit=\"10\"/>{markers}",
+            opacity_attr(style, "opacity", "opacity"),
+            stroke_attrs(style, Some(DEFAULT_STROKE))
+        ));
+        svg.push_str(&self.edge_label(cell, &route));
+        svg.push_str("</g>");
+        Some(route)
+    }
+
+    /// Draws the label of an edge, or of a label cell on an edge, along the
+    /// route of the edge
+    fn edge_label(&mut self, cell: &Cell, route: &[Point]) -> String {
+        let geometry = cell.geometry.clone().unwrap_or_default();
+        let [x, y] = point_along(route, (geometry.x + 1.0) / 2.0);
+        let [dx, dy] = geometry.offset.unwrap_or_default();
+        self.label(cell, Rect::at([x + dx, y + dy]), true)
+    }
+}
+
+fn label_text_group(style: &Style, font_size: f64, anchor: &str) -> String {
+    let color = style
+        .color("fontColor", Some(DEFAULT_FONT_COLOR))
+        .unwrap_or_else(|| "none".to_owned());
+    let family = style.get("fontFamily").unwrap_or(DEFAULT_FONT_FAMILY);
+    let mut attrs = format!(
+        "<g fill=\"{}\" font-family=\"{}\" font-size=\"{font_size}px\" text-anchor=\"{anchor}\" \
+         dominant-baseline=\"text-before-edge\" style=\"white-space: pre;\"",
+        escape_xml(&color),
+        escape_xml(family)
+    );
+    // The font style is a set of flags
+    let font_style = style.number("fontStyle", 0.0);
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    let font_style = font_style as u32;
+    if font_style & 1 != 0 {
+        attrs.push_str(" font-weight=\"bold\"");
+    }
+    if font_style & 2 != 0 {
+        attrs.push_str(" font-style=\"italic\"");
+    }
+    if font_style & 4 != 0 {
+        attrs.push_str(" text-decoration=\"underline\"");
+    }
+    attrs.push('>');
+    attrs
+}
+
+fn rectangle(style: &Style, rect: Rect, paint: &str) -> String {
+    let mut corners = String::new();
+    if style.flag("rounded") {
+        let arc_size = style.number("arcSize", DEFAULT_ARC_SIZE);
+        let radius = if style.flag("absoluteArcSize") {
+            arc_size / 2.0
+        } else {
+            rect.width.min(rect.height) * arc_size / 100.0
+        };
+        corners.push_str(&format!(" rx=\"{radius}\" ry=\"{radius}\""));
+    }
+    format!(
+        "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\"{corners}{paint}/>",
+        rect.x, rect.y, rect.width, rect.height
+    )
+}
+
+fn cylinder(style: &Style, rect: Rect, paint: &str, default_stroke: Option<&str>) -> String {
+    let Rect {
+        x,
+        y,
+        width: w,
+        height: h,
+    } = rect;
+    let depth = style.number("size", 15.0).min(h / 2.0);
+    let (top, bottom) = (y + depth, y + h - depth);
+    // Control points of a cubic that makes half an ellipse
+    let bulge = depth * 4.0 / 3.0;
+    format!(
+        "<path d=\"M {x} {top} C {x} {} {right} {} {right} {top} L {right} {bottom} \
+         C {right} {} {x} {} {x} {bottom} Z\"{paint}/>\
+         <path d=\"M {x} {top} C {x} {} {right} {} {right} {top}\" fill=\"none\"{}/>",
+        top - bulge,
+        top - bulge,
+        bottom + bulge,
+        bottom + bulge,
+        top + bulge,
+        top + bulge,
+        stroke_attrs(style, default_stroke),
+        right = x + w,
+    )
+}
+
+fn swimlane_header(style: &Style, rect: Rect) -> Rect {
+    let start_size = style.number("startSize", DEFAULT_SWIMLANE_START_SIZE);
+    if style.get("horizontal") == Some("0") {
+        Rect {
+            width: start_size.min(rect.width),
+            ..rect
+        }
+    } else {
+        Rect {
+            height: start_size.min(rect.height),
+            ..rect
+        }
+    }
+}
+
+fn swimlane(style: &Style, rect: Rect, paint: &str, default_stroke: Option<&str>) -> String {
+    let header = swimlane_header(style, rect);
+    let body = if style.get("horizontal") == Some("0") {
+        Rect {
+            x: header.right(),
+            width: rect.width - header.width,
+            ..rect
+        }
+    } else {
+        Rect {
+            y: header.bottom(),
+            height: rect.height - header.height,
+            ..rect
+        }
+    };
+    let body_fill = style
+        .color("swimlaneFillColor", None)
+        .unwrap_or_else(|| "none".to_owned());
+    let body_paint = format!(
+        " fill=\"{}\"{}",
+        escape_xml(&body_fill),
+        stroke_attrs(style, default_stroke)
+    );
+    format!(
+        "{}{}",
+        rectangle(style, header, paint),
+        rectangle(&Style::default(), body, &body_paint)
+    )
+}
+
+fn image(style: &Style, rect: Rect) -> String {
+    let Some(href) = style.get("image") else {
+        return String::new();
+    };
+    // Styles can't have a `;`, so draw.io drops it from data urls
+    let href = if href.starts_with("data:") && !href.contains(";base64,") {
+        href.replacen(',', ";base64,", 1)
+    } else {
+        href.to_owned()
+    };
+    let aspect = if style.get("imageAspect") == Some("0") {
+        "none"
+    } else {
+        "xMidYMid meet"
+    };
+    format!(
+        "<image x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" href=\"{}\" preserveAspectRatio=\"{aspect}\"/>",
+        rect.x,
+        rect.y,
+        rect.width,
+        rect.height,
+        escape_xml(&href)
+    )
+}
+
+/// Where the cells of a diagram end up, after adding the positions of the
+/// cells they are in
+pub struct Layout<'a>(Renderer<'a>);
+
+impl<'a> Layout<'a> {
+    pub fn new(model: &'a GraphModel) -> Self {
+        Self(Renderer::new(model, String::new()))
+    }
+
+    /// Box of a vertex, unset for labels placed along an edge
+    pub fn rect(&self, id: &str) -> Option<Rect> {
+        self.0.boxes.get(id).copied()
+    }
+
+    /// Where the geometry of the cell is relative to, the box of the closest
+    /// vertex it is in
+    pub fn origin(&self, cell: &Cell) -> Point {
+        let mut parent = cell.parent.as_deref();
+        // Bounded in case the parents loop
+        for _ in 0..self.0.cells.len() {
+            let Some(id) = parent else { break };
+            if let Some(rect) = self.rect(id) {
+                return [rect.x, rect.y];
+            }
+            parent = self.0.cells.get(id).and_then(|c| c.parent.as_deref());
+        }
+        [0.0, 0.0]
+    }
+
+    /// Points an edge goes through, from its source to its target, unset
+    /// when one of its ends is missing
+    pub fn route(&self, edge: &Cell) -> Option<Vec<Point>> {
+        let mut route = self.0.route(edge, self.origin(edge))?;
+        route.dedup();
+        Some(route)
+    }
+}
+
+pub fn render_svg(
+    model: &GraphModel,
+    input_contents: &[u8],
+    export_opts: &cli::ExportOpts,
+    accessibility: Option<&cli::AccessibilityOpts>,
+) -> Result<String> {
+    let background = model
+        .background
+        .clone()
+        .unwrap_or_else(|| DEFAULT_BACKGROUND.to_owned());
+    let mut renderer = Renderer::new(model, background.clone());
+    let mut body = String::new();
+    renderer.draw("", [0.0, 0.0], None, 0, &mut body);
+    if !renderer.unsupported.is_empty() {
+        let unsupported = renderer.unsupported.iter().cloned().collect::<Vec<_>>();
+        warn!(
+            unsupported = unsupported.join(", "),
+            "Diagram has styles that are drawn approximately"
+        );
+    }
+    info!("Finished rendering raw svg");
+
+    let svg = root_svg(
+        renderer.bounds.rect(),
+        &body,
+        &background,
+        input_contents,
+        export_opts,
+    );
+    let Some(accessibility) = accessibility else {
+        return Ok(svg);
+    };
+    let description = accessibility
+        .description
+        .clone()
+        .or_else(|| Some(renderer.texts.join("\n")).filter(|d| !d.is_empty()));
+    let svg = svg::add_accessibility(&svg, &accessibility.title, description.as_deref())
+        .wrap_err("Failed adding accessibility metadata to svg")?;
+    info!("Finished adding accessibility metadata to svg");
+    Ok(svg)
+}
+
+fn root_svg(
+    bounds: Rect,
+    body: &str,
+    background: &str,
+    input_contents: &[u8],
+    export_opts: &cli::ExportOpts,
+) -> String {
+    let (x, y) = (bounds.x - BORDER, bounds.y - BORDER);
+    let (width, height) = (bounds.width + BORDER * 2.0, bounds.height + BORDER * 2.0);
+    let scale = f64::from(export_opts.scale.max(1));
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" version=\"1.1\" viewBox=\"{x} {y} {width} {height}\" \
+         width=\"{}\" height=\"{}\"",
+        width * scale,
+        height * scale
+    );
+    if export_opts.theme == cli::OutputTheme::Dark {
+        svg.push_str(&format!(" filter=\"{DARK_THEME_FILTER}\""));
+    }
+    // Draw.io opens svgs that have the diagram in their `content`
+    if export_opts.embed_source {
+        svg.push_str(&format!(
+            " content=\"{}\"",
+            escape_xml(&String::from_utf8_lossy(input_contents))
+        ));
+    }
+    svg.push('>');
+    if export_opts.include_background {
+        svg.push_str(&format!(
+            "<rect x=\"{x}\" y=\"{y}\" width=\"{width}\" height=\"{height}\" fill=\"{}\"/>",
+            escape_xml(background)
+        ));
+    }
+    svg.push_str(body);
+    svg.push_str("</svg>");
+    svg
+}
+
+/// Renders the selected pages of a draw.io file, every page to its own
+/// output when they are all exported
+fn render_file(opts: &cli::Opts, input_contents: &[u8]) -> Result<Vec<Output>> {
+    let mut pages = drawio::pages(input_contents).wrap_err("Failed reading draw.io file")?;
+    for page in &mut pages {
+        if let Some(catalogue) = &opts.translation {
+            i18n::translate_model(&mut page.model, catalogue);
+        }
+        if !opts.vars.is_empty() {
+            template::fill_model(&mut page.model, &opts.vars);
+        }
+    }
+    let selected = drawio::select_pages(&pages, &opts.pages)?;
+    let split = opts.pages == cli::Pages::All;
+
+    let mut slugs = Slugs::default();
+    selected
+        .into_iter()
+        .enumerate()
+        .map(|(i, page)| {
+            // Every page is titled after itself, like library items
+            let accessibility = opts.accessibility.as_ref().map(|a| cli::AccessibilityOpts {
+                title: if split {
+                    page.name.clone()
+                } else {
+                    a.title.clone()
+                },
+                description: a.description.clone(),
+            });
+            let svg = render_svg(
+                &page.model,
+                input_contents,
+                &opts.export,
+                accessibility.as_ref(),
+            )
+            .wrap_err_with(|| format!("Failed rendering draw.io page {}", page.name))?;
+            Ok(Output {
+                name: split.then(|| slugs.unique(&page.name, i + 1)),
+                svg: svg.into_bytes(),
+            })
+        })
+        .collect()
+}
+
+pub struct Drawio;
+
+impl DiagramBackend for Drawio {
+    fn name(&self) -> &'static str {
+        "drawio"
+    }
+
+    fn description(&self) -> &'static str {
+        "Drawio mxGraph file (https://drawio.com)"
+    }
+
+    fn suffixes(&self) -> &'static [&'static str] {
+        &[".drawio"]
+    }
+
+    fn detect(&self, contents: &[u8]) -> Result<()> {
+        match detect::xml_root(contents)?.as_str() {
+            // Pages can be compressed, and only decompressing them tells
+            // whether they hold a diagram
+            "mxfile" => {
+                drawio::pages(contents).wrap_err("mxfile has pages that can't be read")?;
+            }
+            "mxGraphModel" => {}
+            root => bail!("root element is <{root}>, not <mxfile> or <mxGraphModel>"),
+        }
+        Ok(())
+    }
+
+    fn supports(&self) -> Supports {
+        Supports {
+            embed_source: true,
+            templates: true,
+            translations: true,
+            pages: true,
+            ..Supports::default()
+        }
+    }
+
+    fn render(
+        &self,
+        _rt: &Runtime,
+        opts: &cli::Opts,
+        input_contents: &[u8],
+    ) -> Result<Vec<Output>> {
+        render_file(opts, input_contents)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn model(cells: &str) -> Result<GraphModel> {
+        let xml = format!("<mxGraphModel><root>{cells}</root></mxGraphModel>");
+        let mut pages = drawio::pages(xml.as_bytes())?;
+        Ok(pages.remove(0).model)
+    }
+
+    fn render(model: &GraphModel) -> Result<String> {
+        let export_opts = cli::ExportOpts {
+            theme: cli::OutputTheme::Light,
+            include_background: false,
+            embed_source: false,
+            scale: 1,
+            deterministic: true,
+        };
+        render_svg(model, b"", &export_opts, None)
+    }
+
+    #[test]
+    fn cyclic_parents_do_not_overflow_the_stack() -> Result<()> {
+        let vertex = r#"vertex="1"><mxGeometry x="10" y="10" width="40" height="20" as="geometry"/></mxCell>"#;
+        for cells in [
+            // A duplicated id makes the cell its own parent
+            format!(
+                r#"<mxCell id="a" value="A" {vertex}<mxCell id="a" parent="a" value="B" {vertex}"#
+            ),
+            // An empty id is the parent of the cells at the top
+            format!(r#"<mxCell id="" value="A" {vertex}"#),
+        ] {
+            let model = model(&cells)?;
+            // Both the layout and the drawing walk down the parents
+            Layout::new(&model);
+            assert!(render(&model)?.contains("<svg"));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,804 @@
+//! Renders excalidraw scenes to svg without a browser, drawing the elements
+//! the way the svg export of excalidraw does
+
+// The maths follows excalidraw, so the output matches what it exports, and
+// sizes of exactly 0 or 1 are special cased like it does
+#![allow(clippy::suboptimal_flops, clippy::float_cmp)]
+
+use std::{collections::HashMap, fmt::Write as _, io::Write as _};
+
+use base64::prelude::*;
+use color_eyre::{eyre::WrapErr, Result};
+use flate2::{write::ZlibEncoder, Compression};
+use hdiag::scene::{
+    Arrowhead, Element, ElementData, FileData, Linear, Point, Roundness, Scene, StrokeStyle,
+    TextAlign, FONT_FAMILY_CASCADIA, FONT_FAMILY_HELVETICA, FONT_FAMILY_VIRGIL,
+};
+use tracing::warn;
+
+use crate::{
+    cli, excalidraw,
+    rough::{self, Drawable, Segment},
+    svg::{escape_xml, DARK_THEME_FILTER},
+};
+
+/// Space around the elements, the default of excalidraw
+const EXPORT_PADDING: f64 = 10.0;
+/// Undoes the theme filter on images, so they keep their colors
+const IMAGE_INVERT_FILTER: &str = "invert(100%) hue-rotate(180deg) saturate(1.25)";
+const FRAME_STROKE_COLOR: &str = "#bbb";
+const FRAME_STROKE_WIDTH: f64 = 2.0;
+const FRAME_RADIUS: f64 = 8.0;
+const DEFAULT_BACKGROUND: &str = "#ffffff";
+const EMOJI_FALLBACK_FONT: &str = "Segoe UI Emoji";
+/// Lines whose ends are closer than this are loops, and can be filled
+const LOOP_THRESHOLD: f64 = 8.0;
+
+/// Kinds of the elements in the scene that can't be drawn natively
+pub fn unsupported_kinds(scene: &Scene) -> Vec<&str> {
+    let mut kinds = scene
+        .live_elements()
+        .filter(|e| matches!(e.data, ElementData::Freedraw(_) | ElementData::Other(_)))
+        .map(|e| e.data.kind())
+        .collect::<Vec<_>>();
+    kinds.sort_unstable();
+    kinds.dedup();
+    kinds
+}
+
+fn rotate(point: Point, center: Point, angle: f64) -> Point {
+    let (sin, cos) = angle.sin_cos();
+    let [x, y] = [point[0] - center[0], point[1] - center[1]];
+    [x * cos - y * sin + center[0], x * sin + y * cos + center[1]]
+}
+
+/// Bounding box of the points, as `[min_x, min_y, max_x, max_y]`
+fn points_bounds(points: &[Point]) -> [f64; 4] {
+    points.iter().fold(
+        [
+            f64::INFINITY,
+            f64::INFINITY,
+            f64::NEG_INFINITY,
+            f64::NEG_INFINITY,
+        ],
+        |[min_x, min_y, max_x, max_y], [x, y]| {
+            [min_x.min(*x), min_y.min(*y), max_x.max(*x), max_y.max(*y)]
+        },
+    )
+}
+
+/// The points of lines and arrows, relative to the element
+fn linear_points(linear: &Linear) -> Vec<Point> {
+    if linear.points.is_empty() {
+        vec![[0.0, 0.0]]
+    } else {
+        linear.points.clone()
+    }
+}
+
+/// Center of the element that it is rotated around, relative to its position
+fn rotation_center(element: &Element) -> Point {
+    match &element.data {
+        ElementData::Line(linear) | ElementData::Arrow(linear) => {
+            let [min_x, min_y, max_x, max_y] = points_bounds(&linear_points(linear));
+            [(max_x - min_x) / 2.0 + min_x, (max_y - min_y) / 2.0 + min_y]
+        }
+        _ => [element.base.width / 2.0, element.base.height / 2.0],
+    }
+}
+
+/// Bounds of the element in the scene, after rotating it
+fn element_bounds(element: &Element) -> [f64; 4] {
+    let base = &element.base;
+    let corners = match &element.data {
+        ElementData::Line(linear) | ElementData::Arrow(linear) => linear_points(linear),
+        _ => vec![
+            [0.0, 0.0],
+            [base.width, 0.0],
+            [base.width, base.height],
+            [0.0, base.height],
+        ],
+    };
+    let center = rotation_center(element);
+    let corners = corners
+        .into_iter()
+        .map(|p| {
+            let [x, y] = rotate(p, center, base.angle);
+            [base.x + x, base.y + y]
+        })
+        .collect::<Vec<_>>();
+    points_bounds(&corners)
+}
+
+fn is_invisibly_small(element: &Element) -> bool {
+    match &element.data {
+        ElementData::Line(linear) | ElementData::Arrow(linear) => linear.points.len() < 2,
+        _ => element.base.width == 0.0 && element.base.height == 0.0,
+    }
+}
+
+fn is_transparent(color: &str) -> bool {
+    color == "transparent"
+        || (color.len() == 5 && color.ends_with('0'))
+        || (color.len() == 9 && color.ends_with("00"))
+}
+
+fn is_loop(points: &[Point]) -> bool {
+    match (points.first(), points.last()) {
+        (Some(first), Some(last)) if points.len() > 2 => {
+            (first[0] - last[0]).hypot(first[1] - last[1]) <= LOOP_THRESHOLD
+        }
+        _ => false,
+    }
+}
+
+/// Small shapes are drawn less rough, or they become a scribble
+fn adjust_roughness(element: &Element) -> f64 {
+    let base = &element.base;
+    let max_size = base.width.max(base.height);
+    let min_size = base.width.min(base.height);
+    let is_linear = matches!(element.data, ElementData::Line(_) | ElementData::Arrow(_));
+    let can_round = matches!(
+        element.data,
+        ElementData::Rectangle
+            | ElementData::Diamond
+            | ElementData::Line(_)
+            | ElementData::Arrow(_)
+    );
+
+    if (min_size >= 20.0 && max_size >= 50.0)
+        || (min_size >= 15.0 && base.roundness.is_some() && can_round)
+        || (is_linear && max_size >= 50.0)
+    {
+        return base.roughness;
+    }
+    (base.roughness / if max_size < 10.0 { 3.0 } else { 2.0 }).min(2.5)
+}
+
+fn dotted_dash(stroke_width: f64) -> [f64; 2] {
+    [1.5, 6.0 + stroke_width]
+}
+
+fn rough_options(element: &Element, continuous_path: bool) -> rough::Options {
+    let base = &element.base;
+    let solid = base.stroke_style == StrokeStyle::Solid;
+    let mut options = rough::Options::default();
+    options.seed = base.seed;
+    options.stroke_line_dash = match base.stroke_style {
+        StrokeStyle::Solid => None,
+        StrokeStyle::Dashed => Some([8.0, 8.0 + base.stroke_width]),
+        StrokeStyle::Dotted => Some(dotted_dash(base.stroke_width)),
+    };
+    options.disable_multi_stroke = !solid;
+    options.stroke_width = if solid {
+        base.stroke_width
+    } else {
+        base.stroke_width + 0.5
+    };
+    options.fill_weight = base.stroke_width / 2.0;
+    options.hachure_gap = base.stroke_width * 4.0;
+    options.roughness = adjust_roughness(element);
+    options.stroke.clone_from(&base.stroke_color);
+    options.preserve_vertices = continuous_path || base.roughness < 2.0;
+
+    let fillable = match &element.data {
+        ElementData::Rectangle | ElementData::Diamond | ElementData::Ellipse => true,
+        ElementData::Line(linear) => is_loop(&linear.points),
+        _ => false,
+    };
+    if fillable {
+        options.fill_style = base.fill_style;
+        options.fill = Some(base.background_color.clone()).filter(|color| !is_transparent(color));
+    }
+    if matches!(element.data, ElementData::Ellipse) {
+        options.curve_fitting = 1.0;
+    }
+    options
+}
+
+fn corner_radius(size: f64, roundness: &Roundness) -> f64 {
+    // Adaptive roundness has a fixed radius, unless the shape is small
+    const ADAPTIVE: u8 = 3;
+    const DEFAULT_ADAPTIVE_RADIUS: f64 = 32.0;
+    const PROPORTIONAL_RADIUS: f64 = 0.25;
+
+    if roundness.kind == ADAPTIVE {
+        let fixed = roundness.value.unwrap_or(DEFAULT_ADAPTIVE_RADIUS);
+        if size <= fixed / PROPORTIONAL_RADIUS {
+            size * PROPORTIONAL_RADIUS
+        } else {
+            fixed
+        }
+    } else {
+        size * PROPORTIONAL_RADIUS
+    }
+}
+
+fn rectangle_shape(element: &Element) -> Drawable {
+    let (w, h) = (element.base.width, element.base.height);
+    let Some(roundness) = &element.base.roundness else {
+        return rough::rectangle(0.0, 0.0, w, h, &rough_options(element, false));
+    };
+
+    let r = corner_radius(w.min(h), roundness);
+    let segments = [
+        Segment::Move([r, 0.0]),
+        Segment::Line([w - r, 0.0]),
+        Segment::Quadratic([w, 0.0], [w, r]),
+        Segment::Line([w, h - r]),
+        Segment::Quadratic([w, h], [w - r, h]),
+        Segment::Line([r, h]),
+        Segment::Quadratic([0.0, h], [0.0, h - r]),
+        Segment::Line([0.0, r]),
+        Segment::Quadratic([0.0, 0.0], [r, 0.0]),
+    ];
+    rough::path(&segments, &rough_options(element, true))
+}
+
+fn diamond_shape(element: &Element) -> Drawable {
+    let (w, h) = (element.base.width, element.base.height);
+    let top = [(w / 2.0).floor() + 1.0, 0.0];
+    let right = [w, (h / 2.0).floor() + 1.0];
+    let bottom = [top[0], h];
+    let left = [0.0, right[1]];
+    let Some(roundness) = &element.base.roundness else {
+        return rough::polygon(&[top, right, bottom, left], &rough_options(element, false));
+    };
+
+    let vertical = corner_radius((top[0] - left[0]).abs(), roundness);
+    let horizontal = corner_radius((right[1] - top[1]).abs(), roundness);
+    let corner = |p: Point, end: Point| Segment::Cubic(p, p, end);
+    let segments = [
+        Segment::Move([top[0] + vertical, top[1] + horizontal]),
+        Segment::Line([right[0] - vertical, right[1] - horizontal]),
+        corner(right, [right[0] - vertical, right[1] + horizontal]),
+        Segment::Line([bottom[0] + vertical, bottom[1] - horizontal]),
+        corner(bottom, [bottom[0] - vertical, bottom[1] - horizontal]),
+        Segment::Line([left[0] + vertical, left[1] + horizontal]),
+        corner(left, [left[0] + vertical, left[1] - horizontal]),
+        Segment::Line([top[0] - vertical, top[1] + horizontal]),
+        corner(top, [top[0] + vertical, top[1] + horizontal]),
+    ];
+    rough::path(&segments, &rough_options(element, true))
+}
+
+const fn arrowhead_size(arrowhead: Arrowhead) -> f64 {
+    match arrowhead {
+        Arrowhead::Arrow => 25.0,
+        Arrowhead::Diamond | Arrowhead::DiamondOutline => 12.0,
+        _ => 15.0,
+    }
+}
+
+const fn arrowhead_angle(arrowhead: Arrowhead) -> f64 {
+    match arrowhead {
+        Arrowhead::Bar => 90.0,
+        Arrowhead::Arrow => 20.0,
+        _ => 25.0,
+    }
+}
+
+/// Points of the arrowhead, found along the curve at that end of the arrow
+fn arrowhead_points(
+    element: &Element,
+    points: &[Point],
+    shape: &Drawable,
+    start: bool,
+    arrowhead: Arrowhead,
+) -> Option<Vec<Point>> {
+    let [p0, p1, p2, p3] = shape.end_curve(start)?;
+    // Point on the curve, close to the end
+    let t: f64 = 0.3;
+    let at = |i: usize| {
+        (1.0 - t).powi(3) * p3[i]
+            + 3.0 * t * (1.0 - t).powi(2) * p2[i]
+            + 3.0 * t.powi(2) * (1.0 - t) * p1[i]
+            + p0[i] * t.powi(3)
+    };
+    let tip = if start { p0 } else { p3 };
+    let [x1, y1] = [at(0), at(1)];
+    let distance = (tip[0] - x1).hypot(tip[1] - y1);
+    let (nx, ny) = ((tip[0] - x1) / distance, (tip[1] - y1) / distance);
+
+    // Arrowheads are made smaller on short segments
+    let (end, before_end) = if start {
+        (points[0], points.get(1))
+    } else {
+        (
+            points[points.len() - 1],
+            points.len().checked_sub(2).map(|i| &points[i]),
+        )
+    };
+    let before_end = before_end.copied().unwrap_or([0.0, 0.0]);
+    let length = (end[0] - before_end[0]).hypot(end[1] - before_end[1]);
+    let length_multiplier = if matches!(arrowhead, Arrowhead::Diamond | Arrowhead::DiamondOutline) {
+        0.25
+    } else {
+        0.5
+    };
+    let min_size = arrowhead_size(arrowhead).min(length * length_multiplier);
+    let base = [tip[0] - nx * min_size, tip[1] - ny * min_size];
+
+    if matches!(
+        arrowhead,
+        Arrowhead::Dot | Arrowhead::Circle | Arrowhead::CircleOutline
+    ) {
+        let diameter = (base[1] - tip[1]).hypot(base[0] - tip[0]) + element.base.stroke_width - 2.0;
+        return Some(vec![tip, [diameter, 0.0]]);
+    }
+
+    let angle = arrowhead_angle(arrowhead).to_radians();
+    let side1 = rotate(base, tip, -angle);
+    let side2 = rotate(base, tip, angle);
+    if matches!(arrowhead, Arrowhead::Diamond | Arrowhead::DiamondOutline) {
+        let opposite = if start {
+            rotate(
+                [tip[0] + min_size * 2.0, tip[1]],
+                tip,
+                (before_end[1] - tip[1]).atan2(before_end[0] - tip[0]),
+            )
+        } else {
+            rotate(
+                [tip[0] - min_size * 2.0, tip[1]],
+                tip,
+                (tip[1] - before_end[1]).atan2(tip[0] - before_end[0]),
+            )
+        };
+        return Some(vec![tip, side1, opposite, side2]);
+    }
+    Some(vec![tip, side1, side2])
+}
+
+fn arrowhead_shapes(
+    element: &Element,
+    points: &[Point],
+    shape: &Drawable,
+    start: bool,
+    arrowhead: Arrowhead,
+    canvas_background: &str,
+) -> Vec<Drawable> {
+    let Some(head) = arrowhead_points(element, points, shape, start, arrowhead) else {
+        return vec![];
+    };
+    let mut options = rough_options(element, false);
+    let stroke_color = element.base.stroke_color.clone();
+    // Outlined arrowheads are filled with the background, to hide the line
+    let fill = |outline: bool| {
+        if outline {
+            canvas_background.to_owned()
+        } else {
+            stroke_color.clone()
+        }
+    };
+
+    match arrowhead {
+        Arrowhead::Dot | Arrowhead::Circle | Arrowhead::CircleOutline => {
+            options.stroke_line_dash = None;
+            options.fill = Some(fill(arrowhead == Arrowhead::CircleOutline));
+            options.fill_style = hdiag::scene::FillStyle::Solid;
+            options.roughness = options.roughness.min(0.5);
+            vec![rough::circle(head[0], head[1][0], &options)]
+        }
+        Arrowhead::Triangle
+        | Arrowhead::TriangleOutline
+        | Arrowhead::Diamond
+        | Arrowhead::DiamondOutline => {
+            options.stroke_line_dash = None;
+            options.fill = Some(fill(matches!(
+                arrowhead,
+                Arrowhead::TriangleOutline | Arrowhead::DiamondOutline
+            )));
+            options.fill_style = hdiag::scene::FillStyle::Solid;
+            options.roughness = options.roughness.min(1.0);
+            let mut polygon = head.clone();
+            polygon.push(head[0]);
+            vec![rough::polygon(&polygon, &options)]
+        }
+        Arrowhead::Arrow | Arrowhead::Bar => {
+            options.stroke_line_dash = if element.base.stroke_style == StrokeStyle::Dotted {
+                // Dots are closer together on the head, or it can't be seen
+                let [dash, gap] = dotted_dash(element.base.stroke_width - 1.0);
+                Some([dash, gap - 1.0])
+            } else {
+                None
+            };
+            options.roughness = options.roughness.min(1.0);
+            vec![
+                rough::line(head[1], head[0], &options),
+                rough::line(head[2], head[0], &options),
+            ]
+        }
+    }
+}
+
+fn linear_shapes(element: &Element, linear: &Linear, canvas_background: &str) -> Vec<Drawable> {
+    let points = linear_points(linear);
+    let options = rough_options(element, false);
+    let shape = if element.base.roundness.is_none() {
+        if options.fill.is_some() {
+            rough::polygon(&points, &options)
+        } else {
+            rough::linear_path(&points, &options)
+        }
+    } else {
+        rough::curve(&points, &options)
+    };
+
+    let mut shapes = vec![];
+    if matches!(element.data, ElementData::Arrow(_)) {
+        for (start, arrowhead) in [
+            (true, linear.start_arrowhead),
+            (false, linear.end_arrowhead),
+        ] {
+            if let Some(arrowhead) = arrowhead {
+                shapes.extend(arrowhead_shapes(
+                    element,
+                    &points,
+                    &shape,
+                    start,
+                    arrowhead,
+                    canvas_background,
+                ));
+            }
+        }
+    }
+    shapes.insert(0, shape);
+    shapes
+}
+
+fn font_family(family: u32) -> String {
+    let name = match family {
+        FONT_FAMILY_VIRGIL => "Virgil",
+        FONT_FAMILY_HELVETICA => "Helvetica",
+        FONT_FAMILY_CASCADIA => "Cascadia",
+        _ => return EMOJI_FALLBACK_FONT.to_owned(),
+    };
+    format!("{name}, {EMOJI_FALLBACK_FONT}")
+}
+
+/// Whether the text starts with a right to left script, like Hebrew or
+/// Arabic
+fn is_rtl(text: &str) -> bool {
+    text.chars()
+        .find(|c| c.is_alphabetic())
+        .is_some_and(|c| matches!(c as u32, 0x0590..=0x08FF | 0xFB1D..=0xFDFF | 0xFE70..=0xFEFF))
+}
+
+struct Renderer<'a> {
+    offset: Point,
+    dark: bool,
+    canvas_background: String,
+    files: HashMap<&'a str, &'a FileData>,
+    /// Frames that are drawn, by id, for the elements in them to be clipped
+    frames: HashMap<&'a str, &'a Element>,
+    defs: String,
+}
+
+impl Renderer<'_> {
+    fn transform(&self, element: &Element) -> String {
+        let [cx, cy] = rotation_center(element);
+        format!(
+            "translate({} {}) rotate({} {cx} {cy})",
+            element.base.x + self.offset[0],
+            element.base.y + self.offset[1],
+            element.base.angle.to_degrees(),
+        )
+    }
+
+    fn opacity_attrs(element: &Element) -> String {
+        let opacity = element.base.opacity / 100.0;
+        if opacity == 1.0 {
+            String::new()
+        } else {
+            format!(" stroke-opacity=\"{opacity}\" fill-opacity=\"{opacity}\"")
+        }
+    }
+
+    fn shape_group(&self, element: &Element, shape: &Drawable, attrs: &str) -> String {
+        format!(
+            "<g{}{attrs} transform=\"{}\">{}</g>",
+            Self::opacity_attrs(element),
+            self.transform(element),
+            shape.to_svg()
+        )
+    }
+
+    fn text(&self, element: &Element, text: &hdiag::scene::Text) -> String {
+        let line_height = text.font_size * text.line_height;
+        let horizontal_offset = match text.text_align {
+            TextAlign::Left => 0.0,
+            TextAlign::Center => element.base.width / 2.0,
+            TextAlign::Right => element.base.width,
+        };
+        let rtl = is_rtl(&text.text);
+        let direction = if rtl { "rtl" } else { "ltr" };
+        let anchor = match text.text_align {
+            TextAlign::Center => "middle",
+            TextAlign::Right => "end",
+            TextAlign::Left if rtl => "end",
+            TextAlign::Left => "start",
+        };
+
+        let mut svg = format!(
+            "<g{} transform=\"{}\">",
+            Self::opacity_attrs(element),
+            self.transform(element)
+        );
+        let contents = text.text.replace("\r\n", "\n").replace('\r', "\n");
+        let mut y = 0.0;
+        for line in contents.split('\n') {
+            svg.push_str(&format!(
+                "<text x=\"{horizontal_offset}\" y=\"{}\" font-family=\"{}\" \
+                 font-size=\"{}px\" fill=\"{}\" text-anchor=\"{anchor}\" \
+                 style=\"white-space: pre;\" direction=\"{direction}\" \
+                 dominant-baseline=\"text-before-edge\">{}</text>",
+                y,
+                font_family(text.font_family),
+                text.font_size,
+                escape_xml(&element.base.stroke_color),
+                escape_xml(line)
+            ));
+            y += line_height;
+        }
+        svg.push_str("</g>");
+        svg
+    }
+
+    fn image(&mut self, element: &Element, image: &hdiag::scene::Image) -> String {
+        let file = image
+            .file_id
+            .as_deref()
+            .and_then(|id| self.files.get(id).copied());
+        let Some(file) = file else {
+            warn!(element = element.id, "Image has no file, and is not drawn");
+            return String::new();
+        };
+
+        let symbol_id = format!("image-{}", file.id);
+        if !self.defs.contains(&format!("id=\"{symbol_id}\"")) {
+            self.defs.push_str(&format!(
+                "<symbol id=\"{symbol_id}\"><image width=\"100%\" height=\"100%\" href=\"{}\"/></symbol>",
+                escape_xml(&file.data_url)
+            ));
+        }
+
+        let (w, h) = (element.base.width, element.base.height);
+        let mut attrs = String::new();
+        // The theme filter would invert the colors of the image too
+        if self.dark && file.mime_type != "image/svg+xml" {
+            attrs.push_str(&format!(" filter=\"{IMAGE_INVERT_FILTER}\""));
+        }
+        let [sx, sy] = image.scale;
+        if sx != 1.0 || sy != 1.0 {
+            let tx = if sx == 1.0 { 0.0 } else { -w };
+            let ty = if sy == 1.0 { 0.0 } else { -h };
+            attrs.push_str(&format!(
+                " transform=\"scale({sx}, {sy}) translate({tx} {ty})\""
+            ));
+        }
+        let opacity = element.base.opacity / 100.0;
+        let opacity = if opacity == 1.0 {
+            String::new()
+        } else {
+            format!(" opacity=\"{opacity}\"")
+        };
+        format!(
+            "<g{opacity} transform=\"{}\"><use href=\"#{symbol_id}\" width=\"{w}\" height=\"{h}\"{attrs}/></g>",
+            self.transform(element)
+        )
+    }
+
+    fn frame(&self, element: &Element) -> String {
+        format!(
+            "<rect transform=\"{}\" width=\"{}px\" height=\"{}px\" rx=\"{FRAME_RADIUS}\" \
+             ry=\"{FRAME_RADIUS}\" fill=\"none\" stroke=\"{FRAME_STROKE_COLOR}\" \
+             stroke-width=\"{FRAME_STROKE_WIDTH}\"/>",
+            self.transform(element),
+            element.base.width,
+            element.base.height
+        )
+    }
+
+    fn element(&mut self, element: &Element) -> String {
+        let svg = match &element.data {
+            ElementData::Rectangle => self.shape_group(
+                element,
+                &rectangle_shape(element),
+                " stroke-linecap=\"round\"",
+            ),
+            ElementData::Diamond => self.shape_group(
+                element,
+                &diamond_shape(element),
+                " stroke-linecap=\"round\"",
+            ),
+            ElementData::Ellipse => {
+                let (w, h) = (element.base.width, element.base.height);
+                let shape =
+                    rough::ellipse([w / 2.0, h / 2.0], w, h, &rough_options(element, false));
+                self.shape_group(element, &shape, " stroke-linecap=\"round\"")
+            }
+            ElementData::Line(linear) | ElementData::Arrow(linear) => {
+                let filled = matches!(element.data, ElementData::Line(_))
+                    && is_loop(&linear.points)
+                    && !is_transparent(&element.base.background_color);
+                let attrs = if filled { " fill-rule=\"evenodd\"" } else { "" };
+                let shapes = linear_shapes(element, linear, &self.canvas_background);
+                let paths = shapes
+                    .iter()
+                    .map(|shape| self.shape_group(element, shape, attrs))
+                    .collect::<String>();
+                format!("<g stroke-linecap=\"round\">{paths}</g>")
+            }
+            ElementData::Text(text) => self.text(element, text),
+            ElementData::Image(image) => self.image(element, image),
+            ElementData::Frame(_) | ElementData::MagicFrame(_) => self.frame(element),
+            ElementData::Freedraw(_) | ElementData::Other(_) => {
+                unreachable!("Unsupported elements are rendered by the browser")
+            }
+        };
+
+        // Elements in a frame are cut off at its edges
+        match element.base.frame_id.as_deref() {
+            Some(frame_id) if self.frames.contains_key(frame_id) => {
+                format!("<g clip-path=\"url(#{})\">{svg}</g>", escape_xml(frame_id))
+            }
+            _ => svg,
+        }
+    }
+
+    fn frame_clip_paths(&self, frames: &[&Element]) -> Result<String> {
+        frames.iter().try_fold(String::new(), |mut output, frame| {
+            write!(
+                output,
+                "<clipPath id=\"{}\"><rect transform=\"{}\" width=\"{}\" height=\"{}\"/></clipPath>",
+                escape_xml(&frame.id),
+                self.transform(frame),
+                frame.base.width,
+                frame.base.height
+            )
+            .wrap_err("Failed writing clip path of frame to string")?;
+            Ok(output)
+        })
+    }
+}
+
+/// The `@font-face`s excalidraw adds, which `apply_font_format` reads the
+/// fonts to embed from
+fn font_faces() -> Result<String> {
+    let asset_path = format!(
+        "https://unpkg.com/@excalidraw/excalidraw@{}/dist/excalidraw-assets/",
+        excalidraw::VERSION
+    );
+    [
+        ("Virgil", "Virgil.woff2"),
+        ("Cascadia", "Cascadia.woff2"),
+        ("Assistant", "Assistant-Regular.woff2"),
+    ]
+    .iter()
+    .try_fold(String::new(), |mut output, (family, file)| {
+        write!(
+            output,
+            "\n      @font-face {{\n        font-family: \"{family}\";\n        \
+             src: url(\"{asset_path}{file}\");\n      }}"
+        )
+        .wrap_err("Failed writing font face to string")?;
+        Ok(output)
+    })
+}
+
+/// The scene, compressed into comments the way excalidraw embeds it, so the
+/// svg can be opened in excalidraw again
+fn scene_metadata(scene_json: &[u8]) -> Result<String> {
+    let mut encoder = ZlibEncoder::new(vec![], Compression::default());
+    encoder
+        .write_all(scene_json)
+        .wrap_err("Failed compressing scene")?;
+    let compressed = encoder.finish().wrap_err("Failed compressing scene")?;
+
+    // Excalidraw stores the bytes as a string of characters, which is then
+    // encoded as JSON and base64 as if every character was a byte
+    let byte_string = compressed
+        .iter()
+        .map(|b| char::from(*b))
+        .collect::<String>();
+    let payload = serde_json::json!({
+        "version": "1",
+        "encoding": "bstring",
+        "compressed": true,
+        "encoded": byte_string,
+    })
+    .to_string();
+    #[allow(clippy::cast_possible_truncation)]
+    let bytes = payload.chars().map(|c| c as u8).collect::<Vec<_>>();
+
+    Ok(format!(
+        "<!-- payload-type:application/vnd.excalidraw+json --><!-- payload-version:2 -->\
+         <!-- payload-start -->{}<!-- payload-end -->",
+        BASE64_STANDARD.encode(bytes)
+    ))
+}
+
+/// Renders the scene, which must have no unsupported elements, to the same
+/// kind of svg excalidraw exports
+pub fn raw_svg(scene: &Scene, scene_json: &[u8], export_opts: &cli::ExportOpts) -> Result<String> {
+    let elements = scene
+        .live_elements()
+        .filter(|e| !is_invisibly_small(e))
+        .collect::<Vec<_>>();
+
+    let [min_x, min_y, max_x, max_y] = elements.iter().map(|e| element_bounds(e)).fold(
+        [
+            f64::INFINITY,
+            f64::INFINITY,
+            f64::NEG_INFINITY,
+            f64::NEG_INFINITY,
+        ],
+        |[a, b, c, d], [x1, y1, x2, y2]| [a.min(x1), b.min(y1), c.max(x2), d.max(y2)],
+    );
+    let [min_x, min_y, max_x, max_y] = if elements.is_empty() {
+        [0.0; 4]
+    } else {
+        [min_x, min_y, max_x, max_y]
+    };
+    let width = max_x - min_x + EXPORT_PADDING * 2.0;
+    let height = max_y - min_y + EXPORT_PADDING * 2.0;
+    let scale = f64::from(excalidraw::export_scale(export_opts.scale));
+
+    let background = scene
+        .app_state
+        .as_ref()
+        .and_then(|s| s.view_background_color.clone())
+        .unwrap_or_else(|| DEFAULT_BACKGROUND.to_owned());
+    let frames = elements
+        .iter()
+        .filter(|e| e.is_frame())
+        .copied()
+        .collect::<Vec<_>>();
+    let mut renderer = Renderer {
+        offset: [EXPORT_PADDING - min_x, EXPORT_PADDING - min_y],
+        dark: export_opts.theme == cli::OutputTheme::Dark,
+        canvas_background: background.clone(),
+        files: scene
+            .files
+            .iter()
+            .flatten()
+            .map(|(id, file)| (id.as_str(), file))
+            .collect(),
+        frames: frames.iter().map(|f| (f.id.as_str(), *f)).collect(),
+        defs: String::new(),
+    };
+
+    let body = elements
+        .iter()
+        .map(|e| renderer.element(e))
+        .collect::<String>();
+
+    let mut svg = format!(
+        "<svg version=\"1.1\" xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"0 0 {width} {height}\" \
+         width=\"{}\" height=\"{}\"",
+        width * scale,
+        height * scale
+    );
+    if renderer.dark {
+        svg.push_str(&format!(" filter=\"{DARK_THEME_FILTER}\""));
+    }
+    svg.push_str(">\n<!-- svg-source:excalidraw -->\n");
+    if export_opts.embed_source {
+        svg.push_str(&scene_metadata(scene_json)?);
+    }
+    svg.push_str(&format!(
+        "\n<defs>\n  <style class=\"style-fonts\">{}\n  </style>\n  {}{}\n</defs>\n",
+        font_faces()?,
+        renderer.frame_clip_paths(&frames)?,
+        renderer.defs
+    ));
+    if export_opts.include_background {
+        svg.push_str(&format!(
+            "<rect x=\"0\" y=\"0\" width=\"{width}\" height=\"{height}\" fill=\"{}\"/>",
+            escape_xml(&background)
+        ));
+    }
+    svg.push_str(&body);
+    svg.push_str("</svg>");
+    Ok(svg)
+}
@@ -0,0 +1,152 @@
+use color_eyre::{
+    eyre::{bail, ContextCompat, WrapErr},
+    Result,
+};
+use tokio::runtime::Runtime;
+
+use crate::{
+    backend::{DiagramBackend, Supports},
+    cli, excalidraw, Output,
+};
+
+/// Finds the body and info string of the first fenced code block after the
+/// `Drawing` heading
+fn drawing_block(markdown: &str) -> Result<(&str, &str)> {
+    let mut lines = markdown.lines();
+    lines
+        .by_ref()
+        .find(|l| {
+            let l = l.trim();
+            l.starts_with('#') && l.trim_start_matches('#').trim() == "Drawing"
+        })
+        .context("Markdown has no `Drawing` heading")?;
+
+    let fence = lines
+        .by_ref()
+        .find(|l| l.trim_start().starts_with("```"))
+        .context("Drawing heading is not followed by a code block")?;
+    let info = fence.trim().trim_start_matches('`').trim();
+
+    // The block is a slice of the original string, so we work with offsets
+    // from here on
+    let fence_end = fence.as_ptr() as usize - markdown.as_ptr() as usize + fence.len();
+    let rest = &markdown[fence_end..];
+    let body_end = rest
+        .find("\n```")
+        .context("Code block of the drawing is never closed")?;
+    Ok((info, &rest[..body_end]))
+}
+
+/// Extracts the excalidraw scene from a drawing saved by the Obsidian
+/// Excalidraw plugin, which is stored as JSON, or LZ-string compressed JSON,
+/// in a code block of the markdown
+pub fn extract_scene(markdown: &[u8]) -> Result<Vec<u8>> {
+    let markdown = std::str::from_utf8(markdown).wrap_err("Markdown is not valid UTF-8")?;
+    let (info, body) = drawing_block(markdown).wrap_err("Failed finding drawing in markdown")?;
+
+    match info {
+        "json" => Ok(body.trim().as_bytes().to_vec()),
+        "compressed-json" => {
+            // The plugin wraps the base64 to keep lines short
+            let compressed: String = body.chars().filter(|c| !c.is_whitespace()).collect();
+            // Invalid base64 can decompress to nothing instead of failing
+            let decompressed = lz_str::decompress_from_base64(&compressed)
+                .filter(|d| !d.is_empty())
+                .context("Drawing is not valid LZ-string compressed base64")?;
+            let scene = String::from_utf16(&decompressed)
+                .wrap_err("Decompressed drawing is not valid UTF-16")?;
+            Ok(scene.into_bytes())
+        }
+        info => {
+            bail!("Unknown code block `{info}` for drawing, expected `json` or `compressed-json`")
+        }
+    }
+}
+
+pub struct Obsidian;
+
+impl DiagramBackend for Obsidian {
+    fn name(&self) -> &'static str {
+        "obsidian"
+    }
+
+    fn description(&self) -> &'static str {
+        "Excalidraw drawing saved as markdown by the Obsidian Excalidraw plugin \
+         (https://github.com/zsviczian/obsidian-excalidraw-plugin)"
+    }
+
+    fn suffixes(&self) -> &'static [&'static str] {
+        &[".excalidraw.md"]
+    }
+
+    fn detect(&self, contents: &[u8]) -> Result<()> {
+        extract_scene(contents).map(drop)
+    }
+
+    fn supports(&self) -> Supports {
+        excalidraw::SCENE_SUPPORTS
+    }
+
+    fn render(&self, rt: &Runtime, opts: &cli::Opts, input_contents: &[u8]) -> Result<Vec<Output>> {
+        let scene = extract_scene(input_contents)
+            .wrap_err("Failed extracting scene from Obsidian markdown")?;
+        excalidraw::render_scene(rt, opts, scene)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCENE: &str = r#"{"type":"excalidraw","elements":[{"text":"Grüße 👋"}]}"#;
+
+    fn drawing(info: &str, body: &str) -> String {
+        format!(
+            "---\nexcalidraw-plugin: parsed\n---\n# Text Elements\nGrüße 👋 ^abc\n\n\
+             %%\n## Drawing\n```{info}\n{body}\n```\n%%\n"
+        )
+    }
+
+    #[test]
+    fn extract_scene_reads_json() -> Result<()> {
+        let markdown = drawing("json", SCENE);
+        assert_eq!(extract_scene(markdown.as_bytes())?, SCENE.as_bytes());
+        Ok(())
+    }
+
+    #[test]
+    fn extract_scene_reads_wrapped_compressed_json() -> Result<()> {
+        let compressed = lz_str::compress_to_base64(SCENE);
+        // The plugin breaks the base64 into lines
+        let wrapped = compressed
+            .as_bytes()
+            .chunks(16)
+            .map(|line| String::from_utf8_lossy(line).into_owned())
+            .collect::<Vec<_>>()
+            .join("\n\n");
+        let markdown = drawing("compressed-json", &wrapped);
+        assert_eq!(extract_scene(markdown.as_bytes())?, SCENE.as_bytes());
+        Ok(())
+    }
+
+    #[test]
+    fn drawing_block_finds_the_block_after_the_heading() -> Result<()> {
+        let markdown = "```json\n{}\n```\n# Drawing\nText\n  ```json  \n{\"a\": 1}\n```\n";
+        // The body starts after the fence, with the newline that ends it
+        assert_eq!(drawing_block(markdown)?, ("json", "\n{\"a\": 1}"));
+        Ok(())
+    }
+
+    #[test]
+    fn extract_scene_rejects_broken_drawings() {
+        for markdown in [
+            "# Text Elements\n```json\n{}\n```\n".to_owned(),
+            "## Drawing\nno block\n".to_owned(),
+            "## Drawing\n```json\n{}\n".to_owned(),
+            drawing("yaml", "a: 1"),
+            drawing("compressed-json", "!!!"),
+        ] {
+            assert!(extract_scene(markdown.as_bytes()).is_err(), "{markdown}");
+        }
+    }
+}
@@ -0,0 +1,106 @@
+use std::{
+    env,
+    io::Write as _,
+    process::{Command, Stdio},
+    thread,
+};
+
+use color_eyre::{
+    eyre::{bail, ContextCompat, WrapErr},
+    Result,
+};
+use tokio::runtime::Runtime;
+use tracing::{debug, info};
+
+use crate::{backend::DiagramBackend, cli, svg, Output};
+
+/// Environment variable with the command that runs plantuml, like
+/// `java -jar plantuml.jar`. Default is `plantuml`
+const COMMAND_VAR: &str = "HDIAG_PLANTUML";
+
+/// Runs plantuml on the source, and returns the svg it writes
+fn raw_svg(input_contents: &[u8], export_opts: &cli::ExportOpts) -> Result<String> {
+    let command = env::var(COMMAND_VAR).unwrap_or_else(|_| "plantuml".to_owned());
+    let mut words = command.split_whitespace();
+    let program = words
+        .next()
+        .with_context(|| format!("{COMMAND_VAR} is empty"))?;
+
+    let mut plantuml = Command::new(program);
+    plantuml
+        .args(words)
+        .args(["-tsvg", "-pipe", "-charset", "UTF-8"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    if !export_opts.include_background {
+        plantuml.arg("-SbackgroundColor=transparent");
+    }
+    debug!(command, "Running PlantUML");
+    let mut child = plantuml.spawn().wrap_err_with(|| {
+        format!("Failed to run `{command}`, install PlantUML or set {COMMAND_VAR}")
+    })?;
+    let mut stdin = child.stdin.take().context("PlantUML has no stdin")?;
+    // Written while the output is read, PlantUML stops reading once the pipe
+    // of its output is full
+    let (output, written) = thread::scope(|scope| {
+        let writer = scope.spawn(move || stdin.write_all(input_contents));
+        let output = child.wait_with_output();
+        let written = writer.join().expect("Writing to PlantUML doesn't panic");
+        (output, written)
+    });
+    let output = output.wrap_err("Failed waiting for PlantUML")?;
+    // A PlantUML that failed stops reading, its error says why
+    if !output.status.success() {
+        bail!(
+            "PlantUML failed with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    written.wrap_err("Failed writing diagram to PlantUML")?;
+    String::from_utf8(output.stdout).wrap_err("Output of PlantUML was not valid UTF-8")
+}
+
+pub struct PlantUml;
+
+impl DiagramBackend for PlantUml {
+    fn name(&self) -> &'static str {
+        "plantuml"
+    }
+
+    fn description(&self) -> &'static str {
+        "PlantUML diagram (https://plantuml.com), rendered by the `plantuml` command, \
+         or the one in HDIAG_PLANTUML"
+    }
+
+    fn suffixes(&self) -> &'static [&'static str] {
+        &[".puml", ".plantuml", ".pu", ".iuml"]
+    }
+
+    fn detect(&self, contents: &[u8]) -> Result<()> {
+        let source = std::str::from_utf8(contents).wrap_err("not UTF-8")?;
+        if !source.lines().any(|l| l.trim_start().starts_with("@start")) {
+            bail!("text has no `@startuml`");
+        }
+        Ok(())
+    }
+
+    fn cacheable(&self) -> bool {
+        false
+    }
+
+    fn render(
+        &self,
+        _rt: &Runtime,
+        opts: &cli::Opts,
+        input_contents: &[u8],
+    ) -> Result<Vec<Output>> {
+        let raw =
+            raw_svg(input_contents, &opts.export).wrap_err("Failed rendering PlantUML svg")?;
+        info!("Finished rendering raw svg");
+        let svg = svg::apply_export(&raw, &opts.export, opts.accessibility.as_ref())
+            .wrap_err("Failed applying export options to svg")?;
+        Ok(Output::single(svg))
+    }
+}
@@ -0,0 +1,964 @@
+//! Port of the parts of rough.js that excalidraw draws its shapes with, so
+//! that the native engine gives the same hand-drawn strokes for the same seed
+
+// The maths is kept as close to rough.js as possible, `mul_add` and `hypot`
+// round differently and would move the points, and points are compared
+// exactly like it does
+#![allow(clippy::suboptimal_flops, clippy::imprecise_flops, clippy::float_cmp)]
+
+use std::{f64::consts::PI, fmt::Write as _};
+
+use hdiag::scene::{FillStyle, Point};
+
+use crate::svg::escape_xml;
+
+/// The random number generator of rough.js, a Lehmer generator
+#[derive(Clone, Copy)]
+struct Random {
+    seed: i32,
+}
+
+impl Random {
+    #[allow(clippy::cast_possible_truncation)]
+    const fn new(seed: u64) -> Self {
+        // Like `Math.imul`, only the low 32 bits of the seed are used
+        Self { seed: seed as i32 }
+    }
+
+    fn next(&mut self) -> f64 {
+        self.seed = self.seed.wrapping_mul(48271);
+        f64::from(self.seed & i32::MAX) / 2_147_483_648.0
+    }
+}
+
+#[derive(Clone)]
+pub struct Options {
+    pub seed: u64,
+    pub roughness: f64,
+    pub bowing: f64,
+    pub max_randomness_offset: f64,
+    pub stroke: String,
+    pub stroke_width: f64,
+    pub stroke_line_dash: Option<[f64; 2]>,
+    pub curve_fitting: f64,
+    pub curve_tightness: f64,
+    pub curve_step_count: f64,
+    pub fill: Option<String>,
+    pub fill_style: FillStyle,
+    /// Width of the lines of pattern fills, half the stroke width if negative
+    pub fill_weight: f64,
+    pub hachure_angle: f64,
+    /// Space between the lines of pattern fills, four times the stroke width
+    /// if negative
+    pub hachure_gap: f64,
+    pub disable_multi_stroke: bool,
+    /// Keep the ends of lines where they are, only the middle is rough
+    pub preserve_vertices: bool,
+    random: Option<Random>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            roughness: 1.0,
+            bowing: 1.0,
+            max_randomness_offset: 2.0,
+            stroke: "#000".into(),
+            stroke_width: 1.0,
+            stroke_line_dash: None,
+            curve_fitting: 0.95,
+            curve_tightness: 0.0,
+            curve_step_count: 9.0,
+            fill: None,
+            fill_style: FillStyle::Hachure,
+            fill_weight: -1.0,
+            hachure_angle: -41.0,
+            hachure_gap: -1.0,
+            disable_multi_stroke: false,
+            preserve_vertices: false,
+            random: None,
+        }
+    }
+}
+
+impl Options {
+    /// Every shape starts again from the seed
+    fn resolve(&self) -> Self {
+        Self {
+            random: None,
+            ..self.clone()
+        }
+    }
+
+    fn random(&mut self) -> f64 {
+        let seed = self.seed;
+        self.random.get_or_insert_with(|| Random::new(seed)).next()
+    }
+
+    fn offset(&mut self, min: f64, max: f64, gain: f64) -> f64 {
+        self.roughness * gain * (self.random() * (max - min) + min)
+    }
+
+    fn offset_opt(&mut self, x: f64, gain: f64) -> f64 {
+        self.offset(-x, x, gain)
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Op {
+    Move(Point),
+    BezierTo([f64; 6]),
+    LineTo(Point),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum OpSetKind {
+    /// A stroke
+    Path,
+    /// A solid fill
+    FillPath,
+    /// A fill drawn with lines
+    FillSketch,
+}
+
+struct OpSet {
+    kind: OpSetKind,
+    ops: Vec<Op>,
+}
+
+/// A shape, ready to be written to svg
+pub struct Drawable {
+    sets: Vec<OpSet>,
+    options: Options,
+    /// Curves and polygons can cross themselves, and are filled `evenodd`
+    even_odd: bool,
+}
+
+/// Part of the outline given to [`path`]
+#[derive(Clone, Copy)]
+pub enum Segment {
+    Move(Point),
+    Line(Point),
+    /// A control point and the end point
+    Quadratic(Point, Point),
+    /// Two control points and the end point
+    Cubic(Point, Point, Point),
+}
+
+/// Rounds like the svg export of excalidraw, to two decimals
+fn fixed(v: f64) -> f64 {
+    // Adding zero turns `-0` into `0`
+    (v * 100.0).round() / 100.0 + 0.0
+}
+
+fn ops_to_path(ops: &[Op]) -> String {
+    let mut path = String::new();
+    for op in ops {
+        let _ = match *op {
+            Op::Move([x, y]) => write!(path, "M{} {} ", fixed(x), fixed(y)),
+            Op::BezierTo([x1, y1, x2, y2, x, y]) => write!(
+                path,
+                "C{} {}, {} {}, {} {} ",
+                fixed(x1),
+                fixed(y1),
+                fixed(x2),
+                fixed(y2),
+                fixed(x),
+                fixed(y)
+            ),
+            Op::LineTo([x, y]) => write!(path, "L{} {} ", fixed(x), fixed(y)),
+        };
+    }
+    path.trim_end().to_owned()
+}
+
+impl Drawable {
+    /// The `<path>`s of the shape, for the caller to put in a group
+    pub fn to_svg(&self) -> String {
+        let o = &self.options;
+        let fill = escape_xml(o.fill.as_deref().unwrap_or_default());
+        let mut svg = String::new();
+        for set in &self.sets {
+            let d = ops_to_path(&set.ops);
+            let _ = match set.kind {
+                OpSetKind::Path => {
+                    let dash = o.stroke_line_dash.map_or_else(String::new, |[dash, gap]| {
+                        format!(" stroke-dasharray=\"{dash} {gap}\"")
+                    });
+                    write!(
+                        svg,
+                        "<path d=\"{d}\" stroke=\"{}\" stroke-width=\"{}\" fill=\"none\"{dash}/>",
+                        escape_xml(&o.stroke),
+                        o.stroke_width
+                    )
+                }
+                OpSetKind::FillPath => {
+                    let rule = if self.even_odd {
+                        " fill-rule=\"evenodd\""
+                    } else {
+                        ""
+                    };
+                    write!(
+                        svg,
+                        "<path d=\"{d}\" stroke=\"none\" stroke-width=\"0\" fill=\"{fill}\"{rule}/>"
+                    )
+                }
+                OpSetKind::FillSketch => {
+                    let weight = if o.fill_weight < 0.0 {
+                        o.stroke_width / 2.0
+                    } else {
+                        o.fill_weight
+                    };
+                    write!(
+                        svg,
+                        "<path d=\"{d}\" stroke=\"{fill}\" stroke-width=\"{weight}\" fill=\"none\"/>"
+                    )
+                }
+            };
+        }
+        svg
+    }
+
+    /// The points of the curve of the stroke that an arrowhead is drawn at,
+    /// the start and end points with the two control points between them
+    pub fn end_curve(&self, start: bool) -> Option<[Point; 4]> {
+        let ops = &self
+            .sets
+            .iter()
+            .find(|s| s.kind == OpSetKind::Path)
+            .or_else(|| self.sets.first())?
+            .ops;
+        let index = if start { 1 } else { ops.len().checked_sub(1)? };
+        let Op::BezierTo([x1, y1, x2, y2, x, y]) = *ops.get(index)? else {
+            return None;
+        };
+        let p0 = match ops.get(index.checked_sub(1)?)? {
+            Op::Move(p) => *p,
+            Op::BezierTo([.., x, y]) => [*x, *y],
+            Op::LineTo(_) => [0.0, 0.0],
+        };
+        Some([p0, [x1, y1], [x2, y2], [x, y]])
+    }
+}
+
+fn single_line(p1: Point, p2: Point, o: &mut Options, overlay: bool) -> Vec<Op> {
+    let [x1, y1] = p1;
+    let [x2, y2] = p2;
+    let length_sq = (x1 - x2).powi(2) + (y1 - y2).powi(2);
+    let length = length_sq.sqrt();
+    let gain = if length < 200.0 {
+        1.0
+    } else if length > 500.0 {
+        0.4
+    } else {
+        -0.001_666_8 * length + 1.233_334
+    };
+
+    let mut offset = o.max_randomness_offset;
+    if offset * offset * 100.0 > length_sq {
+        offset = length / 10.0;
+    }
+    let jitter = if overlay { offset / 2.0 } else { offset };
+    let diverge_point = 0.2 + o.random() * 0.2;
+    let mid_disp_x = o.bowing * o.max_randomness_offset * (y2 - y1) / 200.0;
+    let mid_disp_y = o.bowing * o.max_randomness_offset * (x1 - x2) / 200.0;
+    let mid_disp_x = o.offset_opt(mid_disp_x, gain);
+    let mid_disp_y = o.offset_opt(mid_disp_y, gain);
+
+    let preserve = o.preserve_vertices;
+    let vertex = |o: &mut Options| {
+        if preserve {
+            0.0
+        } else {
+            o.offset_opt(jitter, gain)
+        }
+    };
+    let start = [x1 + vertex(o), y1 + vertex(o)];
+    let curve = [
+        mid_disp_x + x1 + (x2 - x1) * diverge_point + o.offset_opt(jitter, gain),
+        mid_disp_y + y1 + (y2 - y1) * diverge_point + o.offset_opt(jitter, gain),
+        mid_disp_x + x1 + 2.0 * (x2 - x1) * diverge_point + o.offset_opt(jitter, gain),
+        mid_disp_y + y1 + 2.0 * (y2 - y1) * diverge_point + o.offset_opt(jitter, gain),
+        x2 + vertex(o),
+        y2 + vertex(o),
+    ];
+    vec![Op::Move(start), Op::BezierTo(curve)]
+}
+
+/// Lines are drawn twice, unless multiple strokes are disabled
+fn double_line(p1: Point, p2: Point, o: &mut Options, filling: bool) -> Vec<Op> {
+    let mut ops = single_line(p1, p2, o, false);
+    if filling || !o.disable_multi_stroke {
+        ops.extend(single_line(p1, p2, o, true));
+    }
+    ops
+}
+
+fn linear_path_ops(points: &[Point], close: bool, o: &mut Options) -> Vec<Op> {
+    match points.len() {
+        0 | 1 => vec![],
+        2 => double_line(points[0], points[1], o, false),
+        len => {
+            let mut ops = vec![];
+            for pair in points.windows(2) {
+                ops.extend(double_line(pair[0], pair[1], o, false));
+            }
+            if close {
+                ops.extend(double_line(points[len - 1], points[0], o, false));
+            }
+            ops
+        }
+    }
+}
+
+fn curve_ops(points: &[Point], o: &mut Options) -> Vec<Op> {
+    let s = 1.0 - o.curve_tightness;
+    match points.len() {
+        0 | 1 => vec![],
+        2 => single_line(points[0], points[1], o, true),
+        3 => vec![
+            Op::Move(points[1]),
+            Op::BezierTo([
+                points[1][0],
+                points[1][1],
+                points[2][0],
+                points[2][1],
+                points[2][0],
+                points[2][1],
+            ]),
+        ],
+        len => {
+            let mut ops = vec![Op::Move(points[1])];
+            let mut i = 1;
+            while i + 2 < len {
+                let p = points[i];
+                ops.push(Op::BezierTo([
+                    p[0] + (s * points[i + 1][0] - s * points[i - 1][0]) / 6.0,
+                    p[1] + (s * points[i + 1][1] - s * points[i - 1][1]) / 6.0,
+                    points[i + 1][0] + (s * points[i][0] - s * points[i + 2][0]) / 6.0,
+                    points[i + 1][1] + (s * points[i][1] - s * points[i + 2][1]) / 6.0,
+                    points[i + 1][0],
+                    points[i + 1][1],
+                ]));
+                i += 1;
+            }
+            ops
+        }
+    }
+}
+
+fn curve_with_offset(points: &[Point], offset: f64, o: &mut Options) -> Vec<Op> {
+    let Some(&first) = points.first() else {
+        return vec![];
+    };
+    let jittered = |p: Point, o: &mut Options| {
+        [
+            p[0] + o.offset_opt(offset, 1.0),
+            p[1] + o.offset_opt(offset, 1.0),
+        ]
+    };
+
+    let mut ps = vec![jittered(first, o), jittered(first, o)];
+    for (i, &p) in points.iter().enumerate().skip(1) {
+        ps.push(jittered(p, o));
+        if i == points.len() - 1 {
+            ps.push(jittered(p, o));
+        }
+    }
+    curve_ops(&ps, o)
+}
+
+fn curve_outline(points: &[Point], o: &mut Options) -> Vec<Op> {
+    let mut ops = curve_with_offset(points, 1.0 + o.roughness * 0.2, o);
+    if !o.disable_multi_stroke {
+        let mut altered = Options {
+            seed: o.seed + 1,
+            random: None,
+            ..o.clone()
+        };
+        let offset = 1.5 * (1.0 + o.roughness * 0.22);
+        ops.extend(curve_with_offset(points, offset, &mut altered));
+    }
+    ops
+}
+
+struct EllipseParams {
+    increment: f64,
+    rx: f64,
+    ry: f64,
+}
+
+fn ellipse_params(width: f64, height: f64, o: &mut Options) -> EllipseParams {
+    let psq = (PI * 2.0 * (((width / 2.0).powi(2) + (height / 2.0).powi(2)) / 2.0).sqrt()).sqrt();
+    let step_count = o
+        .curve_step_count
+        .max((o.curve_step_count / 200_f64.sqrt()) * psq)
+        .ceil();
+    let increment = (PI * 2.0) / step_count;
+    let mut rx = (width / 2.0).abs();
+    let mut ry = (height / 2.0).abs();
+    let curve_fit_randomness = 1.0 - o.curve_fitting;
+    rx += o.offset_opt(rx * curve_fit_randomness, 1.0);
+    ry += o.offset_opt(ry * curve_fit_randomness, 1.0);
+    EllipseParams { increment, rx, ry }
+}
+
+/// Returns the points to draw the ellipse through, and the points on the
+/// ellipse itself
+#[allow(clippy::too_many_arguments)]
+fn ellipse_points(
+    increment: f64,
+    center: Point,
+    rx: f64,
+    ry: f64,
+    offset: f64,
+    overlap: f64,
+    o: &mut Options,
+) -> (Vec<Point>, Vec<Point>) {
+    let [cx, cy] = center;
+    let mut core_points = vec![];
+    let mut all_points = vec![];
+
+    if o.roughness == 0.0 {
+        let increment = increment / 4.0;
+        all_points.push([cx + rx * (-increment).cos(), cy + ry * (-increment).sin()]);
+        let mut angle = 0.0;
+        while angle <= PI * 2.0 {
+            let p = [cx + rx * f64::cos(angle), cy + ry * f64::sin(angle)];
+            core_points.push(p);
+            all_points.push(p);
+            angle += increment;
+        }
+        all_points.push([cx + rx, cy]);
+        all_points.push([cx + rx * increment.cos(), cy + ry * increment.sin()]);
+        return (all_points, core_points);
+    }
+
+    let rad_offset = o.offset_opt(0.5, 1.0) - PI / 2.0;
+    let point = |o: &mut Options, radius: f64, angle: f64| {
+        [
+            o.offset_opt(offset, 1.0) + cx + radius * rx * angle.cos(),
+            o.offset_opt(offset, 1.0) + cy + radius * ry * angle.sin(),
+        ]
+    };
+    all_points.push(point(o, 0.9, rad_offset - increment));
+    let end_angle = PI * 2.0 + rad_offset - 0.01;
+    let mut angle = rad_offset;
+    while angle < end_angle {
+        let p = point(o, 1.0, angle);
+        core_points.push(p);
+        all_points.push(p);
+        angle += increment;
+    }
+    all_points.push(point(o, 1.0, rad_offset + PI * 2.0 + overlap * 0.5));
+    all_points.push(point(o, 0.98, rad_offset + overlap));
+    all_points.push(point(o, 0.9, rad_offset + overlap * 0.5));
+    (all_points, core_points)
+}
+
+/// Returns the points on the ellipse, and the ops of its outline
+fn ellipse_with_params(
+    center: Point,
+    params: &EllipseParams,
+    o: &mut Options,
+) -> (Vec<Point>, Vec<Op>) {
+    let inner = o.offset(0.4, 1.0, 1.0);
+    let overlap = params.increment * o.offset(0.1, inner, 1.0);
+    let (all_points, core_points) = ellipse_points(
+        params.increment,
+        center,
+        params.rx,
+        params.ry,
+        1.0,
+        overlap,
+        o,
+    );
+    let mut ops = curve_ops(&all_points, o);
+    if !o.disable_multi_stroke && o.roughness != 0.0 {
+        let (all_points, _) =
+            ellipse_points(params.increment, center, params.rx, params.ry, 1.5, 0.0, o);
+        ops.extend(curve_ops(&all_points, o));
+    }
+    (core_points, ops)
+}
+
+fn solid_fill_polygons(polygons: &[Vec<Point>], o: &mut Options) -> OpSet {
+    let offset = o.max_randomness_offset;
+    let mut ops = vec![];
+    for points in polygons.iter().filter(|p| p.len() > 2) {
+        let jittered = |p: Point, o: &mut Options| {
+            [
+                p[0] + o.offset(-offset, offset, 1.0),
+                p[1] + o.offset(-offset, offset, 1.0),
+            ]
+        };
+        ops.push(Op::Move(jittered(points[0], o)));
+        for &p in &points[1..] {
+            ops.push(Op::LineTo(jittered(p, o)));
+        }
+    }
+    OpSet {
+        kind: OpSetKind::FillPath,
+        ops,
+    }
+}
+
+/// `Math.round`, which rounds halves up instead of away from zero
+fn js_round(v: f64) -> f64 {
+    (v + 0.5).floor()
+}
+
+fn rotate_points(points: &mut [Point], degrees: f64) {
+    let angle = (PI / 180.0) * degrees;
+    let (sin, cos) = angle.sin_cos();
+    for p in points {
+        let [x, y] = *p;
+        *p = [x * cos - y * sin, x * sin + y * cos];
+    }
+}
+
+struct Edge {
+    ymin: f64,
+    ymax: f64,
+    x: f64,
+    islope: f64,
+}
+
+/// Horizontal lines filling the polygons, found with a scanline
+fn straight_hachure_lines(polygons: &[Vec<Point>], gap: f64, step: f64) -> Vec<[Point; 2]> {
+    let mut edges = vec![];
+    for polygon in polygons {
+        let mut vertices = polygon.clone();
+        let (Some(&first), Some(&last)) = (vertices.first(), vertices.last()) else {
+            continue;
+        };
+        if first != last {
+            vertices.push(first);
+        }
+        if vertices.len() <= 2 {
+            continue;
+        }
+        for pair in vertices.windows(2) {
+            let [p1, p2] = [pair[0], pair[1]];
+            if p1[1] != p2[1] {
+                let ymin = p1[1].min(p2[1]);
+                edges.push(Edge {
+                    ymin,
+                    ymax: p1[1].max(p2[1]),
+                    x: if ymin == p1[1] { p1[0] } else { p2[0] },
+                    islope: (p2[0] - p1[0]) / (p2[1] - p1[1]),
+                });
+            }
+        }
+    }
+    edges.sort_by(|a, b| {
+        a.ymin
+            .total_cmp(&b.ymin)
+            .then(a.x.total_cmp(&b.x))
+            .then(a.ymax.total_cmp(&b.ymax))
+    });
+
+    let mut lines = vec![];
+    let Some(first) = edges.first() else {
+        return lines;
+    };
+    let mut y = first.ymin;
+    let mut edges = edges.into_iter().peekable();
+    let mut active: Vec<Edge> = vec![];
+    let mut iteration = 0.0;
+    while !active.is_empty() || edges.peek().is_some() {
+        while let Some(edge) = edges.next_if(|e| e.ymin <= y) {
+            active.push(edge);
+        }
+        active.retain(|e| e.ymax > y);
+        active.sort_by(|a, b| a.x.total_cmp(&b.x));
+
+        if step != 1.0 || iteration % gap == 0.0 {
+            for pair in active.chunks_exact(2) {
+                lines.push([[js_round(pair[0].x), y], [js_round(pair[1].x), y]]);
+            }
+        }
+
+        y += step;
+        for edge in &mut active {
+            edge.x += step * edge.islope;
+        }
+        iteration += 1.0;
+    }
+    lines
+}
+
+fn polygon_hachure_lines(polygons: &[Vec<Point>], o: &mut Options) -> Vec<[Point; 2]> {
+    let angle = o.hachure_angle + 90.0;
+    let gap = if o.hachure_gap < 0.0 {
+        o.stroke_width * 4.0
+    } else {
+        o.hachure_gap
+    };
+    let gap = gap.max(0.1).round().max(1.0);
+    let step = if o.roughness >= 1.0 && o.random() > 0.7 {
+        gap
+    } else {
+        1.0
+    };
+
+    // Lines are found horizontally, so the polygons are rotated to the angle
+    // of the lines first, and the lines are rotated back after
+    let mut polygons = polygons.to_vec();
+    for polygon in &mut polygons {
+        rotate_points(polygon, angle);
+    }
+    let mut lines = straight_hachure_lines(&polygons, gap, step);
+    for line in &mut lines {
+        rotate_points(line, -angle);
+    }
+    lines
+}
+
+fn render_lines(lines: &[[Point; 2]], o: &mut Options) -> Vec<Op> {
+    lines
+        .iter()
+        .flat_map(|[p1, p2]| double_line(*p1, *p2, o, true))
+        .collect()
+}
+
+fn pattern_fill_polygons(polygons: &[Vec<Point>], o: &mut Options) -> OpSet {
+    let ops = match o.fill_style {
+        FillStyle::CrossHatch => {
+            let lines = polygon_hachure_lines(polygons, o);
+            let mut ops = render_lines(&lines, o);
+            o.hachure_angle += 90.0;
+            let lines = polygon_hachure_lines(polygons, o);
+            ops.extend(render_lines(&lines, o));
+            o.hachure_angle -= 90.0;
+            ops
+        }
+        FillStyle::Zigzag => {
+            let gap = if o.hachure_gap < 0.0 {
+                o.stroke_width * 4.0
+            } else {
+                o.hachure_gap
+            };
+            let gap = gap.max(0.1);
+            let hachure_gap = o.hachure_gap;
+            o.hachure_gap = gap;
+            let lines = polygon_hachure_lines(polygons, o);
+            o.hachure_gap = hachure_gap;
+
+            let (sin, cos) = (PI / 180.0 * o.hachure_angle).sin_cos();
+            let (half_gap_x, half_gap_y) = (gap * 0.5 * cos, gap * 0.5 * sin);
+            let zigzag = lines
+                .iter()
+                .filter(|[p1, p2]| p1 != p2)
+                .flat_map(|&[p1, p2]| {
+                    [
+                        [[p1[0] - half_gap_x, p1[1] + half_gap_y], p2],
+                        [[p1[0] + half_gap_x, p1[1] - half_gap_y], p2],
+                    ]
+                })
+                .collect::<Vec<_>>();
+            render_lines(&zigzag, o)
+        }
+        FillStyle::Hachure | FillStyle::Solid => {
+            let lines = polygon_hachure_lines(polygons, o);
+            render_lines(&lines, o)
+        }
+    };
+    OpSet {
+        kind: OpSetKind::FillSketch,
+        ops,
+    }
+}
+
+fn fill_polygons(polygons: &[Vec<Point>], o: &mut Options) -> OpSet {
+    if o.fill_style == FillStyle::Solid {
+        solid_fill_polygons(polygons, o)
+    } else {
+        pattern_fill_polygons(polygons, o)
+    }
+}
+
+const fn stroke(ops: Vec<Op>) -> OpSet {
+    OpSet {
+        kind: OpSetKind::Path,
+        ops,
+    }
+}
+
+pub fn line(p1: Point, p2: Point, o: &Options) -> Drawable {
+    let mut o = o.resolve();
+    let ops = double_line(p1, p2, &mut o, false);
+    Drawable {
+        sets: vec![stroke(ops)],
+        options: o,
+        even_odd: false,
+    }
+}
+
+pub fn linear_path(points: &[Point], o: &Options) -> Drawable {
+    let mut o = o.resolve();
+    let ops = linear_path_ops(points, false, &mut o);
+    Drawable {
+        sets: vec![stroke(ops)],
+        options: o,
+        even_odd: false,
+    }
+}
+
+pub fn polygon(points: &[Point], o: &Options) -> Drawable {
+    let mut o = o.resolve();
+    let outline = linear_path_ops(points, true, &mut o);
+    let mut sets = vec![];
+    if o.fill.is_some() {
+        sets.push(fill_polygons(&[points.to_vec()], &mut o));
+    }
+    sets.push(stroke(outline));
+    Drawable {
+        sets,
+        options: o,
+        even_odd: true,
+    }
+}
+
+pub fn rectangle(x: f64, y: f64, width: f64, height: f64, o: &Options) -> Drawable {
+    let points = [
+        [x, y],
+        [x + width, y],
+        [x + width, y + height],
+        [x, y + height],
+    ];
+    let mut o = o.resolve();
+    let outline = linear_path_ops(&points, true, &mut o);
+    let mut sets = vec![];
+    if o.fill.is_some() {
+        sets.push(fill_polygons(&[points.to_vec()], &mut o));
+    }
+    sets.push(stroke(outline));
+    Drawable {
+        sets,
+        options: o,
+        even_odd: false,
+    }
+}
+
+pub fn ellipse(center: Point, width: f64, height: f64, o: &Options) -> Drawable {
+    let mut o = o.resolve();
+    let params = ellipse_params(width, height, &mut o);
+    let (estimated_points, outline) = ellipse_with_params(center, &params, &mut o);
+    let mut sets = vec![];
+    if o.fill.is_some() {
+        if o.fill_style == FillStyle::Solid {
+            let (_, ops) = ellipse_with_params(center, &params, &mut o);
+            sets.push(OpSet {
+                kind: OpSetKind::FillPath,
+                ops,
+            });
+        } else {
+            sets.push(pattern_fill_polygons(&[estimated_points], &mut o));
+        }
+    }
+    sets.push(stroke(outline));
+    Drawable {
+        sets,
+        options: o,
+        even_odd: false,
+    }
+}
+
+pub fn circle(center: Point, diameter: f64, o: &Options) -> Drawable {
+    ellipse(center, diameter, diameter, o)
+}
+
+/// Drops every move but the first, so a solid fill is a single shape
+fn merged_shape(ops: Vec<Op>) -> Vec<Op> {
+    ops.into_iter()
+        .enumerate()
+        .filter(|(i, op)| *i == 0 || !matches!(op, Op::Move(_)))
+        .map(|(_, op)| op)
+        .collect()
+}
+
+/// A smooth curve through the points
+pub fn curve(points: &[Point], o: &Options) -> Drawable {
+    let mut o = o.resolve();
+    let outline = curve_outline(points, &mut o);
+    let mut sets = vec![];
+    if o.fill.is_some() && points.len() >= 3 {
+        if o.fill_style == FillStyle::Solid {
+            let (disable_multi_stroke, roughness) = (o.disable_multi_stroke, o.roughness);
+            o.disable_multi_stroke = true;
+            if o.roughness != 0.0 {
+                // The fill shape is rougher than the outline
+                o.roughness += 0.8;
+            }
+            let ops = curve_outline(points, &mut o);
+            (o.disable_multi_stroke, o.roughness) = (disable_multi_stroke, roughness);
+            sets.push(OpSet {
+                kind: OpSetKind::FillPath,
+                ops: merged_shape(ops),
+            });
+        } else {
+            sets.push(pattern_fill_polygons(&[points.to_vec()], &mut o));
+        }
+    }
+    sets.push(stroke(outline));
+    Drawable {
+        sets,
+        options: o,
+        even_odd: true,
+    }
+}
+
+fn bezier_to(c1: Point, c2: Point, end: Point, current: Point, o: &mut Options) -> Vec<Op> {
+    let ros = [o.max_randomness_offset, o.max_randomness_offset + 0.3];
+    let iterations = if o.disable_multi_stroke { 1 } else { 2 };
+    let preserve = o.preserve_vertices;
+    let mut ops = vec![];
+    for (i, ro) in ros.into_iter().enumerate().take(iterations) {
+        if i == 0 || preserve {
+            ops.push(Op::Move(current));
+        } else {
+            ops.push(Op::Move([
+                current[0] + o.offset_opt(ros[0], 1.0),
+                current[1] + o.offset_opt(ros[0], 1.0),
+            ]));
+        }
+        let f = if preserve {
+            end
+        } else {
+            [
+                end[0] + o.offset_opt(ro, 1.0),
+                end[1] + o.offset_opt(ro, 1.0),
+            ]
+        };
+        ops.push(Op::BezierTo([
+            c1[0] + o.offset_opt(ro, 1.0),
+            c1[1] + o.offset_opt(ro, 1.0),
+            c2[0] + o.offset_opt(ro, 1.0),
+            c2[1] + o.offset_opt(ro, 1.0),
+            f[0],
+            f[1],
+        ]));
+    }
+    ops
+}
+
+/// Quadratic curves are drawn as the equivalent cubic ones
+fn to_cubic(segments: &[Segment]) -> Vec<Segment> {
+    let mut current = [0.0, 0.0];
+    segments
+        .iter()
+        .map(|&segment| {
+            let segment = match segment {
+                Segment::Quadratic(c, end) => Segment::Cubic(
+                    [
+                        current[0] + 2.0 / 3.0 * (c[0] - current[0]),
+                        current[1] + 2.0 / 3.0 * (c[1] - current[1]),
+                    ],
+                    [
+                        end[0] + 2.0 / 3.0 * (c[0] - end[0]),
+                        end[1] + 2.0 / 3.0 * (c[1] - end[1]),
+                    ],
+                    end,
+                ),
+                segment => segment,
+            };
+            if let Segment::Move(p) | Segment::Line(p) | Segment::Cubic(_, _, p) = segment {
+                current = p;
+            }
+            segment
+        })
+        .collect()
+}
+
+fn path_ops(segments: &[Segment], o: &mut Options) -> Vec<Op> {
+    let mut ops = vec![];
+    let mut current = [0.0, 0.0];
+    for &segment in segments {
+        match segment {
+            Segment::Move(p) => current = p,
+            Segment::Line(p) => {
+                ops.extend(double_line(current, p, o, false));
+                current = p;
+            }
+            Segment::Cubic(c1, c2, p) => {
+                ops.extend(bezier_to(c1, c2, p, current, o));
+                current = p;
+            }
+            Segment::Quadratic(..) => unreachable!("Quadratic curves are made cubic first"),
+        }
+    }
+    ops
+}
+
+/// Points along the path, to fill it with a pattern
+fn path_polygons(segments: &[Segment]) -> Vec<Vec<Point>> {
+    const STEPS: u32 = 10;
+
+    let mut polygons: Vec<Vec<Point>> = vec![];
+    let mut current = [0.0, 0.0];
+    for &segment in segments {
+        match segment {
+            Segment::Move(p) => polygons.push(vec![p]),
+            Segment::Line(p) => {
+                if let Some(polygon) = polygons.last_mut() {
+                    polygon.push(p);
+                }
+            }
+            Segment::Cubic(c1, c2, p) => {
+                if let Some(polygon) = polygons.last_mut() {
+                    for step in 1..=STEPS {
+                        let t = f64::from(step) / f64::from(STEPS);
+                        let mt = 1.0 - t;
+                        let at = |i: usize| {
+                            mt.powi(3) * current[i]
+                                + 3.0 * mt.powi(2) * t * c1[i]
+                                + 3.0 * mt * t.powi(2) * c2[i]
+                                + t.powi(3) * p[i]
+                        };
+                        polygon.push([at(0), at(1)]);
+                    }
+                }
+            }
+            Segment::Quadratic(..) => unreachable!("Quadratic curves are made cubic first"),
+        }
+        if let Segment::Move(p) | Segment::Line(p) | Segment::Cubic(_, _, p) = segment {
+            current = p;
+        }
+    }
+    polygons
+}
+
+/// A shape with an outline of lines and curves, like a rounded rectangle
+pub fn path(segments: &[Segment], o: &Options) -> Drawable {
+    let mut o = o.resolve();
+    let segments = to_cubic(segments);
+    let polygons = path_polygons(&segments);
+    let outline = path_ops(&segments, &mut o);
+
+    let mut sets = vec![];
+    if o.fill.is_some() {
+        if o.fill_style == FillStyle::Solid && polygons.len() == 1 {
+            let (disable_multi_stroke, roughness) = (o.disable_multi_stroke, o.roughness);
+            o.disable_multi_stroke = true;
+            if o.roughness != 0.0 {
+                o.roughness += 0.8;
+            }
+            let ops = path_ops(&segments, &mut o);
+            (o.disable_multi_stroke, o.roughness) = (disable_multi_stroke, roughness);
+            sets.push(OpSet {
+                kind: OpSetKind::FillPath,
+                ops: merged_shape(ops),
+            });
+        } else {
+            sets.push(fill_polygons(&polygons, &mut o));
+        }
+    }
+    sets.push(stroke(outline));
+    Drawable {
+        sets,
+        options: o,
+        even_odd: false,
+    }
+}
@@ -0,0 +1,599 @@
+//! Typed model of the excalidraw scene format, as saved in `.excalidraw`
+//! files.
+//!
+//! Fields that hdiag doesn't know about are kept in the `extra` map of the
+//! struct they belong to, so a scene can be read, changed and written back
+//! without losing anything. Fields that excalidraw always writes are filled in
+//! with its defaults when they are missing.
+//!
+//! ```
+//! use hdiag::scene::{ElementData, Scene};
+//!
+//! let scene: Scene = serde_json::from_str(
+//!     r#"{"elements": [{"id": "a", "type": "text", "text": "Hello"}]}"#,
+//! )
+//! .unwrap();
+//! let texts: Vec<_> = scene
+//!     .live_elements()
+//!     .filter_map(|e| match &e.data {
+//!         ElementData::Text(text) => Some(text.text.as_str()),
+//!         _ => None,
+//!     })
+//!     .collect();
+//! assert_eq!(texts, ["Hello"]);
+//! ```
+
+use std::collections::BTreeMap;
+
+use serde::{
+    de::{self, DeserializeOwned},
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+use serde_json::{Map, Value};
+
+/// A number written the way JavaScript writes it, so that saving a scene
+/// doesn't turn every `1` into `1.0`
+struct Num(f64);
+
+impl Serialize for Num {
+    #[allow(clippy::cast_possible_truncation)]
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        // Above 2^53 floats can't tell whole numbers apart anyway
+        if self.0.fract() == 0.0 && self.0.abs() < 9_007_199_254_740_992.0 {
+            serializer.serialize_i64(self.0 as i64)
+        } else {
+            serializer.serialize_f64(self.0)
+        }
+    }
+}
+
+#[allow(clippy::trivially_copy_pass_by_ref)]
+fn number<S: Serializer>(n: &f64, serializer: S) -> Result<S::Ok, S::Error> {
+    Num(*n).serialize(serializer)
+}
+
+fn numbers<S: Serializer>(ns: &[f64], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(ns.iter().copied().map(Num))
+}
+
+fn opt_number<S: Serializer>(n: &Option<f64>, serializer: S) -> Result<S::Ok, S::Error> {
+    n.map(Num).serialize(serializer)
+}
+
+fn point<S: Serializer>(p: &Point, serializer: S) -> Result<S::Ok, S::Error> {
+    p.map(Num).serialize(serializer)
+}
+
+fn opt_point<S: Serializer>(p: &Option<Point>, serializer: S) -> Result<S::Ok, S::Error> {
+    p.map(|p| p.map(Num)).serialize(serializer)
+}
+
+fn points<S: Serializer>(ps: &[Point], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(ps.iter().map(|p| p.map(Num)))
+}
+
+/// An `[x, y]` pair, relative to the element for the points of lines
+pub type Point = [f64; 2];
+
+/// A whole scene, as saved in an `.excalidraw` file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Scene {
+    #[serde(rename = "type", default = "default_scene_kind")]
+    pub kind: String,
+    #[serde(default = "default_scene_version")]
+    pub version: u64,
+    /// Where the scene was saved from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    #[serde(default)]
+    pub elements: Vec<Element>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub app_state: Option<AppState>,
+    /// Images used by the image elements, by file id
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub files: Option<BTreeMap<String, FileData>>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+fn default_scene_kind() -> String {
+    "excalidraw".into()
+}
+
+const fn default_scene_version() -> u64 {
+    2
+}
+
+impl Default for Scene {
+    fn default() -> Self {
+        Self {
+            kind: default_scene_kind(),
+            version: default_scene_version(),
+            source: None,
+            elements: vec![],
+            app_state: None,
+            files: None,
+            extra: Map::new(),
+        }
+    }
+}
+
+impl Scene {
+    /// Elements that are drawn, deleted ones are kept in the file for
+    /// collaboration, but are not part of the drawing
+    pub fn live_elements(&self) -> impl Iterator<Item = &Element> {
+        self.elements.iter().filter(|e| !e.base.is_deleted)
+    }
+
+    #[must_use]
+    pub fn element(&self, id: &str) -> Option<&Element> {
+        self.elements.iter().find(|e| e.id == id)
+    }
+}
+
+/// The parts of the editor state that are saved with the scene
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AppState {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub view_background_color: Option<String>,
+    #[serde(default, serialize_with = "opt_number")]
+    pub grid_size: Option<f64>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// An image embedded in the scene, as a data URL
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FileData {
+    pub id: String,
+    pub mime_type: String,
+    #[serde(rename = "dataURL")]
+    pub data_url: String,
+    /// When the file was added, in milliseconds since the epoch
+    #[serde(default)]
+    pub created: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_retrieved: Option<u64>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FillStyle {
+    #[default]
+    Hachure,
+    CrossHatch,
+    Solid,
+    Zigzag,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StrokeStyle {
+    #[default]
+    Solid,
+    Dashed,
+    Dotted,
+}
+
+/// How the corners of an element are rounded
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Roundness {
+    /// 1 is legacy, 2 is proportional to the size, 3 is adaptive
+    #[serde(rename = "type")]
+    pub kind: u8,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "opt_number"
+    )]
+    pub value: Option<f64>,
+}
+
+/// Reference to an element bound to another one, like the text in a
+/// container, or an arrow to its ends
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BoundElement {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub kind: String,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// Fields that every kind of element has
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ElementBase {
+    #[serde(serialize_with = "number")]
+    pub x: f64,
+    #[serde(serialize_with = "number")]
+    pub y: f64,
+    #[serde(serialize_with = "number")]
+    pub width: f64,
+    #[serde(serialize_with = "number")]
+    pub height: f64,
+    /// Rotation around the center, in radians
+    #[serde(serialize_with = "number")]
+    pub angle: f64,
+    pub stroke_color: String,
+    pub background_color: String,
+    pub fill_style: FillStyle,
+    #[serde(serialize_with = "number")]
+    pub stroke_width: f64,
+    pub stroke_style: StrokeStyle,
+    /// 0 is architect, 1 is artist and 2 is cartoonist
+    #[serde(serialize_with = "number")]
+    pub roughness: f64,
+    /// From 0 to 100
+    #[serde(serialize_with = "number")]
+    pub opacity: f64,
+    pub group_ids: Vec<String>,
+    pub frame_id: Option<String>,
+    pub roundness: Option<Roundness>,
+    /// Seed of the random numbers used to draw the rough strokes
+    pub seed: u64,
+    pub version: u64,
+    pub version_nonce: u64,
+    pub is_deleted: bool,
+    pub bound_elements: Option<Vec<BoundElement>>,
+    /// When the element was last changed, in milliseconds since the epoch
+    pub updated: u64,
+    pub link: Option<String>,
+    pub locked: bool,
+}
+
+impl Default for ElementBase {
+    fn default() -> Self {
+        Self {
+            x: 0.0,
+            y: 0.0,
+            width: 0.0,
+            height: 0.0,
+            angle: 0.0,
+            stroke_color: "#1e1e1e".into(),
+            background_color: "transparent".into(),
+            fill_style: FillStyle::default(),
+            stroke_width: 1.0,
+            stroke_style: StrokeStyle::default(),
+            roughness: 1.0,
+            opacity: 100.0,
+            group_ids: vec![],
+            frame_id: None,
+            roundness: None,
+            seed: 1,
+            version: 1,
+            version_nonce: 0,
+            is_deleted: false,
+            bound_elements: None,
+            updated: 1,
+            link: None,
+            locked: false,
+        }
+    }
+}
+
+/// Virgil, the handwritten font
+pub const FONT_FAMILY_VIRGIL: u32 = 1;
+pub const FONT_FAMILY_HELVETICA: u32 = 2;
+/// Cascadia, the code font
+pub const FONT_FAMILY_CASCADIA: u32 = 3;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TextAlign {
+    #[default]
+    Left,
+    Center,
+    Right,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VerticalAlign {
+    #[default]
+    Top,
+    Middle,
+    Bottom,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Text {
+    #[serde(serialize_with = "number")]
+    pub font_size: f64,
+    pub font_family: u32,
+    pub text: String,
+    pub text_align: TextAlign,
+    pub vertical_align: VerticalAlign,
+    /// The shape the text is drawn in, if any
+    pub container_id: Option<String>,
+    /// The text before it was wrapped to fit its container
+    pub original_text: String,
+    /// Height of a line, relative to the font size
+    #[serde(serialize_with = "number")]
+    pub line_height: f64,
+}
+
+impl Default for Text {
+    fn default() -> Self {
+        Self {
+            font_size: 20.0,
+            font_family: FONT_FAMILY_VIRGIL,
+            text: String::new(),
+            text_align: TextAlign::default(),
+            vertical_align: VerticalAlign::default(),
+            container_id: None,
+            original_text: String::new(),
+            line_height: 1.25,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Arrowhead {
+    Arrow,
+    Bar,
+    Dot,
+    Circle,
+    CircleOutline,
+    Triangle,
+    TriangleOutline,
+    Diamond,
+    DiamondOutline,
+}
+
+/// Where the end of an arrow is attached to another element
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Binding {
+    pub element_id: String,
+    #[serde(default, serialize_with = "number")]
+    pub focus: f64,
+    #[serde(default, serialize_with = "number")]
+    pub gap: f64,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// Lines and arrows
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Linear {
+    #[serde(serialize_with = "points")]
+    pub points: Vec<Point>,
+    #[serde(serialize_with = "opt_point")]
+    pub last_committed_point: Option<Point>,
+    pub start_binding: Option<Binding>,
+    pub end_binding: Option<Binding>,
+    pub start_arrowhead: Option<Arrowhead>,
+    pub end_arrowhead: Option<Arrowhead>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Freedraw {
+    #[serde(serialize_with = "points")]
+    pub points: Vec<Point>,
+    /// Pen pressure at every point, from 0 to 1
+    #[serde(serialize_with = "numbers")]
+    pub pressures: Vec<f64>,
+    /// Whether the pressure is made up from the speed of the stroke
+    pub simulate_pressure: bool,
+    #[serde(serialize_with = "opt_point")]
+    pub last_committed_point: Option<Point>,
+}
+
+impl Default for Freedraw {
+    fn default() -> Self {
+        Self {
+            points: vec![],
+            pressures: vec![],
+            simulate_pressure: true,
+            last_committed_point: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImageStatus {
+    #[default]
+    Pending,
+    Saved,
+    Error,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Image {
+    /// Key of the image in the scene's files
+    pub file_id: Option<String>,
+    pub status: ImageStatus,
+    /// -1 flips the image on that axis
+    #[serde(serialize_with = "point")]
+    pub scale: [f64; 2],
+}
+
+impl Default for Image {
+    fn default() -> Self {
+        Self {
+            file_id: None,
+            status: ImageStatus::default(),
+            scale: [1.0, 1.0],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Frame {
+    pub name: Option<String>,
+}
+
+/// Data of the kinds of elements that have no fields of their own
+#[derive(Serialize, Deserialize)]
+struct Empty {}
+
+/// What kind of element it is, with the fields specific to that kind
+#[derive(Debug, Clone, PartialEq)]
+pub enum ElementData {
+    Rectangle,
+    Ellipse,
+    Diamond,
+    Text(Text),
+    Line(Linear),
+    Arrow(Linear),
+    Freedraw(Freedraw),
+    Image(Image),
+    Frame(Frame),
+    MagicFrame(Frame),
+    /// A kind hdiag doesn't model, its fields are all in `extra`
+    Other(String),
+}
+
+impl ElementData {
+    /// The `type` of the element in the file
+    #[must_use]
+    pub fn kind(&self) -> &str {
+        match self {
+            Self::Rectangle => "rectangle",
+            Self::Ellipse => "ellipse",
+            Self::Diamond => "diamond",
+            Self::Text(_) => "text",
+            Self::Line(_) => "line",
+            Self::Arrow(_) => "arrow",
+            Self::Freedraw(_) => "freedraw",
+            Self::Image(_) => "image",
+            Self::Frame(_) => "frame",
+            Self::MagicFrame(_) => "magicframe",
+            Self::Other(kind) => kind,
+        }
+    }
+}
+
+/// An element of the scene, the `type` field picks the variant of `data`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Element {
+    pub id: String,
+    pub base: ElementBase,
+    pub data: ElementData,
+    pub extra: Map<String, Value>,
+}
+
+impl Element {
+    #[must_use]
+    pub fn new(id: impl Into<String>, data: ElementData) -> Self {
+        Self {
+            id: id.into(),
+            base: ElementBase::default(),
+            data,
+            extra: Map::new(),
+        }
+    }
+
+    #[must_use]
+    pub const fn is_frame(&self) -> bool {
+        matches!(
+            self.data,
+            ElementData::Frame(_) | ElementData::MagicFrame(_)
+        )
+    }
+}
+
+#[derive(Serialize)]
+struct TaggedRef<'a, T> {
+    id: &'a str,
+    #[serde(rename = "type")]
+    kind: &'a str,
+    #[serde(flatten)]
+    base: &'a ElementBase,
+    #[serde(flatten)]
+    data: &'a T,
+    #[serde(flatten)]
+    extra: &'a Map<String, Value>,
+}
+
+impl Serialize for Element {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        fn tagged<'a, T>(element: &'a Element, data: &'a T) -> TaggedRef<'a, T> {
+            TaggedRef {
+                id: &element.id,
+                kind: element.data.kind(),
+                base: &element.base,
+                data,
+                extra: &element.extra,
+            }
+        }
+
+        match &self.data {
+            ElementData::Rectangle
+            | ElementData::Ellipse
+            | ElementData::Diamond
+            | ElementData::Other(_) => tagged(self, &Empty {}).serialize(serializer),
+            ElementData::Text(text) => tagged(self, text).serialize(serializer),
+            ElementData::Line(linear) | ElementData::Arrow(linear) => {
+                tagged(self, linear).serialize(serializer)
+            }
+            ElementData::Freedraw(freedraw) => tagged(self, freedraw).serialize(serializer),
+            ElementData::Image(image) => tagged(self, image).serialize(serializer),
+            ElementData::Frame(frame) | ElementData::MagicFrame(frame) => {
+                tagged(self, frame).serialize(serializer)
+            }
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct Tagged<T> {
+    id: String,
+    #[serde(flatten)]
+    base: ElementBase,
+    #[serde(flatten)]
+    data: T,
+    #[serde(flatten)]
+    extra: Map<String, Value>,
+}
+
+impl<'de> Deserialize<'de> for Element {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        fn element<T: DeserializeOwned, E: de::Error>(
+            fields: Map<String, Value>,
+            data: impl FnOnce(T) -> ElementData,
+        ) -> Result<Element, E> {
+            let tagged: Tagged<T> =
+                serde_json::from_value(Value::Object(fields)).map_err(E::custom)?;
+            Ok(Element {
+                id: tagged.id,
+                base: tagged.base,
+                data: data(tagged.data),
+                extra: tagged.extra,
+            })
+        }
+
+        let mut fields = Map::deserialize(deserializer)?;
+        let kind = match fields.remove("type") {
+            Some(Value::String(kind)) => kind,
+            Some(_) => return Err(de::Error::custom("element type is not a string")),
+            None => return Err(de::Error::missing_field("type")),
+        };
+        match kind.as_str() {
+            "rectangle" => element(fields, |Empty {}| ElementData::Rectangle),
+            "ellipse" => element(fields, |Empty {}| ElementData::Ellipse),
+            "diamond" => element(fields, |Empty {}| ElementData::Diamond),
+            "text" => element(fields, ElementData::Text),
+            "line" => element(fields, ElementData::Line),
+            "arrow" => element(fields, ElementData::Arrow),
+            "freedraw" => element(fields, ElementData::Freedraw),
+            "image" => element(fields, ElementData::Image),
+            "frame" => element(fields, ElementData::Frame),
+            "magicframe" => element(fields, ElementData::MagicFrame),
+            _ => element(fields, |Empty {}| ElementData::Other(kind.clone())),
+        }
+    }
+}
@@ -1,4 +1,4 @@
-use std::{io::Cursor, net::SocketAddr, path::PathBuf, sync::Arc};
+use std::{io::Cursor, net::SocketAddr, path::PathBuf, sync::Arc, time::Duration};
 
 use axum::{
     body::{Body, Bytes},
@@ -8,12 +8,25 @@ use axum::{
     routing::{get, post},
     Router,
 };
-use color_eyre::{eyre::eyre, Result};
+use color_eyre::{
+    eyre::{bail, eyre, WrapErr as _},
+    Result,
+};
 use headless_chrome::{Browser, LaunchOptionsBuilder};
-use tokio::{net::TcpListener, sync::mpsc::Sender, task::spawn_blocking};
+use tokio::{
+    net::TcpListener,
+    sync::mpsc::{self, Sender},
+    task::spawn_blocking,
+};
 use tracing::{debug, info, warn};
 use zip::ZipArchive;
 
+use crate::assets::{self, Assets};
+
+/// How long to wait for the app to post back its output, before giving up
+/// on a page that failed without telling
+const RENDER_TIMEOUT: Duration = Duration::from_secs(120);
+
 #[allow(clippy::cast_precision_loss)]
 pub fn size_str(n: u64) -> String {
     const BYTE_SIZE: u64 = 1024;
@@ -41,30 +54,17 @@ struct AppState {
     input_name: Arc<str>,
     svg_channel: Arc<Sender<Vec<u8>>>,
     export_opts: Arc<serde_json::Value>,
+    /// Images for the page that are read from disk, by file id
+    assets: Arc<Assets>,
 }
 
 type StatusResult<T> = Result<T, (StatusCode, String)>;
 
-pub async fn http_serve(
-    listener: TcpListener,
-    name: &str,
-    input_name: &str,
-    input_contents: &[u8],
-    zip_bytes: &[u8],
-    svg_channel: Arc<Sender<Vec<u8>>>,
-    export_opts: serde_json::Value,
-) -> Result<()> {
-    let state = AppState {
-        zip_file: zip_bytes.to_vec().into(),
-        input_contents: input_contents.to_vec().into(),
-        input_name: input_name.to_string().into(),
-        svg_channel,
-        export_opts: Arc::new(export_opts),
-    };
-
+async fn http_serve(listener: TcpListener, name: &str, state: AppState) -> Result<()> {
     let app = Router::new()
         .route("/", get(fetch_root_from_zip))
         .route("/export_opts", get(fetch_export_opts))
+        .route("/assets/:file_id", get(fetch_asset))
         .route("/*path", get(fetch_from_zip))
         .route("/return", post(output_from_app))
         .with_state(state);
@@ -125,6 +125,30 @@ async fn fetch_export_opts(State(state): State<AppState>) -> Response<Body> {
         .expect("Failed creating response from export options")
 }
 
+async fn fetch_asset(
+    State(state): State<AppState>,
+    extract::Path(file_id): extract::Path<String>,
+) -> StatusResult<Response<Body>> {
+    let path = state
+        .assets
+        .get(&file_id)
+        .ok_or((StatusCode::NOT_FOUND, "Asset was not found".to_string()))?;
+    debug!(file_id, path = %path.display(), "Requested asset");
+    let bytes = tokio::fs::read(path).await.map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to read asset {}: {e}", path.display()),
+        )
+    })?;
+
+    let res = Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, assets::mime_type(path).essence_str())
+        .body(Body::from(bytes))
+        .expect("Couldn't make response");
+    Ok(res)
+}
+
 async fn fetch_path_from_zip(state: AppState, path: PathBuf) -> StatusResult<Response<Body>> {
     let mime = path
         .extension()
@@ -186,3 +210,59 @@ pub fn goto_page_chrome(addr: SocketAddr) -> Result<()> {
 
     Ok(())
 }
+
+/// Serves the app in the zip with the input and assets, opens it in headless
+/// Chrome, and returns what the app posts back
+pub async fn render_in_browser(
+    name: &'static str,
+    input_name: &'static str,
+    input_contents: Vec<u8>,
+    zip_bytes: &'static [u8],
+    export_opts: serde_json::Value,
+    assets: Assets,
+) -> Result<Vec<u8>> {
+    let addr = SocketAddr::from(([127, 0, 0, 1], 0));
+    let listener = tokio::net::TcpListener::bind(addr).await?;
+    let addr = listener
+        .local_addr()
+        .expect("The listener is already bound");
+
+    let (tx, mut rx) = mpsc::channel(1);
+    let state = AppState {
+        zip_file: zip_bytes.into(),
+        input_contents: input_contents.into(),
+        input_name: input_name.into(),
+        svg_channel: Arc::new(tx),
+        export_opts: Arc::new(export_opts),
+        assets: Arc::new(assets),
+    };
+    let http_server = tokio::spawn(async move { http_serve(listener, name, state).await });
+
+    let mut chrome = spawn_blocking(move || goto_page_chrome(addr));
+    let received = tokio::time::timeout(RENDER_TIMEOUT, async {
+        tokio::select! {
+            received = rx.recv() => Ok(received),
+            // Failing to launch Chrome is reported right away, once the page
+            // is open the app still has to post back
+            opened = &mut chrome => {
+                opened
+                    .wrap_err("Chrome task panicked")?
+                    .wrap_err("Failed to open the page in Chrome")?;
+                Ok(rx.recv().await)
+            }
+        }
+    })
+    .await;
+    http_server.abort();
+    chrome.abort();
+
+    match received {
+        Ok(Ok(Some(bytes))) => Ok(bytes),
+        Ok(Ok(None)) => bail!("Sender for svg dropped unexpectedly"),
+        Ok(Err(e)) => Err(e),
+        Err(_) => bail!(
+            "The {name} app posted nothing back in {} seconds",
+            RENDER_TIMEOUT.as_secs()
+        ),
+    }
+}
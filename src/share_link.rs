@@ -0,0 +1,265 @@
+use std::io::Read as _;
+
+use aes_gcm::{aead::Aead as _, Aes128Gcm, KeyInit as _, Nonce};
+use base64::prelude::*;
+use color_eyre::{
+    eyre::{bail, eyre, ContextCompat, WrapErr},
+    Result,
+};
+use flate2::read::ZlibDecoder;
+use hdiag::scene::{ElementData, Scene};
+use tokio::runtime::Runtime;
+use tracing::{debug, warn};
+
+use crate::{
+    backend::{DiagramBackend, Supports},
+    cli, excalidraw, Output,
+};
+
+/// Version of the format used to concatenate several buffers into one
+const CONCAT_BUFFERS_VERSION: u32 = 1;
+const IV_LENGTH: usize = 12;
+
+/// Extracts the key from a share link, its `#json=id,key` fragment, or just
+/// the key itself
+pub fn parse_key(link: &str) -> &str {
+    let fragment = link.rsplit_once("#json=").map_or(link, |(_, f)| f);
+    fragment.rsplit_once(',').map_or(fragment, |(_, key)| key)
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Result<u32> {
+    let chunk = bytes
+        .get(offset..(offset + 4))
+        .context("Buffer ended before the chunk size")?;
+    Ok(u32::from_be_bytes(
+        chunk.try_into().expect("Chunk is exactly 4 bytes"),
+    ))
+}
+
+/// Splits a buffer made by excalidraw's `concatBuffers`, which is a version
+/// followed by chunks prefixed by their size, all big endian `u32`s
+fn split_buffers(bytes: &[u8]) -> Result<Vec<&[u8]>> {
+    let version = read_u32(bytes, 0)?;
+    if version > CONCAT_BUFFERS_VERSION {
+        bail!("Unsupported buffer version {version}");
+    }
+
+    let mut chunks = vec![];
+    let mut cursor = 4;
+    while cursor < bytes.len() {
+        let size = read_u32(bytes, cursor)? as usize;
+        cursor += 4;
+        let chunk = bytes
+            .get(cursor..(cursor + size))
+            .context("Buffer ended before the end of a chunk")?;
+        chunks.push(chunk);
+        cursor += size;
+    }
+    Ok(chunks)
+}
+
+fn decrypt(cipher: &Aes128Gcm, iv: &[u8], encrypted: &[u8]) -> Result<Vec<u8>> {
+    if iv.len() != IV_LENGTH {
+        bail!("IV must be {IV_LENGTH} bytes, was {}", iv.len());
+    }
+    cipher
+        .decrypt(Nonce::from_slice(iv), encrypted)
+        .map_err(|_| eyre!("Failed to decrypt scene, the key is probably wrong"))
+}
+
+/// The current format, with metadata about the encoding, and the scene
+/// compressed before encrypting it
+fn decode(blob: &[u8], cipher: &Aes128Gcm) -> Result<Vec<u8>> {
+    let [encoding, iv, encrypted] = split_buffers(blob)?[..] else {
+        bail!("Expected an encoding, an IV and the encrypted scene");
+    };
+    let encoding: serde_json::Value =
+        serde_json::from_slice(encoding).wrap_err("Encoding metadata is not JSON")?;
+    let is_compressed = encoding
+        .get("compression")
+        .and_then(serde_json::Value::as_str)
+        .is_some_and(|c| !c.is_empty());
+
+    let decrypted = decrypt(cipher, iv, encrypted)?;
+    let decrypted = if is_compressed {
+        let mut decompressed = vec![];
+        ZlibDecoder::new(decrypted.as_slice())
+            .read_to_end(&mut decompressed)
+            .wrap_err("Failed to decompress scene")?;
+        decompressed
+    } else {
+        decrypted
+    };
+
+    let [_metadata, contents] = split_buffers(&decrypted)?[..] else {
+        bail!("Expected the metadata and contents of the scene");
+    };
+    Ok(contents.to_vec())
+}
+
+/// The format from before there was any metadata, which is just the IV and
+/// then the encrypted scene
+fn decode_legacy(blob: &[u8], cipher: &Aes128Gcm) -> Result<Vec<u8>> {
+    if blob.len() < IV_LENGTH {
+        bail!("Scene is too short to have an IV");
+    }
+    let (iv, encrypted) = blob.split_at(IV_LENGTH);
+    decrypt(cipher, iv, encrypted)
+}
+
+/// Decrypts the scene behind a share link, as it is saved from
+/// `https://json.excalidraw.com/api/v2/<id>`
+pub fn decrypt_scene(blob: &[u8], key: &str) -> Result<Vec<u8>> {
+    let key = BASE64_URL_SAFE_NO_PAD
+        .decode(key.trim_end_matches('='))
+        .wrap_err("Share link key is not valid base64")?;
+    let cipher = Aes128Gcm::new_from_slice(&key)
+        .map_err(|_| eyre!("Share link key must be 16 bytes, was {}", key.len()))?;
+
+    let scene = decode(blob, &cipher).or_else(|e| {
+        debug!("Scene is not in the current share link format, trying legacy: {e}");
+        decode_legacy(blob, &cipher)
+    })?;
+
+    // Images are uploaded separately from the scene, so they can't be in it
+    let has_images = serde_json::from_slice::<Scene>(&scene).is_ok_and(|s| {
+        s.elements
+            .iter()
+            .any(|e| matches!(e.data, ElementData::Image(_)))
+    });
+    if has_images {
+        warn!("Images in share links are stored separately, and will be missing");
+    }
+
+    Ok(scene)
+}
+
+pub struct ShareLink;
+
+impl DiagramBackend for ShareLink {
+    fn name(&self) -> &'static str {
+        "share-link"
+    }
+
+    fn description(&self) -> &'static str {
+        "Encrypted scene of an Excalidraw share link, as saved from json.excalidraw.com. \
+         Needs `--share-key`"
+    }
+
+    fn detect(&self, _contents: &[u8]) -> Result<()> {
+        bail!("scenes are encrypted, pass `--share-key` to read one")
+    }
+
+    fn supports(&self) -> Supports {
+        excalidraw::SCENE_SUPPORTS
+    }
+
+    fn render(&self, rt: &Runtime, opts: &cli::Opts, input_contents: &[u8]) -> Result<Vec<Output>> {
+        let key = opts
+            .share_key
+            .as_deref()
+            .context("Share links need a key to decrypt them, pass it with `--share-key`")?;
+        let scene =
+            decrypt_scene(input_contents, key).wrap_err("Failed decrypting share link scene")?;
+        excalidraw::render_scene(rt, opts, scene)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write as _;
+
+    use flate2::{write::ZlibEncoder, Compression};
+
+    use super::*;
+
+    const KEY: &str = "AAECAwQFBgcICQoLDA0ODw";
+    const IV: [u8; IV_LENGTH] = [7; IV_LENGTH];
+    const SCENE: &[u8] = br#"{"type":"excalidraw","elements":[]}"#;
+
+    /// Concatenates buffers the way excalidraw's `concatBuffers` does
+    fn concat_buffers(buffers: &[&[u8]]) -> Vec<u8> {
+        let mut bytes = CONCAT_BUFFERS_VERSION.to_be_bytes().to_vec();
+        for buffer in buffers {
+            let size = u32::try_from(buffer.len()).unwrap_or(u32::MAX);
+            bytes.extend(size.to_be_bytes());
+            bytes.extend(*buffer);
+        }
+        bytes
+    }
+
+    fn encrypt(plain: &[u8]) -> Result<Vec<u8>> {
+        let key = BASE64_URL_SAFE_NO_PAD.decode(KEY)?;
+        let cipher = Aes128Gcm::new_from_slice(&key).map_err(|e| eyre!("{e}"))?;
+        cipher
+            .encrypt(Nonce::from_slice(&IV), plain)
+            .map_err(|e| eyre!("{e}"))
+    }
+
+    #[test]
+    fn parse_key_accepts_links_fragments_and_keys() {
+        for link in [
+            "https://excalidraw.com/#json=abc123,secretKey",
+            "#json=abc123,secretKey",
+            "abc123,secretKey",
+            "secretKey",
+        ] {
+            assert_eq!(parse_key(link), "secretKey", "{link}");
+        }
+    }
+
+    #[test]
+    fn split_buffers_reverses_concat_buffers() -> Result<()> {
+        let buffers: [&[u8]; 3] = [b"first", b"", b"third"];
+        assert_eq!(split_buffers(&concat_buffers(&buffers))?, buffers);
+        assert!(split_buffers(&concat_buffers(&[]))?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn split_buffers_rejects_broken_buffers() {
+        let mut truncated = concat_buffers(&[b"chunk"]);
+        truncated.pop();
+        assert!(split_buffers(&truncated).is_err());
+        // The size of a chunk is cut short
+        assert!(split_buffers(&[0, 0, 0, 1, 0, 0]).is_err());
+        assert!(split_buffers(&[0, 0]).is_err());
+
+        let mut newer = concat_buffers(&[b"chunk"]);
+        newer[3] = 2;
+        assert_eq!(
+            split_buffers(&newer).map_err(|e| e.to_string()),
+            Err("Unsupported buffer version 2".to_owned())
+        );
+    }
+
+    #[test]
+    fn decrypt_scene_reads_compressed_scenes() -> Result<()> {
+        let mut encoder = ZlibEncoder::new(vec![], Compression::default());
+        encoder.write_all(&concat_buffers(&[b"{}", SCENE]))?;
+        let encrypted = encrypt(&encoder.finish()?)?;
+        let blob = concat_buffers(&[
+            br#"{"version":2,"compression":"pako@1","encryption":"AES-GCM"}"#,
+            &IV,
+            &encrypted,
+        ]);
+        assert_eq!(decrypt_scene(&blob, KEY)?, SCENE);
+        Ok(())
+    }
+
+    #[test]
+    fn decrypt_scene_reads_legacy_scenes() -> Result<()> {
+        let blob = [IV.as_slice(), &encrypt(SCENE)?].concat();
+        assert_eq!(decrypt_scene(&blob, KEY)?, SCENE);
+        Ok(())
+    }
+
+    #[test]
+    fn decrypt_scene_rejects_wrong_keys() -> Result<()> {
+        let blob = [IV.as_slice(), &encrypt(SCENE)?].concat();
+        assert!(decrypt_scene(&blob, "AAAAAAAAAAAAAAAAAAAAAA").is_err());
+        assert!(decrypt_scene(&blob, "c2hvcnQ").is_err());
+        assert!(decrypt_scene(&blob, "not base64!").is_err());
+        Ok(())
+    }
+}
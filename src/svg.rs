@@ -0,0 +1,348 @@
+use std::{collections::HashMap, fmt::Write as _};
+
+use color_eyre::{
+    eyre::{bail, ContextCompat, WrapErr},
+    Result,
+};
+
+use crate::{cli, drawio};
+
+const TITLE_ID: &str = "hdiag-title";
+const DESC_ID: &str = "hdiag-desc";
+
+/// Filter on the root of dark themed svgs, the one excalidraw uses
+pub const DARK_THEME_FILTER: &str = "invert(93%) hue-rotate(180deg)";
+
+pub fn escape_xml(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Returns the position of the `>` that closes the root `<svg ...>` tag
+fn root_tag_end(svg: &str) -> Result<usize> {
+    let start = svg.find("<svg").context("SVG has no root svg tag")?;
+    let end = svg[start..]
+        .find('>')
+        .context("SVG root tag is never closed")?;
+    let end = start + end;
+    if svg[..end].ends_with('/') {
+        bail!("SVG root tag has no children");
+    }
+    Ok(end)
+}
+
+/// Adds a `<title>` and optionally a `<desc>` to the root of the svg,
+/// and marks it as an image for assistive technology
+pub fn add_accessibility(svg: &str, title: &str, description: Option<&str>) -> Result<String> {
+    let tag_end = root_tag_end(svg)?;
+
+    let labelled_by = if description.is_some() {
+        format!("{TITLE_ID} {DESC_ID}")
+    } else {
+        TITLE_ID.to_owned()
+    };
+
+    let mut children = format!("<title id=\"{TITLE_ID}\">{}</title>", escape_xml(title));
+    if let Some(description) = description {
+        write!(
+            children,
+            "<desc id=\"{DESC_ID}\">{}</desc>",
+            escape_xml(description)
+        )
+        .wrap_err("Failed writing svg description")?;
+    }
+
+    let before = &svg[..tag_end];
+    let after = &svg[(tag_end + 1)..];
+    Ok(format!(
+        "{before} role=\"img\" aria-labelledby=\"{labelled_by}\">{children}{after}"
+    ))
+}
+
+/// Length of the CSS identifier at the start of the text
+fn css_ident_len(text: &str) -> usize {
+    text.find(|c: char| !(c.is_alphanumeric() || c == '-' || c == '_'))
+        .unwrap_or(text.len())
+}
+
+/// Renames every `id` in the svg with `rename`, given the id and the order
+/// it first appears in, and updates all the references to it, including the
+/// `#id` selectors of its stylesheets
+fn rename_ids(svg: &str, mut rename: impl FnMut(&str, usize) -> String) -> String {
+    const ID_ATTR: &str = " id=\"";
+    const LABELLED_BY: &str = " aria-labelledby=\"";
+    const REFERENCES: [(&str, char); 3] = [(ID_ATTR, '"'), ("url(#", ')'), ("href=\"#", '"')];
+
+    let mut ids = HashMap::new();
+    let mut slice = svg;
+    while let Some(idx) = slice.find(ID_ATTR) {
+        let start_idx = idx + ID_ATTR.len();
+        let Some(end_idx) = slice[start_idx..].find('"') else {
+            break;
+        };
+        let end_idx = end_idx + start_idx;
+        let next = ids.len();
+        let id = &slice[start_idx..end_idx];
+        ids.entry(id).or_insert_with(|| rename(id, next));
+        slice = &slice[end_idx..];
+    }
+
+    let mut output = String::with_capacity(svg.len());
+    let mut slice = svg;
+    let mut in_style = false;
+    'outer: while !slice.is_empty() {
+        for (prefix, terminator) in REFERENCES {
+            if !slice.starts_with(prefix) {
+                continue;
+            }
+            let start_idx = prefix.len();
+            let Some(end_idx) = slice[start_idx..].find(terminator) else {
+                continue;
+            };
+            let end_idx = end_idx + start_idx;
+            if let Some(new_id) = ids.get(&slice[start_idx..end_idx]) {
+                output.push_str(prefix);
+                output.push_str(new_id);
+                slice = &slice[end_idx..];
+                continue 'outer;
+            }
+        }
+        // Lists the ids of the title and description
+        if slice.starts_with(LABELLED_BY) {
+            if let Some(end_idx) = slice[LABELLED_BY.len()..].find('"') {
+                let end_idx = end_idx + LABELLED_BY.len();
+                let labels = slice[LABELLED_BY.len()..end_idx]
+                    .split_whitespace()
+                    .map(|id| ids.get(id).map_or(id, String::as_str))
+                    .collect::<Vec<_>>();
+                output.push_str(LABELLED_BY);
+                output.push_str(&labels.join(" "));
+                slice = &slice[end_idx..];
+                continue;
+            }
+        }
+        if slice.starts_with("<style") {
+            in_style = true;
+        } else if slice.starts_with("</style") {
+            in_style = false;
+        } else if in_style && slice.starts_with('#') {
+            let len = css_ident_len(&slice[1..]);
+            if let Some(new_id) = ids.get(&slice[1..=len]) {
+                output.push('#');
+                output.push_str(new_id);
+                slice = &slice[len + 1..];
+                continue;
+            }
+        }
+        let c = slice.chars().next().expect("Slice is not empty");
+        output.push(c);
+        slice = &slice[c.len_utf8()..];
+    }
+    output
+}
+
+/// Renames every `id` in the svg to a name based on the order it appears
+/// in, and updates all the references to it
+pub fn normalize_ids(svg: &str) -> String {
+    rename_ids(svg, |_, position| format!("hdiag-{position}"))
+}
+
+/// Prefixes every `id` in the svg, so it can be put in a document with other
+/// svgs without their ids clashing
+pub fn prefix_ids(svg: &str, prefix: &str) -> String {
+    rename_ids(svg, |id, _| format!("{prefix}-{id}"))
+}
+
+/// Returns the value of an attribute of the root `<svg>` tag
+fn root_attribute<'a>(svg: &'a str, name: &str) -> Option<&'a str> {
+    let start = svg.find("<svg")?;
+    let end = start + svg[start..].find('>')?;
+    let tag = &svg[start..end];
+
+    let pat = format!(" {name}=\"");
+    let value_start = tag.find(&pat)? + pat.len();
+    let value_end = value_start + tag[value_start..].find('"')?;
+    Some(&tag[value_start..value_end])
+}
+
+/// Returns the width and height of the svg
+pub fn dimensions(svg: &str) -> Result<(f64, f64)> {
+    let parse = |name| {
+        root_attribute(svg, name)
+            .with_context(|| format!("SVG has no {name}"))?
+            .trim_end_matches("px")
+            .parse::<f64>()
+            .wrap_err_with(|| format!("SVG {name} is not a number"))
+    };
+    Ok((parse("width")?, parse("height")?))
+}
+
+/// Positions an svg to nest it inside another one
+pub fn with_position(svg: &str, x: f64, y: f64) -> Result<String> {
+    let start = svg.find("<svg").context("SVG has no root svg tag")? + "<svg".len();
+    let before = &svg[..start];
+    let after = &svg[start..];
+    Ok(format!("{before} x=\"{x}\" y=\"{y}\"{after}"))
+}
+
+/// Sets an attribute of the root `<svg>` tag, replacing its value if it is
+/// already set
+pub fn set_root_attribute(svg: &str, name: &str, value: &str) -> Result<String> {
+    let start = svg.find("<svg").context("SVG has no root svg tag")?;
+    let end = root_tag_end(svg)?;
+    let tag = &svg[start..end];
+    let value = escape_xml(value);
+
+    let pat = format!(" {name}=\"");
+    if let Some(value_start) = tag.find(&pat).map(|i| start + i + pat.len()) {
+        let value_end = value_start
+            + svg[value_start..]
+                .find('"')
+                .context("SVG attribute is never closed")?;
+        return Ok(format!(
+            "{}{value}{}",
+            &svg[..value_start],
+            &svg[value_end..]
+        ));
+    }
+    Ok(format!("{} {name}=\"{value}\"{}", &svg[..end], &svg[end..]))
+}
+
+/// Multiplies the width and height of the svg, keeping their units
+pub fn scaled(svg: &str, scale: f64) -> Result<String> {
+    let mut svg = svg.to_owned();
+    for name in ["width", "height"] {
+        let Some(value) = root_attribute(&svg, name) else {
+            continue;
+        };
+        let unit_start = value
+            .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .unwrap_or(value.len());
+        let (number, unit) = value.split_at(unit_start);
+        let number = number
+            .parse::<f64>()
+            .wrap_err_with(|| format!("SVG {name} is not a number"))?;
+        svg = set_root_attribute(&svg, name, &format!("{}{unit}", number * scale))?;
+    }
+    Ok(svg)
+}
+
+/// Contents of every `<text>` in the svg, in the order they appear
+pub fn texts(svg: &str) -> Vec<String> {
+    let mut texts = vec![];
+    let mut rest = svg;
+    while let Some(start) = rest.find("<text") {
+        let Some(content_start) = rest[start..].find('>').map(|i| start + i + 1) else {
+            break;
+        };
+        let Some(content_end) = rest[content_start..]
+            .find("</text>")
+            .map(|i| content_start + i)
+        else {
+            break;
+        };
+        let text = drawio::html_to_text(&rest[content_start..content_end]);
+        let text = text.trim();
+        if !text.is_empty() {
+            texts.push(text.to_owned());
+        }
+        rest = &rest[content_end..];
+    }
+    texts
+}
+
+/// Applies the export options to an svg from a renderer that knows nothing
+/// of them, darkening it like excalidraw does
+pub fn apply_export(
+    svg: &str,
+    export_opts: &cli::ExportOpts,
+    accessibility: Option<&cli::AccessibilityOpts>,
+) -> Result<String> {
+    let mut svg = scaled(svg, f64::from(export_opts.scale))?;
+    if export_opts.theme == cli::OutputTheme::Dark {
+        svg = set_root_attribute(&svg, "filter", DARK_THEME_FILTER)?;
+    }
+    if export_opts.deterministic {
+        svg = normalize_ids(&svg);
+    }
+    let Some(accessibility) = accessibility else {
+        return Ok(svg);
+    };
+    let description = accessibility
+        .description
+        .clone()
+        .or_else(|| Some(texts(&svg).join("\n")).filter(|d| !d.is_empty()));
+    add_accessibility(&svg, &accessibility.title, description.as_deref())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_ids_renames_in_order_of_appearance() {
+        let svg = r#"<svg><defs><clipPath id="clip-a"/><mask id="mask-b"/></defs><g clip-path="url(#clip-a)" mask="url(#mask-b)"/></svg>"#;
+        assert_eq!(
+            normalize_ids(svg),
+            r#"<svg><defs><clipPath id="hdiag-0"/><mask id="hdiag-1"/></defs><g clip-path="url(#hdiag-0)" mask="url(#hdiag-1)"/></svg>"#
+        );
+    }
+
+    #[test]
+    fn normalize_ids_updates_hrefs() {
+        let svg = r##"<svg><symbol id="s"/><use href="#s"/><use xlink:href="#s"/></svg>"##;
+        assert_eq!(
+            normalize_ids(svg),
+            r##"<svg><symbol id="hdiag-0"/><use href="#hdiag-0"/><use xlink:href="#hdiag-0"/></svg>"##
+        );
+    }
+
+    #[test]
+    fn normalize_ids_round_trips() {
+        let svg = r#"<svg><path id="x1"/><path id="x2" fill="url(#x1)"/></svg>"#;
+        let normalized = normalize_ids(svg);
+        assert_eq!(normalize_ids(&normalized), normalized);
+        // Renders with other random ids normalize to the same svg
+        let other = svg.replace("x1", "random-7").replace("x2", "random-3");
+        assert_eq!(normalize_ids(&other), normalized);
+    }
+
+    #[test]
+    fn normalize_ids_leaves_unknown_references() {
+        let svg = r##"<svg><path id="a"/><g fill="url(#elsewhere)"/><a href="#top"/></svg>"##;
+        assert_eq!(
+            normalize_ids(svg),
+            r##"<svg><path id="hdiag-0"/><g fill="url(#elsewhere)"/><a href="#top"/></svg>"##
+        );
+    }
+
+    #[test]
+    fn normalize_ids_updates_style_selectors() {
+        let svg = r##"<svg id="hdiag"><style>#hdiag{fill:#333;}#hdiag .node{stroke:#9370DB;}</style><path fill="#333"/></svg>"##;
+        assert_eq!(
+            normalize_ids(svg),
+            r##"<svg id="hdiag-0"><style>#hdiag-0{fill:#333;}#hdiag-0 .node{stroke:#9370DB;}</style><path fill="#333"/></svg>"##
+        );
+    }
+
+    #[test]
+    fn prefix_ids_updates_labels() -> Result<()> {
+        let svg = add_accessibility("<svg><g/></svg>", "Title", Some("Text"))?;
+        assert_eq!(
+            prefix_ids(&svg, "p"),
+            "<svg role=\"img\" aria-labelledby=\"p-hdiag-title p-hdiag-desc\">\
+             <title id=\"p-hdiag-title\">Title</title><desc id=\"p-hdiag-desc\">Text</desc><g/></svg>"
+        );
+        Ok(())
+    }
+}
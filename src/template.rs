@@ -0,0 +1,180 @@
+//! Fills the `{{name}}` placeholders in the text of diagrams with values
+//! from `--vars` and `--set`, so one diagram can be rendered for several
+//! variants
+
+use std::{borrow::Cow, collections::BTreeMap, collections::BTreeSet, fs, path::Path};
+
+use color_eyre::{
+    eyre::{bail, WrapErr},
+    Result,
+};
+use hdiag::scene::{Element, ElementData, Scene, TextAlign};
+use tracing::{debug, warn};
+
+use crate::{drawio::GraphModel, svg::escape_xml};
+
+/// Values of the placeholders, by name
+pub type Vars = BTreeMap<String, String>;
+
+/// Adds the values of a TOML table, naming the ones in nested tables with
+/// their path like `product.name`
+fn add_table(vars: &mut Vars, prefix: &str, table: toml::Table) -> Result<()> {
+    for (key, value) in table {
+        let name = if prefix.is_empty() {
+            key
+        } else {
+            format!("{prefix}.{key}")
+        };
+        let value = match value {
+            toml::Value::String(s) => s,
+            toml::Value::Integer(n) => n.to_string(),
+            toml::Value::Float(n) => n.to_string(),
+            toml::Value::Boolean(b) => b.to_string(),
+            toml::Value::Datetime(d) => d.to_string(),
+            toml::Value::Table(table) => {
+                add_table(vars, &name, table)?;
+                continue;
+            }
+            toml::Value::Array(_) => bail!("`{name}` is an array, values must be text"),
+        };
+        vars.insert(name, value);
+    }
+    Ok(())
+}
+
+/// Reads the values from the TOML file, then the `key=value` pairs, which
+/// win over the file
+pub fn load(file: Option<&Path>, set: &[(String, String)]) -> Result<Vars> {
+    let mut vars = Vars::new();
+    if let Some(file) = file {
+        let contents = fs::read_to_string(file)
+            .wrap_err_with(|| format!("Failed to read file {}", file.display()))?;
+        let table = contents
+            .parse::<toml::Table>()
+            .wrap_err_with(|| format!("{} is not valid TOML", file.display()))?;
+        add_table(&mut vars, "", table)
+            .wrap_err_with(|| format!("Failed reading values from {}", file.display()))?;
+    }
+    vars.extend(set.iter().cloned());
+    Ok(vars)
+}
+
+/// Splits a `key=value` pair from `--set`
+pub fn parse_assignment(assignment: &str) -> Result<(String, String), String> {
+    let (key, value) = assignment
+        .split_once('=')
+        .ok_or_else(|| format!("`{assignment}` is not like `key=value`"))?;
+    let key = key.trim();
+    if key.is_empty() {
+        return Err(format!("`{assignment}` has no key before the `=`"));
+    }
+    Ok((key.to_owned(), value.to_owned()))
+}
+
+/// Replaces the placeholders in the text, keeping the ones without a value
+/// and adding their names to `missing`
+fn fill<'a>(text: &'a str, vars: &Vars, missing: &mut BTreeSet<String>) -> Cow<'a, str> {
+    if !text.contains("{{") {
+        return Cow::Borrowed(text);
+    }
+    let mut filled = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find("{{") {
+        let Some(end) = rest[start..].find("}}").map(|e| start + e) else {
+            break;
+        };
+        let name = rest[start + 2..end].trim();
+        filled.push_str(&rest[..start]);
+        if let Some(value) = vars.get(name) {
+            filled.push_str(value);
+        } else {
+            missing.insert(name.to_owned());
+            filled.push_str(&rest[start..end + 2]);
+        }
+        rest = &rest[end + 2..];
+    }
+    filled.push_str(rest);
+    Cow::Owned(filled)
+}
+
+fn report(missing: &BTreeSet<String>) {
+    if !missing.is_empty() {
+        let names = missing.iter().map(String::as_str).collect::<Vec<_>>();
+        warn!(
+            names = names.join(", "),
+            "Placeholders have no value, pass them with `--vars` or `--set`"
+        );
+    }
+}
+
+fn longest_line(text: &str) -> usize {
+    text.lines().map(|l| l.chars().count()).max().unwrap_or(0)
+}
+
+/// Replaces the text of a text element. Its width is scaled with the length
+/// of its longest line, keeping it aligned where it was, since the text is
+/// not measured again
+pub fn replace_text(element: &mut Element, text: String, original_text: String) {
+    let ElementData::Text(data) = &mut element.data else {
+        return;
+    };
+    let (before, after) = (longest_line(&data.text), longest_line(&text));
+    data.text = text;
+    data.original_text = original_text;
+    if before == 0 {
+        return;
+    }
+    let base = &mut element.base;
+    let width = base.width * count(after) / count(before);
+    base.x += match data.text_align {
+        TextAlign::Left => 0.0,
+        TextAlign::Center => (base.width - width) / 2.0,
+        TextAlign::Right => base.width - width,
+    };
+    base.width = width;
+}
+
+/// Fills the placeholders in the text elements of the scene
+pub fn fill_scene(scene: &mut Scene, vars: &Vars) {
+    let mut missing = BTreeSet::new();
+    for element in &mut scene.elements {
+        let ElementData::Text(text) = &element.data else {
+            continue;
+        };
+        let filled = fill(&text.text, vars, &mut missing);
+        if filled == text.text {
+            continue;
+        }
+        debug!(element = element.id, "Filled placeholders");
+        let filled = filled.into_owned();
+        let original_text = fill(&text.original_text, vars, &mut missing).into_owned();
+        replace_text(element, filled, original_text);
+    }
+    report(&missing);
+}
+
+/// Fills the placeholders in the labels of the cells, escaping the values
+/// that go in HTML labels
+pub fn fill_model(model: &mut GraphModel, vars: &Vars) {
+    let escaped = vars
+        .iter()
+        .map(|(name, value)| (name.clone(), escape_xml(value)))
+        .collect();
+    let mut missing = BTreeSet::new();
+    for cell in &mut model.cells {
+        let vars = if cell.style.flag("html") {
+            &escaped
+        } else {
+            vars
+        };
+        if let Cow::Owned(value) = fill(&cell.value, vars, &mut missing) {
+            cell.value = value;
+        }
+    }
+    report(&missing);
+}
+
+#[allow(clippy::cast_precision_loss)]
+const fn count(n: usize) -> f64 {
+    n as f64
+}
@@ -0,0 +1,199 @@
+//! Reads the text out of diagrams, for search indexes and translation
+//! catalogues
+
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, Write as _},
+    path::{Path, PathBuf},
+};
+
+use color_eyre::{
+    eyre::{bail, WrapErr},
+    Result,
+};
+use hdiag::scene::{ElementData, Scene};
+use serde::Serialize;
+use tracing::info;
+
+use crate::{
+    backend::DiagramBackend as _,
+    cli::{self, TextFormat},
+    convert, detect,
+    drawio::{self, Cell, CellKind},
+    dsl, embedded,
+    geometry::Rect,
+    mxgraph::{self, Layout},
+};
+
+/// A text in a diagram, and the box it is drawn in. Labels along edges
+/// have an empty box at the point they are centered on
+#[derive(Serialize)]
+struct Item {
+    file: PathBuf,
+    /// Page of a draw.io file
+    #[serde(skip_serializing_if = "Option::is_none")]
+    page: Option<String>,
+    /// Id of the text element, or of the cell the label is in
+    id: String,
+    text: String,
+    x: f64,
+    y: f64,
+    width: f64,
+    height: f64,
+}
+
+impl Item {
+    fn new(file: &Path, page: Option<&str>, id: &str, text: String, rect: Rect) -> Self {
+        Self {
+            file: file.to_path_buf(),
+            page: page.map(ToOwned::to_owned),
+            id: id.to_owned(),
+            text,
+            x: rect.x,
+            y: rect.y,
+            width: rect.width,
+            height: rect.height,
+        }
+    }
+}
+
+/// Text elements of the scene, in the order they are drawn
+fn scene_items(file: &Path, scene: &Scene) -> Vec<Item> {
+    scene
+        .live_elements()
+        .filter_map(|element| {
+            let ElementData::Text(text) = &element.data else {
+                return None;
+            };
+            let base = &element.base;
+            let rect = Rect {
+                x: base.x,
+                y: base.y,
+                width: base.width,
+                height: base.height,
+            };
+            Some(Item::new(file, None, &element.id, text.text.clone(), rect))
+        })
+        .collect()
+}
+
+/// Where the label of the cell is: the box of a vertex, or the point along
+/// an edge for the labels of edges
+fn label_box(layout: &Layout, cells: &HashMap<&str, &Cell>, cell: &Cell) -> Rect {
+    if let Some(rect) = layout.rect(&cell.id) {
+        return rect;
+    }
+    // Labels in cells of their own are placed along their edge
+    let along = if cell.kind == CellKind::Edge {
+        Some((cell, 0.5))
+    } else {
+        let parent = cell.parent.as_deref().and_then(|p| cells.get(p).copied());
+        let x = cell.geometry.as_ref().map_or(0.0, |g| g.x);
+        parent
+            .filter(|p| p.kind == CellKind::Edge)
+            .map(|edge| (edge, (x + 1.0) / 2.0))
+    };
+    let Some((edge, position)) = along else {
+        return Rect::at(layout.origin(cell));
+    };
+    layout
+        .route(edge)
+        .filter(|route| !route.is_empty())
+        .map_or_else(
+            || Rect::at(layout.origin(cell)),
+            |route| Rect::at(mxgraph::point_along(&route, position)),
+        )
+}
+
+/// Labels of the visible cells of every page, in the order they are in the
+/// file
+fn drawio_items(file: &Path, contents: &[u8]) -> Result<Vec<Item>> {
+    let contents = if embedded::DrawioImage.detect(contents).is_ok() {
+        embedded::drawio_file(contents)?
+    } else {
+        contents.to_vec()
+    };
+    let mut items = vec![];
+    for page in drawio::pages(&contents)? {
+        let model = &page.model;
+        let layout = Layout::new(model);
+        let cells = model
+            .cells
+            .iter()
+            .map(|c| (c.id.as_str(), c))
+            .collect::<HashMap<_, _>>();
+        for cell in model.cells.iter().filter(|c| c.visible) {
+            let label = cell.label();
+            if label.trim().is_empty() {
+                continue;
+            }
+            let rect = label_box(&layout, &cells, cell);
+            items.push(Item::new(file, Some(&page.name), &cell.id, label, rect));
+        }
+    }
+    Ok(items)
+}
+
+fn read_items(file: &Path) -> Result<Vec<Item>> {
+    let contents =
+        fs::read(file).wrap_err_with(|| format!("Failed to read file {}", file.display()))?;
+    let file_type = detect::file_type(file)?;
+    match file_type.0.name() {
+        "excalidraw" | "image" | "obsidian" => {
+            Ok(scene_items(file, &convert::read_scene(&contents)?))
+        }
+        "hdiag" => {
+            let source = std::str::from_utf8(&contents).wrap_err("Diagram is not valid UTF-8")?;
+            Ok(scene_items(file, &dsl::compile(source)?))
+        }
+        "drawio" | "drawio-image" => drawio_items(file, &contents),
+        name => {
+            bail!("Can't read the text of {name} diagrams, only excalidraw, draw.io and hdiag ones")
+        }
+    }
+}
+
+/// Every text on a line, with its line breaks turned into spaces
+fn plain(items: &[Item], with_file: bool) -> String {
+    let mut output = String::new();
+    for item in items {
+        if with_file {
+            output.push_str(&item.file.to_string_lossy());
+            output.push_str(": ");
+        }
+        output.push_str(&item.text.split_whitespace().collect::<Vec<_>>().join(" "));
+        output.push('\n');
+    }
+    output
+}
+
+pub fn run(opts: &cli::TextOpts) -> Result<()> {
+    let mut items = vec![];
+    for file in &opts.files {
+        let file_items = read_items(file)
+            .wrap_err_with(|| format!("Failed reading the text of {}", file.display()))?;
+        items.extend(file_items);
+    }
+
+    let output = match opts.format {
+        TextFormat::Plain => plain(&items, opts.files.len() > 1),
+        TextFormat::Json => {
+            let mut json =
+                serde_json::to_string_pretty(&items).wrap_err("Failed serializing text")?;
+            json.push('\n');
+            json
+        }
+    };
+    match &opts.output_file {
+        Some(path) => {
+            fs::write(path, output)
+                .wrap_err_with(|| format!("Failed to write file {}", path.display()))?;
+            info!(path = %path.display(), count = items.len(), "Saved text");
+        }
+        None => io::stdout()
+            .write_all(output.as_bytes())
+            .wrap_err("Failed writing text to stdout")?,
+    }
+    Ok(())
+}